@@ -4,11 +4,20 @@ use esrc::{
     aggregate::Root,
     event::{PublishExt, ReplayOneExt},
 };
+#[cfg(feature = "webhook")]
+use esrc_ext::dead_letter::alert::{DeadLetterWatcher, WebhookNotifier};
 use esrc_ext::{
     admin::{
+        auth::{AdminAuth, StaticTokenAuthenticator},
+        auto_retry::AutoRetryPolicy,
         http::{AdminAppState, HasAdminAppState},
+        openapi::{self, AdminOpenApi},
+        projector_registry::ProjectorRegistry,
+        rebuild_view::{StreamViewRebuilder, ViewRegistry},
+        retention::{DeadLetterRetention, RetentionPolicy},
         AdminHandler,
     },
+    dead_letter::failure::DeadLetterFailureLog,
     feature::Feature,
 };
 use nats_dead_letter::postgres::SqlxDeadLetterStore;
@@ -68,7 +77,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // * Dependencies
     // Create CommandBus and attach handlers
     let mut admin_command_registry = registry::CommandHandlerRegistry::new();
-    let feature = Feature::new(&event_store);
+    // Errors and panics of the projects are recorded, and shown by the admin
+    // API with the dead letters they caused
+    let failure_log = DeadLetterFailureLog::new(db_pool.clone());
+    failure_log.setup().await?;
+    let feature = Feature::new(&event_store).with_failure_log(failure_log);
 
     // * Features
     // User feature setup
@@ -94,19 +107,83 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         });
     }
 
+    // Post an alert to ALERT_WEBHOOK_URL when a consumer has 10 dead letters
+    // or a new error dead letters a message
+    #[cfg(feature = "webhook")]
+    if let Ok(url) = std::env::var("ALERT_WEBHOOK_URL") {
+        let watcher = DeadLetterWatcher::new(
+            SqlxDeadLetterStore::new(db_pool.clone()),
+            WebhookNotifier::new(url),
+        )
+        .with_threshold(10);
+        feature.start_dead_letter_watcher(watcher, "dead_letter_watcher");
+    }
+
+    // Dead letters are kept for 30 days, and at most 10 000 per stream
+    let retention = DeadLetterRetention::new(
+        SqlxDeadLetterStore::new(db_pool.clone()),
+        RetentionPolicy {
+            max_per_stream: Some(10_000),
+            ..RetentionPolicy::default()
+        },
+    );
+    // With `--features archive` they are archived to ./dead-letter-archive first
+    #[cfg(feature = "archive")]
+    let retention = retention.with_archive("dead-letter-archive");
+    feature.start_dead_letter_retention(retention, "dead_letter_retention");
+
     // Set up the AdminHandler for managing admin commands
     let replay_store = SqlxDeadLetterStore::new(db_pool.clone());
 
-    let admin_handler = AdminHandler::new(replay_store, user_project, context);
-    admin_command_registry.register(admin_handler.clone());
-    admin_handler.setup_router(&mut router, "/api/v1");
+    // Each dead letter is replayed by the projector of the consumer that failed it
+    let mut projectors = ProjectorRegistry::new();
+    projectors.register("users", "user_creation", user_project.clone());
+
+    // The users table can be rebuilt from the users stream
+    let mut views = ViewRegistry::new();
+    views.register(
+        "users",
+        StreamViewRebuilder::new(context.clone(), "users", "users", user_project.clone())
+            .with_reset(user_project),
+    );
+
+    let admin_handler =
+        AdminHandler::new(replay_store, projectors, context, db_pool.clone()).with_views(views);
+    admin_handler.setup().await?;
+    admin_handler.resume_replay_jobs().await?;
+    // Dead letters are retried an hour after they failed, up to 3 times,
+    // before being left for an operator
+    feature.start_auto_retry(
+        admin_handler.auto_retry(AutoRetryPolicy::default()),
+        "dead_letter_auto_retry",
+    );
+    // Every admin command changing the dead letters is recorded in the audit log
+    admin_command_registry.register(admin_handler.clone().audited());
+    // Admin routes require `Authorization: Bearer <ADMIN_TOKEN>`
+    let admin_token = std::env::var("ADMIN_TOKEN").expect("ADMIN_TOKEN must be set");
+    let admin_auth = AdminAuth::new(StaticTokenAuthenticator::new(admin_token));
+    admin_handler.setup_router_with_auth(&mut router, "/api/v1", admin_auth.clone());
+    // A handler created with `AdminHandler::with_name` is documented with
+    // `.with_authenticated_handler(Some(<name>))`
+    openapi::setup_router(
+        &mut router,
+        &AdminOpenApi::new("/api/v1").with_authenticated_handler(None),
+    );
+    // Browser dashboard, built with `--features dashboard`
+    #[cfg(feature = "dashboard")]
+    esrc_ext::admin::dashboard::setup_router_with_auth(
+        &mut router,
+        "/api/v1",
+        feature.monitor(),
+        admin_auth,
+    );
 
     let admin_command_bus = discern::command::CommandBus::new(admin_command_registry);
 
     let app_state = AppState {
-        admin_app_state: AdminAppState {
-            command_bus: admin_command_bus,
-        },
+        // Handlers created with `AdminHandler::with_name` are mounted under
+        // `/admin/<name>` and need `.with_named_command_bus(<name>, <bus>)`
+        admin_app_state: AdminAppState::new(admin_command_bus),
     };
 
     // * Start Application
@@ -115,11 +192,50 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing::info!("Replay API server started on http://0.0.0.0:3001");
 
     // Available endpoints:
-    // PATCH /admin/dead-letters/replay/:event_id - Replay a specific event by its aggregate ID
-    // POST /admin/dead-letters/replay-all - Replay all dead letter events
+    // PATCH /admin/dead-letters/replay/:event_id - Start a job replaying a specific aggregate
+    // POST /admin/dead-letters/replay-all - Start a job replaying all dead letter events
+    // Both replay endpoints accept `policy: halt-aggregate-on-failure` to stop an
+    // aggregate's replay at its first failed event, as a query parameter of the
+    // single replay and in the JSON body of replay-all, which also accepts
+    // `concurrency: <n>` to replay up to n aggregates in parallel. With
+    // `strategy: republish` events are published to `redelivery-<consumer>.<event>.<id>`
+    // for the original durable consumer instead of being projected in process, see
+    // `AdminHandler::with_redelivery_prefix`
+    // POST /admin/dead-letters/:id/edit-and-replay - Replay a corrected payload or headers of a
+    // dead letter, with a reason, the original is kept in the `dead_letter_edits` table
+    // GET /admin/dead-letters - List the dead letters, filtered by stream, consumer, aggregate_id or subject
+    // GET /admin/dead-letters/stats - Dead letter counts by stream, consumer, event name, error and hour
+    // GET /admin/dead-letters/:id - Inspect a dead letter and its payload
+    // GET /admin/dead-letters/export - Export the dead letters, filtered like the list, as NDJSON
+    // POST /admin/dead-letters/import - Import the dead letters of an NDJSON export
+    // DELETE /admin/dead-letters/:id - Discard a dead letter, with a reason
+    // POST /admin/dead-letters/purge - Discard the dead letters matching a filter, with a reason
+    // GET /admin/replay-jobs/:id - Progress and summary of a replay job
+    // POST /admin/replay-jobs/:id/cancel - Cancel a replay job
+    // GET /admin/audit - Audit log of the admin actions
+    // POST /admin/views/:view/rebuild - Rebuild a view from its stream, a JSON body
+    // `{"aggregate_id": "<id>"}` only rebuilds the rows of one aggregate
+    // GET /admin/openapi.json - OpenAPI document of the admin API
+    // GET /admin/ui - Dashboard, with the `dashboard` feature
+    // GET /admin/automations - Status of the automations, with the `dashboard` feature
     tracing::info!("Available endpoints:");
     tracing::info!("  PATCH http://localhost:3001/api/v1/admin/dead-letters/replay/<aggregate-id>");
     tracing::info!("  POST  http://localhost:3001/api/v1/admin/dead-letters/replay-all");
+    tracing::info!("  POST  http://localhost:3001/api/v1/admin/dead-letters/<id>/edit-and-replay");
+    tracing::info!("  GET   http://localhost:3001/api/v1/admin/dead-letters");
+    tracing::info!("  GET   http://localhost:3001/api/v1/admin/dead-letters/stats");
+    tracing::info!("  GET   http://localhost:3001/api/v1/admin/dead-letters/<id>");
+    tracing::info!("  GET   http://localhost:3001/api/v1/admin/dead-letters/export");
+    tracing::info!("  POST  http://localhost:3001/api/v1/admin/dead-letters/import");
+    tracing::info!("  DELETE http://localhost:3001/api/v1/admin/dead-letters/<id>");
+    tracing::info!("  POST  http://localhost:3001/api/v1/admin/dead-letters/purge");
+    tracing::info!("  GET   http://localhost:3001/api/v1/admin/replay-jobs/<job-id>");
+    tracing::info!("  POST  http://localhost:3001/api/v1/admin/replay-jobs/<job-id>/cancel");
+    tracing::info!("  GET   http://localhost:3001/api/v1/admin/audit");
+    tracing::info!("  POST  http://localhost:3001/api/v1/admin/views/users/rebuild");
+    tracing::info!("  GET   http://localhost:3001/api/v1/admin/openapi.json");
+    #[cfg(feature = "dashboard")]
+    tracing::info!("  GET   http://localhost:3001/api/v1/admin/ui");
 
     // Spawn the server in a background task
     let server_handle = tokio::spawn(async move {
@@ -2,7 +2,10 @@ use esrc::{
     project::{Context, Project},
     Envelope,
 };
+use esrc_ext::admin::rebuild_view::ResetView;
+use futures::future::BoxFuture;
 use sqlx::PgPool;
+use uuid::Uuid;
 
 use crate::domain::user::Events;
 
@@ -40,6 +43,23 @@ impl UserProject {
     }
 }
 
+// Rows removed before the users view is rebuilt from its stream
+impl ResetView for UserProject {
+    fn reset_view(
+        &self,
+        aggregate_id: Option<Uuid>,
+    ) -> BoxFuture<'_, Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+        Box::pin(async move {
+            let query = match aggregate_id {
+                Some(id) => sqlx::query("DELETE FROM users WHERE id = $1").bind(id),
+                None => sqlx::query("DELETE FROM users"),
+            };
+            query.execute(&self.db_pool).await?;
+            Ok(())
+        })
+    }
+}
+
 impl Project for UserProject {
     type EventGroup = Events;
     type Error = UserErrors;
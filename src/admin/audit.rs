@@ -0,0 +1,457 @@
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use discern::command::CommandHandler;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::admin::replay_dead_letter::ReplaySummary;
+use crate::admin::{AdminCommands, AdminCommandsError, AdminCommandsOutput};
+
+/// Actor recorded for dispatches made outside of an `with_actor` scope
+pub const SYSTEM_ACTOR: &str = "system";
+
+tokio::task_local! {
+    static ACTOR: String;
+    static ENTRY: Uuid;
+}
+
+/// Run `future` with `actor` recorded as the author of the admin commands it
+/// dispatches
+pub async fn with_actor<F>(actor: String, future: F) -> F::Output
+where
+    F: Future,
+{
+    ACTOR.scope(actor, future).await
+}
+
+fn current_actor() -> String {
+    ACTOR
+        .try_with(Clone::clone)
+        .unwrap_or_else(|_| SYSTEM_ACTOR.to_string())
+}
+
+/// Audit entry of the command being dispatched, stored with the replay jobs
+/// it starts so that they can record their final summary in it
+pub(crate) fn current_entry() -> Option<Uuid> {
+    ENTRY.try_with(Clone::clone).ok()
+}
+
+/// What the audit log keeps of a replay: the whole summary, with the outcome
+/// and error of every dead letter but none of their payloads
+pub(crate) fn replay_result(summary: &ReplaySummary) -> Value {
+    serde_json::to_value(summary).unwrap_or(Value::Null)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditOutcome {
+    /// The command is running, e.g. the replay job it started, or the
+    /// process stopped before it finished
+    Pending,
+    Success,
+    Failure,
+    /// A replay job that was cancelled, its result holds what it replayed
+    /// before it stopped
+    Cancelled,
+}
+
+impl AuditOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AuditOutcome::Pending => "pending",
+            AuditOutcome::Success => "success",
+            AuditOutcome::Failure => "failure",
+            AuditOutcome::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminAuditEntry {
+    pub id: Uuid,
+    pub actor: String,
+    pub command: String,
+    pub parameters: Value,
+    pub outcome: AuditOutcome,
+    /// Summary of the output, e.g. the `ReplaySummary` of a replay
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub command: Option<String>,
+    pub outcome: Option<AuditOutcome>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Postgres table holding an entry for every dispatch of an `AdminCommands`
+/// that changes the dead letters
+#[derive(Clone)]
+pub struct AdminAuditLog {
+    db: sqlx::PgPool,
+}
+
+impl AdminAuditLog {
+    pub fn new(db: sqlx::PgPool) -> Self {
+        Self { db }
+    }
+
+    pub async fn setup(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS admin_audit_log(
+                id uuid                         NOT NULL,
+                actor text                      NOT NULL,
+                command text                    NOT NULL,
+                parameters jsonb                NOT NULL,
+                outcome text                    NOT NULL,
+                result jsonb,
+                error text,
+                duration_ms bigint              NOT NULL,
+                created_at timestamptz          NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (id)
+            );",
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn record(&self, entry: &AdminAuditEntry) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO admin_audit_log
+                (id, actor, command, parameters, outcome, result, error, duration_ms, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(entry.id)
+        .bind(&entry.actor)
+        .bind(&entry.command)
+        .bind(&entry.parameters)
+        .bind(entry.outcome.as_str())
+        .bind(&entry.result)
+        .bind(&entry.error)
+        .bind(entry.duration_ms)
+        .bind(entry.created_at)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Store the outcome of a pending entry, entries that already have one
+    /// are left unchanged
+    pub async fn complete(
+        &self,
+        id: Uuid,
+        outcome: AuditOutcome,
+        result: Option<&Value>,
+        error: Option<&str>,
+        duration_ms: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE admin_audit_log SET outcome = $2, result = $3, error = $4, duration_ms = $5
+                WHERE id = $1 AND outcome = 'pending'",
+        )
+        .bind(id)
+        .bind(outcome.as_str())
+        .bind(result)
+        .bind(error)
+        .bind(duration_ms)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Most recent entries first
+    pub async fn list(&self, filter: &AuditFilter) -> Result<Vec<AdminAuditEntry>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT * FROM admin_audit_log
+                WHERE ($1::text IS NULL OR actor = $1)
+                AND ($2::text IS NULL OR command = $2)
+                AND ($3::text IS NULL OR outcome = $3)
+                AND ($4::timestamptz IS NULL OR created_at >= $4)
+                AND ($5::timestamptz IS NULL OR created_at < $5)
+                ORDER BY created_at DESC
+                LIMIT $6 OFFSET $7",
+        )
+        .bind(&filter.actor)
+        .bind(&filter.command)
+        .bind(filter.outcome.map(|outcome| outcome.as_str()))
+        .bind(filter.from)
+        .bind(filter.to)
+        .bind(filter.limit.unwrap_or(100))
+        .bind(filter.offset.unwrap_or(0))
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| AdminAuditEntry {
+                id: row.get("id"),
+                actor: row.get("actor"),
+                command: row.get("command"),
+                parameters: row.get("parameters"),
+                outcome: match row.get::<&str, _>("outcome") {
+                    "pending" => AuditOutcome::Pending,
+                    "success" => AuditOutcome::Success,
+                    "cancelled" => AuditOutcome::Cancelled,
+                    _ => AuditOutcome::Failure,
+                },
+                result: row.get("result"),
+                error: row.get("error"),
+                duration_ms: row.get("duration_ms"),
+                created_at: row.get("created_at"),
+            })
+            .collect())
+    }
+}
+
+/// `CommandHandler` decorator recording the dispatches of the `AdminCommands`
+/// that change the dead letters in the `AdminAuditLog`. The entry is written
+/// as pending before the command runs, so that an interrupted command still
+/// leaves a trace, and completed with its outcome. The entry of a started
+/// replay job stays pending until the job finishes.
+#[derive(Clone)]
+pub struct AuditedCommandHandler<H> {
+    inner: H,
+    audit_log: AdminAuditLog,
+}
+
+impl<H> AuditedCommandHandler<H> {
+    pub fn new(inner: H, audit_log: AdminAuditLog) -> Self {
+        Self { inner, audit_log }
+    }
+}
+
+#[discern::async_trait]
+impl<H> CommandHandler<AdminCommands> for AuditedCommandHandler<H>
+where
+    H: CommandHandler<AdminCommands> + Send + Sync + 'static,
+{
+    async fn handle(
+        &self,
+        command: AdminCommands,
+    ) -> Result<AdminCommandsOutput, AdminCommandsError> {
+        let Some((name, parameters)) = command.audit_parameters() else {
+            return self.inner.handle(command).await;
+        };
+        let starts_job = matches!(command, AdminCommands::StartReplayJob { .. });
+
+        let entry = AdminAuditEntry {
+            id: Uuid::now_v7(),
+            actor: current_actor(),
+            command: name,
+            parameters,
+            outcome: AuditOutcome::Pending,
+            result: None,
+            error: None,
+            duration_ms: 0,
+            created_at: Utc::now(),
+        };
+        if let Err(e) = self.audit_log.record(&entry).await {
+            tracing::error!(error = %e, command = %entry.command, "Failed to record admin audit entry");
+        }
+
+        let started = Instant::now();
+        let result = ENTRY.scope(entry.id, self.inner.handle(command)).await;
+
+        let (outcome, output, error) = match &result {
+            // Completed by the job with its summary, unless it already did
+            Ok(output) if starts_job => (AuditOutcome::Pending, output.audit_result(), None),
+            Ok(output) => (AuditOutcome::Success, output.audit_result(), None),
+            Err(e) => (AuditOutcome::Failure, None, Some(e.to_string())),
+        };
+        if let Err(e) = self
+            .audit_log
+            .complete(
+                entry.id,
+                outcome,
+                output.as_ref(),
+                error.as_deref(),
+                started.elapsed().as_millis() as i64,
+            )
+            .await
+        {
+            tracing::error!(error = %e, command = %entry.command, "Failed to record admin audit entry");
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::admin::edit_dead_letter::DeadLetterEdit;
+    use crate::admin::filter::DeadLetterFilter;
+    use crate::admin::replay_dead_letter::{ReplayEventResult, ReplayOptions, ReplayOutcome};
+    use crate::admin::transfer_dead_letter::DeadLetterRecord;
+
+    fn record(id: Uuid) -> DeadLetterRecord {
+        DeadLetterRecord {
+            id: Some(id),
+            aggregate_id: Some(Uuid::now_v7()),
+            prefix: Some("test".to_string()),
+            stream: "users".to_string(),
+            consumer: "user-projector".to_string(),
+            subject: format!("test.User.{}", id),
+            stream_sequence: 1,
+            delivery_count: 1,
+            timestamp: Utc::now(),
+            headers: None,
+            payload: "eyJzZWNyZXQiOnRydWV9".to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_without_payloads_are_not_audited() {
+        for command in [
+            AdminCommands::DeadLetterStats {
+                filter: DeadLetterFilter::default(),
+            },
+            AdminCommands::GetReplayJob { id: Uuid::now_v7() },
+            AdminCommands::ListAuditEntries {
+                filter: AuditFilter::default(),
+            },
+        ] {
+            assert!(command.audit_parameters().is_none(), "{:?}", command);
+        }
+    }
+
+    #[test]
+    fn reads_of_dead_letter_payloads_are_audited() {
+        let filter = DeadLetterFilter::default();
+        let names: Vec<_> = [
+            AdminCommands::ListDeadLetters {
+                filter: filter.clone(),
+            },
+            AdminCommands::GetDeadLetter { id: Uuid::now_v7() },
+            AdminCommands::ExportDeadLetters { filter },
+        ]
+        .iter()
+        .map(|command| command.audit_parameters().unwrap().0)
+        .collect();
+
+        assert_eq!(
+            names,
+            [
+                "list-dead-letters",
+                "get-dead-letter",
+                "export-dead-letters"
+            ]
+        );
+    }
+
+    #[test]
+    fn export_is_audited_without_the_records() {
+        let ids = [Uuid::now_v7(), Uuid::now_v7()];
+        let result = AdminCommandsOutput::Export(ids.iter().copied().map(record).collect())
+            .audit_result()
+            .unwrap();
+
+        assert_eq!(result, json!({ "total_records": 2, "ids": ids }));
+    }
+
+    #[test]
+    fn mutating_commands_are_audited_with_their_parameters() {
+        let id = Uuid::now_v7();
+        let (name, parameters) = AdminCommands::PurgeOneDeadLetter {
+            id,
+            reason: "poison".to_string(),
+        }
+        .audit_parameters()
+        .unwrap();
+
+        assert_eq!(name, "purge-one-dead-letter");
+        assert_eq!(parameters, json!({ "id": id, "reason": "poison" }));
+    }
+
+    #[test]
+    fn import_is_audited_without_the_records() {
+        let ids = [Uuid::now_v7(), Uuid::now_v7()];
+        let (name, parameters) = AdminCommands::ImportDeadLetters {
+            records: ids.iter().copied().map(record).collect(),
+        }
+        .audit_parameters()
+        .unwrap();
+
+        assert_eq!(name, "import-dead-letters");
+        assert_eq!(parameters, json!({ "total_records": 2, "ids": ids }));
+    }
+
+    #[test]
+    fn edit_is_audited_without_the_payload() {
+        let id = Uuid::now_v7();
+        let (_, parameters) = AdminCommands::EditAndReplayDeadLetter {
+            id,
+            edit: DeadLetterEdit {
+                payload: Some(json!({ "secret": true })),
+                headers: Some(HashMap::from([("Trace-Id".to_string(), "abc".to_string())])),
+                reason: "fix typo".to_string(),
+            },
+            options: ReplayOptions::default(),
+        }
+        .audit_parameters()
+        .unwrap();
+
+        assert_eq!(parameters["id"], json!(id));
+        assert_eq!(parameters["payload_edited"], json!(true));
+        assert_eq!(parameters["edited_headers"], json!(["Trace-Id"]));
+        assert!(!parameters.to_string().contains("secret"));
+    }
+
+    #[test]
+    fn replay_result_keeps_the_whole_summary() {
+        let dead_letter_id = Uuid::now_v7();
+        let aggregate_id = Uuid::now_v7();
+        let now = Utc::now();
+        let summary = ReplaySummary {
+            total_events: 1,
+            successful_replays: 0,
+            failed_replays: 1,
+            skipped_replays: 0,
+            processed_aggregates: vec![aggregate_id],
+            results: vec![ReplayEventResult {
+                dead_letter_id: Some(dead_letter_id),
+                aggregate_id,
+                stream_sequence: 7,
+                outcome: ReplayOutcome::Failed,
+                error_kind: None,
+                error_message: Some("projection failed".to_string()),
+                removed: false,
+            }],
+            cancelled: false,
+            started_at: now,
+            finished_at: now,
+            duration_ms: 0,
+        };
+
+        let result = AdminCommandsOutput::Replay(summary.clone())
+            .audit_result()
+            .unwrap();
+
+        assert_eq!(result, serde_json::to_value(&summary).unwrap());
+        assert_eq!(
+            result["results"][0]["dead_letter_id"],
+            json!(dead_letter_id)
+        );
+        assert_eq!(
+            result["results"][0]["error_message"],
+            json!("projection failed")
+        );
+    }
+}
@@ -0,0 +1,586 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+};
+use serde::Serialize;
+use subtle::ConstantTimeEq;
+
+use crate::utils::problem_details::ProblemDetails;
+
+/// Role granting every admin permission
+pub const ADMIN_ROLE: &str = "admin";
+
+/// Permission required by an admin route, a principal holds it when it has
+/// the role of the same name or the `admin` role
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AdminPermission {
+    Inspect,
+    Replay,
+    Purge,
+    Import,
+    Audit,
+    Rebuild,
+}
+
+impl AdminPermission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdminPermission::Inspect => "inspect",
+            AdminPermission::Replay => "replay",
+            AdminPermission::Purge => "purge",
+            AdminPermission::Import => "import",
+            AdminPermission::Audit => "audit",
+            AdminPermission::Rebuild => "rebuild",
+        }
+    }
+}
+
+/// Identity of the caller of an admin route, available to the handlers as a
+/// request extension once authenticated
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminPrincipal {
+    pub subject: String,
+    pub roles: HashSet<String>,
+}
+
+impl AdminPrincipal {
+    pub fn can(&self, permission: AdminPermission) -> bool {
+        self.roles.contains(ADMIN_ROLE) || self.roles.contains(permission.as_str())
+    }
+}
+
+/// Authenticates the caller of an admin route from the request headers
+pub trait AdminAuthenticator: Send + Sync + 'static {
+    fn authenticate(&self, headers: &HeaderMap) -> Result<AdminPrincipal, ProblemDetails>;
+}
+
+/// Shared authenticator given to `AdminHandler::setup_router_with_auth`
+#[derive(Clone)]
+pub struct AdminAuth(Arc<dyn AdminAuthenticator>);
+
+impl AdminAuth {
+    pub fn new<A>(authenticator: A) -> Self
+    where
+        A: AdminAuthenticator,
+    {
+        Self(Arc::new(authenticator))
+    }
+
+    /// Require `permission` on the route, or leave it open when no
+    /// authenticator is configured
+    pub(crate) fn guard<S>(
+        auth: Option<&AdminAuth>,
+        permission: AdminPermission,
+        route: MethodRouter<S>,
+    ) -> MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        match auth {
+            Some(auth) => route.route_layer(middleware::from_fn_with_state(
+                AdminGuard {
+                    auth: auth.clone(),
+                    permission,
+                },
+                require_permission,
+            )),
+            None => route,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AdminGuard {
+    auth: AdminAuth,
+    permission: AdminPermission,
+}
+
+async fn require_permission(
+    State(guard): State<AdminGuard>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let principal = match guard.auth.0.authenticate(request.headers()) {
+        Ok(principal) => principal,
+        Err(problem) => return problem.into_response(),
+    };
+
+    if !principal.can(guard.permission) {
+        tracing::warn!(
+            subject = %principal.subject,
+            permission = guard.permission.as_str(),
+            "Admin route forbidden"
+        );
+        return ProblemDetails::forbidden(format!(
+            "The {} permission is required",
+            guard.permission.as_str()
+        ))
+        .into_response();
+    }
+
+    request.extensions_mut().insert(principal);
+    next.run(request).await
+}
+
+/// Extract the token of an `Authorization: Bearer <token>` header
+pub fn bearer_token(headers: &HeaderMap) -> Result<&str, ProblemDetails> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .ok_or_else(|| ProblemDetails::unauthorized("A bearer token is required".to_string()))
+}
+
+/// Accepts a single pre-shared bearer token
+pub struct StaticTokenAuthenticator {
+    token: String,
+    subject: String,
+    roles: HashSet<String>,
+}
+
+impl StaticTokenAuthenticator {
+    /// The token grants the `admin` role, i.e. every permission
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+            subject: "static-token".to_string(),
+            roles: HashSet::from([ADMIN_ROLE.to_string()]),
+        }
+    }
+
+    pub fn with_subject(mut self, subject: impl Into<String>) -> Self {
+        self.subject = subject.into();
+        self
+    }
+
+    pub fn with_roles<I, R>(mut self, roles: I) -> Self
+    where
+        I: IntoIterator<Item = R>,
+        R: Into<String>,
+    {
+        self.roles = roles.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+impl AdminAuthenticator for StaticTokenAuthenticator {
+    fn authenticate(&self, headers: &HeaderMap) -> Result<AdminPrincipal, ProblemDetails> {
+        let token = bearer_token(headers)?;
+
+        // Constant time, so the time taken does not leak the token
+        if !bool::from(token.as_bytes().ct_eq(self.token.as_bytes())) {
+            return Err(ProblemDetails::unauthorized(
+                "Invalid bearer token".to_string(),
+            ));
+        }
+
+        Ok(AdminPrincipal {
+            subject: self.subject.clone(),
+            roles: self.roles.clone(),
+        })
+    }
+}
+
+#[cfg(feature = "jwt")]
+pub use jwt::{JwtAuthenticator, JwtAuthenticatorError};
+
+#[cfg(feature = "jwt")]
+mod jwt {
+    use std::collections::HashSet;
+    use std::path::Path;
+
+    use axum::http::HeaderMap;
+    use jsonwebtoken::{
+        decode, decode_header,
+        jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm},
+        Algorithm, DecodingKey, Validation,
+    };
+    use serde_json::{Map, Value};
+
+    use super::{bearer_token, AdminAuthenticator, AdminPrincipal};
+    use crate::utils::problem_details::ProblemDetails;
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum JwtAuthenticatorError {
+        #[error("Failed to read JWKS file: {0}")]
+        Io(#[from] std::io::Error),
+        #[error("Invalid JWKS file: {0}")]
+        Jwks(#[from] serde_json::Error),
+    }
+
+    /// Validates JWT bearer tokens against the keys of a local JWKS file, the
+    /// roles are read from the `roles` claim by default
+    pub struct JwtAuthenticator {
+        jwks: JwkSet,
+        issuer: Option<String>,
+        audience: Option<String>,
+        roles_claim: String,
+    }
+
+    impl JwtAuthenticator {
+        pub fn new(jwks: JwkSet) -> Self {
+            Self {
+                jwks,
+                issuer: None,
+                audience: None,
+                roles_claim: "roles".to_string(),
+            }
+        }
+
+        pub fn from_jwks_file(path: impl AsRef<Path>) -> Result<Self, JwtAuthenticatorError> {
+            let content = std::fs::read_to_string(path)?;
+            Ok(Self::new(serde_json::from_str(&content)?))
+        }
+
+        pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+            self.issuer = Some(issuer.into());
+            self
+        }
+
+        pub fn with_audience(mut self, audience: impl Into<String>) -> Self {
+            self.audience = Some(audience.into());
+            self
+        }
+
+        pub fn with_roles_claim(mut self, claim: impl Into<String>) -> Self {
+            self.roles_claim = claim.into();
+            self
+        }
+
+        fn roles(&self, claims: &Map<String, Value>) -> HashSet<String> {
+            match claims.get(&self.roles_claim) {
+                Some(Value::Array(roles)) => roles
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect(),
+                // Space separated, like the OAuth `scope` claim
+                Some(Value::String(roles)) => {
+                    roles.split_whitespace().map(str::to_string).collect()
+                },
+                _ => HashSet::new(),
+            }
+        }
+
+        /// Key used to verify a token, a token without `kid` is only
+        /// accepted when the set holds a single key
+        fn jwk(&self, kid: Option<&str>) -> Result<&Jwk, ProblemDetails> {
+            match kid {
+                Some(kid) => self
+                    .jwks
+                    .find(kid)
+                    .ok_or_else(|| ProblemDetails::unauthorized("Unknown signing key".to_string())),
+                None => match self.jwks.keys.as_slice() {
+                    [jwk] => Ok(jwk),
+                    _ => Err(ProblemDetails::unauthorized(
+                        "The token must name its signing key with kid".to_string(),
+                    )),
+                },
+            }
+        }
+    }
+
+    /// Algorithms a key may verify, its `alg` when declared, otherwise every
+    /// algorithm of its key type. The token header is never trusted for this.
+    fn algorithms(jwk: &Jwk) -> Vec<Algorithm> {
+        if let Some(algorithm) = jwk.common.key_algorithm {
+            return match algorithm {
+                KeyAlgorithm::HS256 => vec![Algorithm::HS256],
+                KeyAlgorithm::HS384 => vec![Algorithm::HS384],
+                KeyAlgorithm::HS512 => vec![Algorithm::HS512],
+                KeyAlgorithm::ES256 => vec![Algorithm::ES256],
+                KeyAlgorithm::ES384 => vec![Algorithm::ES384],
+                KeyAlgorithm::RS256 => vec![Algorithm::RS256],
+                KeyAlgorithm::RS384 => vec![Algorithm::RS384],
+                KeyAlgorithm::RS512 => vec![Algorithm::RS512],
+                KeyAlgorithm::PS256 => vec![Algorithm::PS256],
+                KeyAlgorithm::PS384 => vec![Algorithm::PS384],
+                KeyAlgorithm::PS512 => vec![Algorithm::PS512],
+                KeyAlgorithm::EdDSA => vec![Algorithm::EdDSA],
+                // Encryption algorithms, no token is signed with them
+                _ => Vec::new(),
+            };
+        }
+
+        match &jwk.algorithm {
+            AlgorithmParameters::RSA(_) => vec![
+                Algorithm::RS256,
+                Algorithm::RS384,
+                Algorithm::RS512,
+                Algorithm::PS256,
+                Algorithm::PS384,
+                Algorithm::PS512,
+            ],
+            AlgorithmParameters::EllipticCurve(params) => match params.curve {
+                EllipticCurve::P256 => vec![Algorithm::ES256],
+                EllipticCurve::P384 => vec![Algorithm::ES384],
+                _ => Vec::new(),
+            },
+            AlgorithmParameters::OctetKey(_) => {
+                vec![Algorithm::HS256, Algorithm::HS384, Algorithm::HS512]
+            },
+            AlgorithmParameters::OctetKeyPair(_) => vec![Algorithm::EdDSA],
+        }
+    }
+
+    impl AdminAuthenticator for JwtAuthenticator {
+        fn authenticate(&self, headers: &HeaderMap) -> Result<AdminPrincipal, ProblemDetails> {
+            let token = bearer_token(headers)?;
+            let invalid = |e: jsonwebtoken::errors::Error| {
+                ProblemDetails::unauthorized(format!("Invalid token: {}", e))
+            };
+
+            let header = decode_header(token).map_err(invalid)?;
+            let jwk = self.jwk(header.kid.as_deref())?;
+            let algorithms = algorithms(jwk);
+            if !algorithms.contains(&header.alg) {
+                return Err(ProblemDetails::unauthorized(format!(
+                    "The {:?} algorithm does not match the signing key",
+                    header.alg
+                )));
+            }
+            let key = DecodingKey::from_jwk(jwk).map_err(invalid)?;
+
+            let mut validation = Validation::new(header.alg);
+            validation.algorithms = algorithms;
+            if let Some(issuer) = &self.issuer {
+                validation.set_issuer(&[issuer]);
+            }
+            match &self.audience {
+                Some(audience) => validation.set_audience(&[audience]),
+                None => validation.validate_aud = false,
+            }
+
+            let claims = decode::<Map<String, Value>>(token, &key, &validation)
+                .map_err(invalid)?
+                .claims;
+
+            Ok(AdminPrincipal {
+                subject: subject(&claims)?,
+                roles: self.roles(&claims),
+            })
+        }
+    }
+
+    /// The `sub` claim, required so every audited action names its actor
+    fn subject(claims: &Map<String, Value>) -> Result<String, ProblemDetails> {
+        claims
+            .get("sub")
+            .and_then(Value::as_str)
+            .filter(|subject| !subject.is_empty())
+            .map(str::to_string)
+            .ok_or_else(|| ProblemDetails::unauthorized("The token has no subject".to_string()))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use serde_json::json;
+
+        use super::*;
+
+        fn jwks(keys: Value) -> JwkSet {
+            serde_json::from_value(json!({ "keys": keys })).unwrap()
+        }
+
+        fn rsa_key(kid: &str, alg: Option<&str>) -> Value {
+            let mut key = json!({
+                "kty": "RSA",
+                "kid": kid,
+                "use": "sig",
+                "n": "sXchDaQebHnPiGvyDOAT4saGEUetSyo9MKLOoWFsueri23bOdgWp4Dy1WlUzewbgBHod5pcM9H95GQRV3JDXboIRROSBigeC5yjU1hGzHHyXss8UDprecbAYxknTcQkhslANGRUZmdTOQ5qTRsLAt6BTYuyvVRdhS8exSZEy_c4gs_7svlJJQ4H9_NxsiIoLwAEk7-Q3UXERGYw_75IDrGA84-lA_-Ct4eTlXHBIY2EaV7t7LjJaynVJCpkv4LKjTTAumiGUIuQhrNhZLuF_RJLqHpM2kgWFLU7-VTdL1VbC2tejvcI2BlMkEpk1BzBZI0KQB0GaDWFLN-aEAw3vRw",
+                "e": "AQAB",
+            });
+            if let Some(alg) = alg {
+                key["alg"] = json!(alg);
+            }
+            key
+        }
+
+        #[test]
+        fn declared_algorithm_is_the_only_one_allowed() {
+            let jwks = jwks(json!([rsa_key("a", Some("RS256"))]));
+
+            assert_eq!(algorithms(&jwks.keys[0]), vec![Algorithm::RS256]);
+        }
+
+        #[test]
+        fn key_type_limits_algorithms_without_alg() {
+            let jwks = jwks(json!([
+                rsa_key("a", None),
+                { "kty": "oct", "kid": "b", "k": "c2VjcmV0" },
+            ]));
+
+            let rsa = algorithms(&jwks.keys[0]);
+            assert!(rsa.contains(&Algorithm::RS256));
+            assert!(!rsa.contains(&Algorithm::HS256));
+            assert_eq!(
+                algorithms(&jwks.keys[1]),
+                vec![Algorithm::HS256, Algorithm::HS384, Algorithm::HS512]
+            );
+        }
+
+        #[test]
+        fn missing_kid_is_only_accepted_with_a_single_key() {
+            let single = JwtAuthenticator::new(jwks(json!([rsa_key("a", None)])));
+            assert!(single.jwk(None).is_ok());
+
+            let several =
+                JwtAuthenticator::new(jwks(json!([rsa_key("a", None), rsa_key("b", None),])));
+            assert!(several.jwk(None).is_err());
+            assert!(several.jwk(Some("b")).is_ok());
+            assert!(several.jwk(Some("c")).is_err());
+        }
+
+        #[test]
+        fn token_without_subject_is_rejected() {
+            let claims = |claims: Value| claims.as_object().unwrap().clone();
+
+            assert_eq!(subject(&claims(json!({ "sub": "ada" }))).unwrap(), "ada");
+            for claims in [
+                claims(json!({})),
+                claims(json!({ "sub": "" })),
+                claims(json!({ "sub": 42 })),
+            ] {
+                assert_eq!(subject(&claims).unwrap_err().status, 401);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::StatusCode, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn principal(roles: &[&str]) -> AdminPrincipal {
+        AdminPrincipal {
+            subject: "ada".to_string(),
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+        }
+    }
+
+    fn headers(authorization: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(authorization) = authorization {
+            headers.insert(header::AUTHORIZATION, authorization.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn permission_is_granted_by_its_role_or_the_admin_role() {
+        assert!(principal(&["purge"]).can(AdminPermission::Purge));
+        assert!(!principal(&["purge"]).can(AdminPermission::Replay));
+        assert!(principal(&[ADMIN_ROLE]).can(AdminPermission::Replay));
+        assert!(!principal(&[]).can(AdminPermission::Inspect));
+    }
+
+    #[test]
+    fn static_token_grants_its_subject_and_roles() {
+        let authenticator = StaticTokenAuthenticator::new("secret")
+            .with_subject("ops")
+            .with_roles(["inspect"]);
+
+        let principal = authenticator
+            .authenticate(&headers(Some("Bearer secret")))
+            .unwrap();
+
+        assert_eq!(principal.subject, "ops");
+        assert_eq!(principal.roles, HashSet::from(["inspect".to_string()]));
+    }
+
+    #[test]
+    fn static_token_rejects_a_missing_or_wrong_token() {
+        let authenticator = StaticTokenAuthenticator::new("secret");
+
+        for authorization in [
+            None,
+            Some("secret"),
+            Some("Bearer "),
+            Some("Bearer secreT"),
+            Some("Bearer secret2"),
+            Some("Bearer sec"),
+        ] {
+            let problem = authenticator
+                .authenticate(&headers(authorization))
+                .unwrap_err();
+            assert_eq!(problem.status, 401, "{:?}", authorization);
+        }
+    }
+
+    async fn guarded_status(authorization: Option<&str>) -> StatusCode {
+        let auth = AdminAuth::new(StaticTokenAuthenticator::new("secret").with_roles(["inspect"]));
+        let router = Router::new().route(
+            "/",
+            AdminAuth::guard(
+                Some(&auth),
+                AdminPermission::Purge,
+                get(|| async { "purged" }),
+            ),
+        );
+
+        let mut request = Request::builder().uri("/");
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn guard_rejects_unauthenticated_callers_with_401() {
+        assert_eq!(guarded_status(None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            guarded_status(Some("Bearer wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn guard_rejects_callers_without_the_permission_with_403() {
+        assert_eq!(
+            guarded_status(Some("Bearer secret")).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn guard_lets_callers_with_the_permission_through() {
+        let auth = AdminAuth::new(StaticTokenAuthenticator::new("secret"));
+        let router = Router::new().route(
+            "/",
+            AdminAuth::guard(
+                Some(&auth),
+                AdminPermission::Purge,
+                get(|| async { "purged" }),
+            ),
+        );
+
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header(header::AUTHORIZATION, "Bearer secret")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
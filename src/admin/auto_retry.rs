@@ -0,0 +1,480 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use nats_dead_letter::{DeadLetter, DeadLetterStore};
+use serde::Serialize;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::admin::dead_letter_stats::error_fingerprint;
+use crate::admin::filter::DeadLetterFilter;
+use crate::admin::projector_registry::DeadLetterProjector;
+use crate::admin::replay_dead_letter::{
+    replay_groups, ReplayDeadLetter, ReplayEventResult, ReplayGroup, ReplayOptions, ReplayOutcome,
+    ReplayPolicy,
+};
+use crate::dead_letter::failure::{DeadLetterFailureLog, FailureKey};
+
+/// Dead letters retried by an `AutoRetryScheduler`
+#[derive(Debug, Clone)]
+pub struct AutoRetryPolicy {
+    /// Time between two runs of the scheduler
+    pub interval: Duration,
+    /// Time a dead letter is left alone after it was stored or last retried
+    pub min_age: Duration,
+    /// Number of automatic retries after which a dead letter is left for an
+    /// operator
+    pub max_attempts: u32,
+    /// Error fingerprints, as shown by the stats endpoint, worth retrying.
+    /// `None` retries every error, a dead letter without a recorded failure
+    /// is only retried when there is no allow-list.
+    pub errors: Option<Vec<String>>,
+    /// Replay groups with at least one matching dead letter are retried,
+    /// with all of their dead letters so that their events stay in order,
+    /// see `ReplayGroup`
+    pub filter: DeadLetterFilter,
+    pub options: ReplayOptions,
+}
+
+impl Default for AutoRetryPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15 * 60),
+            min_age: Duration::from_secs(60 * 60),
+            max_attempts: 3,
+            errors: None,
+            filter: DeadLetterFilter::default(),
+            // The events of an aggregate stay in order when one of them still
+            // fails
+            options: ReplayOptions {
+                policy: ReplayPolicy::HaltAggregateOnFailure,
+                ..ReplayOptions::default()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RetryAttempt {
+    pub dead_letter_id: Uuid,
+    pub attempts: i32,
+    pub last_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+}
+
+/// Number of automatic retries of each dead letter
+#[derive(Clone)]
+pub struct RetryAttemptLog {
+    db: sqlx::PgPool,
+}
+
+impl RetryAttemptLog {
+    pub fn new(db: sqlx::PgPool) -> Self {
+        Self { db }
+    }
+
+    pub async fn setup(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS dead_letter_retry_attempts(
+                dead_letter_id uuid             NOT NULL,
+                attempts integer                NOT NULL DEFAULT 1,
+                last_attempt_at timestamptz     NOT NULL DEFAULT NOW(),
+                last_error text,
+                PRIMARY KEY (dead_letter_id)
+            );",
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find(&self, ids: &[Uuid]) -> Result<HashMap<Uuid, RetryAttempt>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT dead_letter_id, attempts, last_attempt_at, last_error
+                FROM dead_letter_retry_attempts WHERE dead_letter_id = ANY($1)",
+        )
+        .bind(ids)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| RetryAttempt {
+                dead_letter_id: row.get("dead_letter_id"),
+                attempts: row.get("attempts"),
+                last_attempt_at: row.get("last_attempt_at"),
+                last_error: row.get("last_error"),
+            })
+            .map(|attempt| (attempt.dead_letter_id, attempt))
+            .collect())
+    }
+
+    pub async fn record_failure(&self, id: Uuid, error: Option<&str>) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO dead_letter_retry_attempts (dead_letter_id, last_error)
+                VALUES ($1, $2)
+                ON CONFLICT (dead_letter_id) DO UPDATE SET
+                    attempts = dead_letter_retry_attempts.attempts + 1,
+                    last_attempt_at = NOW(),
+                    last_error = EXCLUDED.last_error",
+        )
+        .bind(id)
+        .bind(error)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Forget a dead letter once it was replayed and removed from the store
+    pub async fn remove(&self, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM dead_letter_retry_attempts WHERE dead_letter_id = $1")
+            .bind(id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Outcome of one run of an `AutoRetryScheduler`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AutoRetryReport {
+    pub retried_aggregates: Vec<Uuid>,
+    pub successful_replays: usize,
+    pub failed_replays: usize,
+    /// Dead letters that reached `max_attempts`, the dead letters replayed
+    /// with them are not retried anymore
+    pub exhausted: Vec<Uuid>,
+}
+
+/// Change a replay result makes to the `RetryAttemptLog`
+#[derive(Debug, PartialEq)]
+enum AttemptUpdate {
+    /// One more attempt, with its error
+    Failed(Option<String>),
+    /// The dead letter left the store, its attempts are forgotten
+    Removed,
+}
+
+impl AttemptUpdate {
+    fn of(result: &ReplayEventResult) -> Option<Self> {
+        match result.outcome {
+            ReplayOutcome::Failed => Some(Self::Failed(result.error_message.clone())),
+            ReplayOutcome::Replayed | ReplayOutcome::Republished if result.removed => {
+                Some(Self::Removed)
+            },
+            // Replayed but still in the store: counted as an attempt, so that
+            // it is not replayed again on every run
+            ReplayOutcome::Replayed | ReplayOutcome::Republished => {
+                Some(Self::Failed(result.error_message.clone().or_else(|| {
+                    Some("Replayed but not removed from the store".to_string())
+                })))
+            },
+            ReplayOutcome::Skipped => None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AutoRetryError {
+    #[error(transparent)]
+    DeadLetterStore(Box<dyn std::error::Error + Send + Sync>),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Replays the dead letters matching an `AutoRetryPolicy` on a schedule, for
+/// the failures caused by transient outages. See `AdminHandler::auto_retry`
+/// and `Feature::start_auto_retry`.
+pub struct AutoRetryScheduler<DLS, P>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+    P: DeadLetterProjector + Clone + 'static,
+{
+    dead_letter_store: DLS,
+    dead_letter_replay: ReplayDeadLetter<DLS, P>,
+    failure_log: DeadLetterFailureLog,
+    attempt_log: RetryAttemptLog,
+    policy: AutoRetryPolicy,
+}
+
+impl<DLS, P> AutoRetryScheduler<DLS, P>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+    P: DeadLetterProjector + Clone + 'static,
+{
+    pub fn new(
+        dead_letter_store: DLS,
+        dead_letter_replay: ReplayDeadLetter<DLS, P>,
+        failure_log: DeadLetterFailureLog,
+        attempt_log: RetryAttemptLog,
+        policy: AutoRetryPolicy,
+    ) -> Self {
+        Self {
+            dead_letter_store,
+            dead_letter_replay,
+            failure_log,
+            attempt_log,
+            policy,
+        }
+    }
+
+    /// Retry forever, a failed run is logged and started again on the next
+    /// interval
+    pub async fn run(self) {
+        loop {
+            match self.retry_once().await {
+                Ok(report) if !report.retried_aggregates.is_empty() => tracing::info!(
+                    aggregates = report.retried_aggregates.len(),
+                    successful = report.successful_replays,
+                    failed = report.failed_replays,
+                    exhausted = report.exhausted.len(),
+                    "Retried dead letters"
+                ),
+                Ok(_) => {},
+                Err(e) => tracing::warn!("Failed to retry dead letters: {}", e),
+            }
+            tokio::time::sleep(self.policy.interval).await;
+        }
+    }
+
+    /// Replay the groups whose dead letters are all eligible, in stream
+    /// order
+    pub async fn retry_once(&self) -> Result<AutoRetryReport, AutoRetryError> {
+        let events = self
+            .dead_letter_store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .map_err(|e| AutoRetryError::DeadLetterStore(e.into()))?;
+        let groups = matching_groups(events, &self.policy.filter);
+
+        let events = groups.values().flatten().collect::<Vec<_>>();
+        let ids = events.iter().filter_map(|e| e.id).collect::<Vec<_>>();
+        let attempts = self.attempt_log.find(&ids).await?;
+        let keys = events.iter().map(|e| FailureKey::of(e)).collect::<Vec<_>>();
+        let failures = self.failure_log.find(&keys).await?;
+
+        let mut report = AutoRetryReport::default();
+        let now = SystemTime::now();
+
+        for (group, events) in groups {
+            // Without an ID the attempts of a dead letter can not be counted
+            let Some(ids) = events.iter().map(|e| e.id).collect::<Option<Vec<_>>>() else {
+                continue;
+            };
+
+            let exhausted = ids
+                .iter()
+                .filter(|id| {
+                    attempts
+                        .get(id)
+                        .is_some_and(|a| a.attempts as u32 >= self.policy.max_attempts)
+                })
+                .copied()
+                .collect::<Vec<_>>();
+            if !exhausted.is_empty() {
+                report.exhausted.extend(exhausted);
+                continue;
+            }
+
+            // Age of the first dead letter of the group, counted from its
+            // last retry when there was one
+            let first = &events[0];
+            let last_touched = attempts
+                .get(&ids[0])
+                .map(|a| SystemTime::from(a.last_attempt_at))
+                .unwrap_or(SystemTime::UNIX_EPOCH)
+                .max(SystemTime::from(first.timestamp));
+            let age = now.duration_since(last_touched).unwrap_or_default();
+            if age < self.policy.min_age {
+                continue;
+            }
+
+            if let Some(errors) = &self.policy.errors {
+                let fingerprint = failures
+                    .get(&FailureKey::of(first))
+                    .map(|failure| error_fingerprint(&failure.error));
+                if !fingerprint.is_some_and(|fingerprint| errors.contains(&fingerprint)) {
+                    continue;
+                }
+            }
+
+            let summary = self
+                .dead_letter_replay
+                .replay_dead_letters(group.aggregate_id, events, self.policy.options.clone())
+                .await;
+
+            // The replay already happened, a failure to count its attempts
+            // must not hold back the other groups
+            for result in &summary.results {
+                let Some(id) = result.dead_letter_id else {
+                    continue;
+                };
+                let updated = match AttemptUpdate::of(result) {
+                    Some(AttemptUpdate::Failed(error)) => {
+                        self.attempt_log.record_failure(id, error.as_deref()).await
+                    },
+                    Some(AttemptUpdate::Removed) => self.attempt_log.remove(id).await,
+                    None => Ok(()),
+                };
+                if let Err(e) = updated {
+                    tracing::warn!(dead_letter_id = %id, error = %e, "Failed to update the retry attempts of dead letter");
+                }
+            }
+
+            if !report.retried_aggregates.contains(&group.aggregate_id) {
+                report.retried_aggregates.push(group.aggregate_id);
+            }
+            report.successful_replays += summary.successful_replays;
+            report.failed_replays += summary.failed_replays;
+        }
+
+        Ok(report)
+    }
+}
+
+/// Dead letters of the replay groups with at least one dead letter matching
+/// `filter`, in stream order. The dead letters of a group that do not match
+/// are kept, replaying the others without them would reorder its events.
+fn matching_groups(
+    events: Vec<DeadLetter>,
+    filter: &DeadLetterFilter,
+) -> BTreeMap<ReplayGroup, Vec<DeadLetter>> {
+    replay_groups(events)
+        .into_iter()
+        .filter(|(_, events)| events.iter().any(|event| filter.matches(event)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use esrc::{
+        version::{DeserializeVersion, SerializeVersion},
+        Event,
+    };
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::testing::TestEnvelope;
+
+    #[derive(Event, Serialize, Deserialize, Debug, Clone, SerializeVersion, DeserializeVersion)]
+    #[esrc(event(name = "User"))]
+    enum UserEvent {
+        Created { name: String },
+    }
+
+    fn dead_letter(id: Uuid, sequence: u64, consumer: &str) -> DeadLetter {
+        let mut dead_letter = TestEnvelope::new(&UserEvent::Created {
+            name: "Ada".to_string(),
+        })
+        .sequence(sequence)
+        .dead_letter("users", consumer);
+        dead_letter.aggregate_id = Some(id);
+        dead_letter
+    }
+
+    #[test]
+    fn matching_groups_keep_the_other_dead_letters_of_the_group() {
+        let matched = Uuid::now_v7();
+        let other = Uuid::now_v7();
+        let mut unmatched = dead_letter(matched, 1, "user-projector");
+        unmatched.subject = "test.UserUpdated.1".to_string();
+        let events = vec![
+            dead_letter(matched, 3, "user-projector"),
+            unmatched,
+            dead_letter(other, 2, "user-projector"),
+        ];
+        let filter = DeadLetterFilter {
+            aggregate_id: Some(matched),
+            subject: Some(events[0].subject.clone()),
+            ..DeadLetterFilter::default()
+        };
+
+        let groups = matching_groups(events, &filter);
+
+        assert_eq!(
+            groups
+                .iter()
+                .map(|(group, events)| (
+                    group.aggregate_id,
+                    events.iter().map(|e| e.stream_sequence).collect::<Vec<_>>()
+                ))
+                .collect::<Vec<_>>(),
+            vec![(matched, vec![1, 3])]
+        );
+    }
+
+    #[test]
+    fn matching_groups_leave_the_other_consumers_of_the_aggregate_alone() {
+        let aggregate_id = Uuid::now_v7();
+        let events = vec![
+            dead_letter(aggregate_id, 3, "user-projector"),
+            dead_letter(aggregate_id, 1, "user-mailer"),
+        ];
+        let filter = DeadLetterFilter {
+            consumer: Some("user-projector".to_string()),
+            ..DeadLetterFilter::default()
+        };
+
+        let groups = matching_groups(events, &filter);
+
+        assert_eq!(
+            groups
+                .keys()
+                .map(|group| group.consumer.as_str())
+                .collect::<Vec<_>>(),
+            vec!["user-projector"]
+        );
+        assert_eq!(groups.values().next().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn matching_groups_skip_dead_letters_without_aggregate() {
+        let mut event = dead_letter(Uuid::now_v7(), 1, "user-projector");
+        event.aggregate_id = None;
+
+        assert!(matching_groups(vec![event], &DeadLetterFilter::default()).is_empty());
+    }
+
+    fn result(outcome: ReplayOutcome, removed: bool, error: Option<&str>) -> ReplayEventResult {
+        ReplayEventResult {
+            dead_letter_id: Some(Uuid::now_v7()),
+            aggregate_id: Uuid::now_v7(),
+            stream_sequence: 1,
+            outcome,
+            error_kind: None,
+            error_message: error.map(str::to_string),
+            removed,
+        }
+    }
+
+    #[test]
+    fn replayed_dead_letter_left_in_the_store_counts_as_an_attempt() {
+        assert_eq!(
+            AttemptUpdate::of(&result(ReplayOutcome::Replayed, true, None)),
+            Some(AttemptUpdate::Removed)
+        );
+        assert_eq!(
+            AttemptUpdate::of(&result(
+                ReplayOutcome::Republished,
+                false,
+                Some("Failed to remove dead letter")
+            )),
+            Some(AttemptUpdate::Failed(Some(
+                "Failed to remove dead letter".to_string()
+            )))
+        );
+        assert!(matches!(
+            AttemptUpdate::of(&result(ReplayOutcome::Replayed, false, None)),
+            Some(AttemptUpdate::Failed(Some(_)))
+        ));
+        assert_eq!(
+            AttemptUpdate::of(&result(ReplayOutcome::Failed, false, Some("timeout"))),
+            Some(AttemptUpdate::Failed(Some("timeout".to_string())))
+        );
+        assert_eq!(
+            AttemptUpdate::of(&result(ReplayOutcome::Skipped, false, None)),
+            None
+        );
+    }
+}
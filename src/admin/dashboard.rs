@@ -0,0 +1,67 @@
+use axum::{response::Html, routing::get, Json, Router};
+
+use crate::{
+    admin::auth::{AdminAuth, AdminPermission},
+    feature::AutomationMonitor,
+};
+
+/// Single page dashboard calling the admin routes from the browser
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+
+/// Serve the dashboard at `{endpoint}/admin/ui` and the status of the
+/// automations at `{endpoint}/admin/automations`, without authentication.
+///
+/// The admin routes used by the dashboard must be mounted on the same
+/// `endpoint` with `AdminHandler::setup_router`. The routes of a handler
+/// created with `AdminHandler::with_name` are reached by entering its name in
+/// the dashboard, or opening `ui?handler=<name>`.
+pub fn setup_router<S>(router: &mut Router<S>, endpoint: &str, monitor: AutomationMonitor)
+where
+    S: Clone + Send + Sync + 'static,
+{
+    mount(router, endpoint, monitor, None);
+}
+
+/// Serve the dashboard, the automations route requiring the `inspect`
+/// permission. The page itself is static, the operator enters a bearer token
+/// that is sent with every call to the admin routes.
+pub fn setup_router_with_auth<S>(
+    router: &mut Router<S>,
+    endpoint: &str,
+    monitor: AutomationMonitor,
+    auth: AdminAuth,
+) where
+    S: Clone + Send + Sync + 'static,
+{
+    mount(router, endpoint, monitor, Some(&auth));
+}
+
+fn mount<S>(
+    router: &mut Router<S>,
+    endpoint: &str,
+    monitor: AutomationMonitor,
+    auth: Option<&AdminAuth>,
+) where
+    S: Clone + Send + Sync + 'static,
+{
+    let admin_path = format!("{}/admin", endpoint.trim_end_matches("/"));
+
+    let new_router = std::mem::take(router)
+        .route(
+            &format!("{}/ui", admin_path),
+            get(|| async { Html(DASHBOARD_HTML) }),
+        )
+        .route(
+            &format!("{}/automations", admin_path),
+            AdminAuth::guard(
+                auth,
+                AdminPermission::Inspect,
+                get(move || {
+                    let monitor = monitor.clone();
+                    async move { Json(monitor.snapshot()) }
+                }),
+            ),
+        );
+
+    *router = new_router;
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use esrc::Envelope;
+use nats_dead_letter::{DeadLetter, DeadLetterStore};
+use serde::Serialize;
+
+use crate::admin::filter::DeadLetterFilter;
+use crate::dead_letter::decoder::DeadLetterDecoder;
+use crate::dead_letter::failure::{
+    failure_keys, DeadLetterFailure, DeadLetterFailureLog, FailureKey,
+};
+
+/// Number of dead letters sharing the same value of a grouping key
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetterStatsBucket {
+    pub key: String,
+    pub count: usize,
+    pub oldest: DateTime<Utc>,
+    pub newest: DateTime<Utc>,
+}
+
+/// Counts of the dead letters matching a filter, grouped in several ways for
+/// triage
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetterStatsSummary {
+    pub total: usize,
+    pub by_stream: Vec<DeadLetterStatsBucket>,
+    pub by_consumer: Vec<DeadLetterStatsBucket>,
+    /// Keyed by `unknown` when the subject can not be decoded
+    pub by_event_name: Vec<DeadLetterStatsBucket>,
+    /// Keyed by the error text with its numbers and IDs masked, `unknown`
+    /// when no failure was recorded for the consumer and stream sequence of
+    /// the message, see `FailureKey`
+    pub by_error: Vec<DeadLetterStatsBucket>,
+    /// Keyed by the Rust type of the projector error, `panic` for a panic
+    pub by_error_type: Vec<DeadLetterStatsBucket>,
+    /// Keyed by the start of the hour the message was dead lettered, oldest
+    /// first
+    pub by_hour: Vec<DeadLetterStatsBucket>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeadLetterStatsError {
+    #[error(transparent)]
+    DeadLetterStore(Box<dyn std::error::Error + Send + Sync>),
+    #[error("Failure log error: {0}")]
+    FailureLog(#[from] sqlx::Error),
+}
+
+const UNKNOWN: &str = "unknown";
+
+/// Error text with the parts that change from one message to the next, like
+/// IDs and sequence numbers, replaced by `#` so that the same failure is
+/// counted once
+pub fn error_fingerprint(error: &str) -> String {
+    error
+        .split_inclusive(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+        .map(|part| {
+            let word_len = part
+                .trim_end_matches(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+                .len();
+            let (word, separator) = part.split_at(word_len);
+            if word.chars().any(|c| c.is_ascii_digit()) {
+                format!("#{}", separator)
+            } else {
+                part.to_string()
+            }
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Grouping {
+    buckets: HashMap<String, DeadLetterStatsBucket>,
+}
+
+impl Grouping {
+    fn add(&mut self, key: String, timestamp: DateTime<Utc>) {
+        self.buckets
+            .entry(key.clone())
+            .and_modify(|bucket| {
+                bucket.count += 1;
+                bucket.oldest = bucket.oldest.min(timestamp);
+                bucket.newest = bucket.newest.max(timestamp);
+            })
+            .or_insert(DeadLetterStatsBucket {
+                key,
+                count: 1,
+                oldest: timestamp,
+                newest: timestamp,
+            });
+    }
+
+    /// Largest buckets first
+    fn by_count(self) -> Vec<DeadLetterStatsBucket> {
+        let mut buckets = self.buckets.into_values().collect::<Vec<_>>();
+        buckets.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+        buckets
+    }
+
+    fn by_key(self) -> Vec<DeadLetterStatsBucket> {
+        let mut buckets = self.buckets.into_values().collect::<Vec<_>>();
+        buckets.sort_by(|a, b| a.key.cmp(&b.key));
+        buckets
+    }
+}
+
+#[derive(Clone)]
+pub struct DeadLetterStats<DLS>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+{
+    dead_letter_store: DLS,
+    failure_log: DeadLetterFailureLog,
+}
+
+impl<DLS> DeadLetterStats<DLS>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+{
+    pub fn new(dead_letter_store: DLS, failure_log: DeadLetterFailureLog) -> Self {
+        Self {
+            dead_letter_store,
+            failure_log,
+        }
+    }
+
+    pub async fn summary(
+        &self,
+        filter: &DeadLetterFilter,
+    ) -> Result<DeadLetterStatsSummary, DeadLetterStatsError> {
+        let events = self
+            .dead_letter_store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .map_err(|e| DeadLetterStatsError::DeadLetterStore(e.into()))?
+            .into_iter()
+            .filter(|e| filter.matches(e))
+            .collect::<Vec<_>>();
+
+        let failures = self.failure_log.find(&failure_keys(&events)).await?;
+
+        Ok(Self::group(&events, &failures))
+    }
+
+    fn group(
+        events: &[DeadLetter],
+        failures: &HashMap<FailureKey, DeadLetterFailure>,
+    ) -> DeadLetterStatsSummary {
+        let mut by_stream = Grouping::default();
+        let mut by_consumer = Grouping::default();
+        let mut by_event_name = Grouping::default();
+        let mut by_error = Grouping::default();
+        let mut by_error_type = Grouping::default();
+        let mut by_hour = Grouping::default();
+
+        for event in events {
+            let timestamp: DateTime<Utc> = SystemTime::from(event.timestamp).into();
+            let event_name = DeadLetterDecoder::envelope(event)
+                .map(|envelope| envelope.name().to_string())
+                .unwrap_or_else(|_| UNKNOWN.to_string());
+            let failure = failures.get(&FailureKey::of(event));
+            let error = failure
+                .map(|failure| error_fingerprint(&failure.error))
+                .unwrap_or_else(|| UNKNOWN.to_string());
+            let error_type = match failure {
+                Some(failure) if failure.is_panic() => "panic".to_string(),
+                Some(failure) => failure
+                    .error_type
+                    .clone()
+                    .unwrap_or_else(|| UNKNOWN.to_string()),
+                None => UNKNOWN.to_string(),
+            };
+            let hour = timestamp
+                .duration_trunc(TimeDelta::hours(1))
+                .unwrap_or(timestamp);
+
+            by_stream.add(event.stream.clone(), timestamp);
+            by_consumer.add(event.consumer.clone(), timestamp);
+            by_event_name.add(event_name, timestamp);
+            by_error.add(error, timestamp);
+            by_error_type.add(error_type, timestamp);
+            by_hour.add(hour.to_rfc3339(), timestamp);
+        }
+
+        DeadLetterStatsSummary {
+            total: events.len(),
+            by_stream: by_stream.by_count(),
+            by_consumer: by_consumer.by_count(),
+            by_event_name: by_event_name.by_count(),
+            by_error: by_error.by_count(),
+            by_error_type: by_error_type.by_count(),
+            by_hour: by_hour.by_key(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use esrc::{
+        version::{DeserializeVersion, SerializeVersion},
+        Event,
+    };
+    use serde::Deserialize;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::dead_letter::memory::InMemoryDeadLetterStore;
+    use crate::testing::TestEnvelope;
+
+    #[derive(Event, Serialize, Deserialize, Debug, Clone, SerializeVersion, DeserializeVersion)]
+    #[esrc(event(name = "User"))]
+    enum UserEvent {
+        Created { name: String },
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 1, 1, hour, minute, 0).unwrap()
+    }
+
+    fn dead_letter(consumer: &str, sequence: u64, timestamp: DateTime<Utc>) -> DeadLetter {
+        TestEnvelope::new(&UserEvent::Created {
+            name: "Ada".to_string(),
+        })
+        .sequence(sequence)
+        .timestamp(timestamp.into())
+        .dead_letter("users", consumer)
+    }
+
+    fn failure(
+        dead_letter: &DeadLetter,
+        error: &str,
+        error_type: Option<&str>,
+    ) -> (FailureKey, DeadLetterFailure) {
+        let failure = DeadLetterFailure {
+            consumer: dead_letter.consumer.clone(),
+            stream_sequence: dead_letter.stream_sequence,
+            aggregate_id: dead_letter.aggregate_id.unwrap(),
+            error: error.to_string(),
+            error_type: error_type.map(str::to_string),
+            attempts: 1,
+            first_failed_at: at(0, 0),
+            last_failed_at: at(0, 0),
+        };
+        (FailureKey::of(dead_letter), failure)
+    }
+
+    fn counts(buckets: &[DeadLetterStatsBucket]) -> Vec<(&str, usize)> {
+        buckets
+            .iter()
+            .map(|bucket| (bucket.key.as_str(), bucket.count))
+            .collect()
+    }
+
+    #[test]
+    fn fingerprint_masks_numbers_and_ids() {
+        assert_eq!(
+            error_fingerprint("User 0190f3c2-7d1e-7abc-8def-0123456789ab not found at sequence 17"),
+            "User # not found at sequence #"
+        );
+        assert_eq!(
+            error_fingerprint("timeout after 30s: retry"),
+            "timeout after #: retry"
+        );
+        assert_eq!(error_fingerprint("projection failed"), "projection failed");
+    }
+
+    #[test]
+    fn fingerprint_is_shared_by_the_same_failure() {
+        assert_eq!(
+            error_fingerprint(&format!("User {} not found", Uuid::now_v7())),
+            error_fingerprint(&format!("User {} not found", Uuid::now_v7()))
+        );
+    }
+
+    #[test]
+    fn group_counts_the_dead_letters_by_each_key() {
+        let first = dead_letter("user-projector", 1, at(10, 5));
+        let second = dead_letter("user-projector", 2, at(10, 50));
+        // Same stream sequence as `first`, failed by another consumer
+        let audit = dead_letter("user-audit", 1, at(11, 0));
+        let mut undecodable = dead_letter("user-projector", 5, at(11, 30));
+        undecodable.subject = "other.User".to_string();
+        let failures = HashMap::from([
+            failure(&first, "User 1 not found", Some("app::Error")),
+            failure(&second, "User 2 not found", Some("app::Error")),
+            failure(&audit, "boom", None),
+        ]);
+
+        let summary = DeadLetterStats::<InMemoryDeadLetterStore>::group(
+            &[first, second, audit, undecodable],
+            &failures,
+        );
+
+        assert_eq!(summary.total, 4);
+        assert_eq!(counts(&summary.by_stream), [("users", 4)]);
+        assert_eq!(
+            counts(&summary.by_consumer),
+            [("user-projector", 3), ("user-audit", 1)]
+        );
+        assert_eq!(counts(&summary.by_event_name), [("User", 3), (UNKNOWN, 1)]);
+        assert_eq!(
+            counts(&summary.by_error),
+            [("User # not found", 2), ("boom", 1), (UNKNOWN, 1)]
+        );
+        assert_eq!(
+            counts(&summary.by_error_type),
+            [("app::Error", 2), ("panic", 1), (UNKNOWN, 1)]
+        );
+        assert_eq!(
+            counts(&summary.by_hour),
+            [
+                ("2026-01-01T10:00:00+00:00", 2),
+                ("2026-01-01T11:00:00+00:00", 2)
+            ]
+        );
+        assert_eq!(summary.by_hour[0].oldest, at(10, 5));
+        assert_eq!(summary.by_hour[0].newest, at(10, 50));
+    }
+}
@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+
+use nats_dead_letter::{DeadLetter, DeadLetterStore};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::admin::projector_registry::{DeadLetterProjector, ProjectorError};
+use crate::admin::replay_dead_letter::{
+    ReplayDeadLetter, ReplayGroup, ReplayOptions, ReplayOutcome, ReplaySummary,
+};
+
+/// Correction of a poison message submitted by an operator
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeadLetterEdit {
+    /// Replaces the payload of the dead letter
+    pub payload: Option<Value>,
+    /// Added to the headers of the dead letter, replacing the values of the
+    /// headers already present
+    pub headers: Option<HashMap<String, String>>,
+    pub reason: String,
+}
+
+impl DeadLetterEdit {
+    fn apply(&self, dead_letter: &DeadLetter) -> Result<DeadLetter, serde_json::Error> {
+        let mut edited = dead_letter.clone();
+
+        if let Some(payload) = &self.payload {
+            edited.payload = serde_json::to_vec(payload)?;
+        }
+        if let Some(edits) = &self.headers {
+            let mut headers = dead_letter
+                .headers
+                .clone()
+                .map(|headers| {
+                    headers
+                        .into_iter()
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect::<HashMap<_, _>>()
+                })
+                .unwrap_or_default();
+            headers.extend(edits.clone());
+            edited.headers = Some(headers.into_iter().collect());
+        }
+
+        Ok(edited)
+    }
+}
+
+/// Original and edited versions of every dead letter replayed after an edit,
+/// the original is removed from the `DeadLetterStore` once replayed. Each
+/// edit is recorded as `pending` before its replay and completed with the
+/// `ReplayOutcome` of the edited dead letter.
+#[derive(Clone)]
+pub struct DeadLetterEditLog {
+    db: sqlx::PgPool,
+}
+
+impl DeadLetterEditLog {
+    pub fn new(db: sqlx::PgPool) -> Self {
+        Self { db }
+    }
+
+    pub async fn setup(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS dead_letter_edits(
+                id uuid                         NOT NULL,
+                dead_letter_id uuid             NOT NULL,
+                aggregate_id uuid,
+                stream text                     NOT NULL,
+                consumer text                   NOT NULL,
+                subject text                    NOT NULL,
+                stream_sequence bigint          NOT NULL,
+                original_headers jsonb,
+                original_payload bytea          NOT NULL,
+                edited_headers jsonb,
+                edited_payload bytea            NOT NULL,
+                reason text                     NOT NULL,
+                outcome text                    NOT NULL DEFAULT 'pending',
+                error text,
+                edited_at timestamptz           NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (id)
+            );",
+        )
+        .execute(&self.db)
+        .await?;
+        sqlx::query(
+            "ALTER TABLE dead_letter_edits
+                ADD COLUMN IF NOT EXISTS outcome text NOT NULL DEFAULT 'pending',
+                ADD COLUMN IF NOT EXISTS error text;",
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Record a pending edit, returns the ID of the entry to complete once
+    /// the edited dead letter is replayed
+    pub async fn record(
+        &self,
+        dead_letter_id: Uuid,
+        original: &DeadLetter,
+        edited: &DeadLetter,
+        reason: &str,
+    ) -> Result<Uuid, EditDeadLetterError> {
+        let headers = |dead_letter: &DeadLetter| {
+            dead_letter
+                .headers
+                .as_ref()
+                .map(serde_json::to_value)
+                .transpose()
+                .map_err(EditDeadLetterError::Headers)
+        };
+        let id = Uuid::now_v7();
+
+        sqlx::query(
+            "INSERT INTO dead_letter_edits
+                (id, dead_letter_id, aggregate_id, stream, consumer, subject, stream_sequence,
+                 original_headers, original_payload, edited_headers, edited_payload, reason)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+        )
+        .bind(id)
+        .bind(dead_letter_id)
+        .bind(original.aggregate_id)
+        .bind(&original.stream)
+        .bind(&original.consumer)
+        .bind(&original.subject)
+        .bind(original.stream_sequence as i64)
+        .bind(headers(original)?)
+        .bind(&original.payload)
+        .bind(headers(edited)?)
+        .bind(&edited.payload)
+        .bind(reason)
+        .execute(&self.db)
+        .await?;
+        Ok(id)
+    }
+
+    /// Outcome of the replay of a pending edit
+    pub async fn complete(
+        &self,
+        id: Uuid,
+        outcome: ReplayOutcome,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE dead_letter_edits SET outcome = $2, error = $3
+                WHERE id = $1 AND outcome = 'pending'",
+        )
+        .bind(id)
+        .bind(outcome.as_str())
+        .bind(error)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EditDeadLetterError {
+    #[error("No dead letter events found")]
+    NotFound,
+    #[error("A reason is required to edit a dead letter")]
+    MissingReason,
+    #[error("The edit must change the payload or the headers")]
+    EmptyEdit,
+    #[error("Dead letter {0} has no aggregate ID")]
+    MissingAggregateId(Uuid),
+    #[error("Invalid payload: {0}")]
+    InvalidPayload(#[from] serde_json::Error),
+    #[error("Edited dead letter is invalid: {0}")]
+    Invalid(#[from] ProjectorError),
+    #[error(transparent)]
+    DeadLetterStore(Box<dyn std::error::Error + Send + Sync>),
+    #[error(transparent)]
+    EditLog(#[from] sqlx::Error),
+    #[error("Failed to serialize the headers of the dead letter: {0}")]
+    Headers(serde_json::Error),
+}
+
+#[derive(Clone)]
+pub struct EditDeadLetter<DLS, P>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+    P: DeadLetterProjector + Clone + 'static,
+{
+    dead_letter_store: DLS,
+    dead_letter_replay: ReplayDeadLetter<DLS, P>,
+    edit_log: DeadLetterEditLog,
+}
+
+impl<DLS, P> EditDeadLetter<DLS, P>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+    P: DeadLetterProjector + Clone + 'static,
+{
+    pub fn new(
+        dead_letter_store: DLS,
+        dead_letter_replay: ReplayDeadLetter<DLS, P>,
+        edit_log: DeadLetterEditLog,
+    ) -> Self {
+        Self {
+            dead_letter_store,
+            dead_letter_replay,
+            edit_log,
+        }
+    }
+
+    /// Apply the edit to the dead letter, check that the result deserializes
+    /// into the event group of its projector and replay it in place of the
+    /// original. The other dead letters its consumer kept for the aggregate
+    /// are replayed with it in stream order, so that it is never projected
+    /// before an earlier event that is still dead lettered, see `ReplayGroup`.
+    pub async fn edit_and_replay(
+        &self,
+        id: Uuid,
+        edit: DeadLetterEdit,
+        options: ReplayOptions,
+    ) -> Result<ReplaySummary, EditDeadLetterError> {
+        if edit.reason.trim().is_empty() {
+            return Err(EditDeadLetterError::MissingReason);
+        }
+        if edit.payload.is_none() && edit.headers.is_none() {
+            return Err(EditDeadLetterError::EmptyEdit);
+        }
+
+        let dead_letters = self
+            .dead_letter_store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .map_err(|e| EditDeadLetterError::DeadLetterStore(e.into()))?;
+        let original = dead_letters
+            .iter()
+            .find(|e| e.id == Some(id))
+            .cloned()
+            .ok_or(EditDeadLetterError::NotFound)?;
+        let group =
+            ReplayGroup::of(&original).ok_or(EditDeadLetterError::MissingAggregateId(id))?;
+        let aggregate_id = group.aggregate_id;
+
+        let edited = edit.apply(&original)?;
+        self.dead_letter_replay.validate(&edited)?;
+        let events = substitute(dead_letters, &group, &edited);
+
+        // The original is recorded first, a successful replay removes it from
+        // the store
+        let edit_id = self
+            .edit_log
+            .record(id, &original, &edited, &edit.reason)
+            .await?;
+
+        tracing::info!(
+            %id,
+            %aggregate_id,
+            total_events = events.len(),
+            reason = %edit.reason,
+            "Replaying edited dead letter with its aggregate"
+        );
+
+        let summary = self
+            .dead_letter_replay
+            .replay_dead_letters(aggregate_id, events, options)
+            .await;
+
+        let (outcome, error) = match summary
+            .results
+            .iter()
+            .find(|result| result.dead_letter_id == Some(id))
+        {
+            Some(result) => (result.outcome, result.error_message.clone()),
+            None => (ReplayOutcome::Skipped, None),
+        };
+        // The replay already happened, a failure to record its outcome leaves
+        // the edit pending instead of failing the command
+        if let Err(e) = self
+            .edit_log
+            .complete(edit_id, outcome, error.as_deref())
+            .await
+        {
+            tracing::error!(%id, %edit_id, "Failed to record the outcome of the edit: {}", e);
+        }
+
+        Ok(summary)
+    }
+}
+
+/// Dead letters of the replay group of the edited one, i.e. of its aggregate
+/// dead lettered by the same consumer of the same stream, with the edited one
+/// in place of its original
+fn substitute(
+    dead_letters: Vec<DeadLetter>,
+    group: &ReplayGroup,
+    edited: &DeadLetter,
+) -> Vec<DeadLetter> {
+    dead_letters
+        .into_iter()
+        .filter(|e| ReplayGroup::of(e).as_ref() == Some(group))
+        .map(|e| {
+            if e.id == edited.id {
+                edited.clone()
+            } else {
+                e
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use esrc::{
+        version::{DeserializeVersion, SerializeVersion},
+        Event,
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::dead_letter::decoder::DeadLetterDecoder;
+    use crate::testing::TestEnvelope;
+
+    #[derive(Event, Serialize, Deserialize, Debug, Clone, SerializeVersion, DeserializeVersion)]
+    #[esrc(event(name = "User"))]
+    enum UserEvent {
+        Created { name: String },
+    }
+
+    fn dead_letter(aggregate_id: Uuid, sequence: u64) -> DeadLetter {
+        consumer_dead_letter(aggregate_id, sequence, "user-projector")
+    }
+
+    fn consumer_dead_letter(aggregate_id: Uuid, sequence: u64, consumer: &str) -> DeadLetter {
+        TestEnvelope::new(&UserEvent::Created {
+            name: "Ada".to_string(),
+        })
+        .id(aggregate_id)
+        .sequence(sequence)
+        .metadata("Trace-Id", "abc")
+        .dead_letter("users", consumer)
+    }
+
+    #[test]
+    fn edit_replaces_the_payload() {
+        let original = dead_letter(Uuid::now_v7(), 1);
+        let edit = DeadLetterEdit {
+            payload: Some(json!({ "Created": { "name": "Grace" } })),
+            headers: None,
+            reason: "typo".to_string(),
+        };
+
+        let edited = edit.apply(&original).unwrap();
+
+        assert_eq!(
+            DeadLetterDecoder::payload(&edited).unwrap(),
+            json!({ "Created": { "name": "Grace" } })
+        );
+        assert_eq!(edited.headers, original.headers);
+    }
+
+    #[test]
+    fn edit_merges_the_headers() {
+        let original = dead_letter(Uuid::now_v7(), 1);
+        let edit = DeadLetterEdit {
+            payload: None,
+            headers: Some(HashMap::from([
+                ("Trace-Id".to_string(), "def".to_string()),
+                ("Tenant".to_string(), "acme".to_string()),
+            ])),
+            reason: "missing tenant".to_string(),
+        };
+
+        let edited = edit.apply(&original).unwrap();
+
+        let headers = DeadLetterDecoder::headers(&edited).unwrap();
+        assert_eq!(headers.get("Trace-Id").unwrap().as_str(), "def");
+        assert_eq!(headers.get("Tenant").unwrap().as_str(), "acme");
+        assert_eq!(edited.payload, original.payload);
+    }
+
+    #[test]
+    fn substitute_keeps_the_other_dead_letters_of_the_aggregate() {
+        let aggregate_id = Uuid::now_v7();
+        let earlier = dead_letter(aggregate_id, 1);
+        let original = dead_letter(aggregate_id, 2);
+        let other_aggregate = dead_letter(Uuid::now_v7(), 3);
+        let mut edited = original.clone();
+        edited.payload = b"{}".to_vec();
+        let group = ReplayGroup::of(&original).unwrap();
+
+        let events = substitute(
+            vec![earlier.clone(), original, other_aggregate],
+            &group,
+            &edited,
+        );
+
+        assert_eq!(
+            events.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![earlier.id, edited.id]
+        );
+        assert_eq!(events[1].payload, edited.payload);
+    }
+
+    #[test]
+    fn substitute_leaves_the_dead_letters_of_other_consumers_alone() {
+        let aggregate_id = Uuid::now_v7();
+        let original = dead_letter(aggregate_id, 2);
+        let other_consumer = consumer_dead_letter(aggregate_id, 1, "user-audit");
+        let mut other_stream = dead_letter(aggregate_id, 3);
+        other_stream.stream = "accounts".to_string();
+        let edited = original.clone();
+        let group = ReplayGroup::of(&original).unwrap();
+
+        let events = substitute(
+            vec![other_consumer, original, other_stream],
+            &group,
+            &edited,
+        );
+
+        assert_eq!(
+            events.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![edited.id]
+        );
+    }
+}
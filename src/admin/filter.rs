@@ -0,0 +1,48 @@
+use nats_dead_letter::DeadLetter;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Criteria used to select a subset of the dead letters held by a
+/// `DeadLetterStore`. Every field that is set must match.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct DeadLetterFilter {
+    pub stream: Option<String>,
+    pub consumer: Option<String>,
+    pub aggregate_id: Option<Uuid>,
+    pub subject: Option<String>,
+}
+
+impl DeadLetterFilter {
+    /// Returns true when no criteria are set, i.e. the filter selects everything
+    pub fn is_empty(&self) -> bool {
+        self.stream.is_none()
+            && self.consumer.is_none()
+            && self.aggregate_id.is_none()
+            && self.subject.is_none()
+    }
+
+    pub fn matches(&self, dead_letter: &DeadLetter) -> bool {
+        if let Some(stream) = &self.stream
+            && &dead_letter.stream != stream
+        {
+            return false;
+        }
+        if let Some(consumer) = &self.consumer
+            && &dead_letter.consumer != consumer
+        {
+            return false;
+        }
+        if let Some(aggregate_id) = self.aggregate_id
+            && dead_letter.aggregate_id != Some(aggregate_id)
+        {
+            return false;
+        }
+        if let Some(subject) = &self.subject
+            && &dead_letter.subject != subject
+        {
+            return false;
+        }
+
+        true
+    }
+}
@@ -1,24 +1,79 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+
 use axum::{
-    extract::{FromRef, Path, State},
-    routing::{patch, post},
-    Json, Router,
+    extract::{DefaultBodyLimit, FromRef, FromRequestParts, Path, Query, State},
+    handler::Handler,
+    http::{header, request::Parts, Method, StatusCode},
+    routing::{on, MethodFilter, MethodRouter},
+    Extension, Json, Router,
 };
 use discern::command::CommandBus;
-use esrc::project::Project;
 use nats_dead_letter::DeadLetterStore;
+use serde::Deserialize;
 use uuid::Uuid;
 
 use crate::{
     admin::{
-        replay_dead_letter::{ReplayDeadLetterError, ReplaySummary},
-        AdminCommands, AdminCommandsError, AdminHandler,
+        audit::{with_actor, AdminAuditEntry, AuditFilter},
+        auth::{AdminAuth, AdminPermission, AdminPrincipal},
+        dead_letter_stats::{DeadLetterStatsError, DeadLetterStatsSummary},
+        edit_dead_letter::{DeadLetterEdit, EditDeadLetterError},
+        filter::DeadLetterFilter,
+        inspect_dead_letter::{DeadLetterView, InspectDeadLetterError},
+        projector_registry::DeadLetterProjector,
+        purge_dead_letter::{PurgeDeadLetterError, PurgeSummary},
+        rebuild_view::{RebuildSummary, RebuildViewError},
+        replay_dead_letter::{ReplayDeadLetterError, ReplayOptions, ReplaySummary},
+        replay_jobs::{ReplayJob, ReplayJobError, ReplayJobTarget},
+        transfer_dead_letter::{DeadLetterRecord, ImportSummary, TransferDeadLetterError},
+        AdminCommands, AdminCommandsError, AdminCommandsOutput, AdminHandler,
     },
     utils::problem_details::ProblemDetails,
 };
 
+/// Command buses the admin routes dispatch to. Both fields are public so the
+/// state can still be built as a struct literal, which now has to list
+/// `named_command_buses` too: use `AdminAppState::new` to keep code that only
+/// mounts an unnamed handler unchanged.
 #[derive(Clone)]
 pub struct AdminAppState {
     pub command_bus: CommandBus,
+    /// Command bus of each `AdminHandler` mounted with a name, see
+    /// `with_named_command_bus`
+    pub named_command_buses: HashMap<String, CommandBus>,
+}
+
+impl AdminAppState {
+    /// `command_bus` handles the routes of the unnamed `AdminHandler`
+    pub fn new(command_bus: CommandBus) -> Self {
+        Self {
+            command_bus,
+            named_command_buses: HashMap::new(),
+        }
+    }
+
+    /// Dispatch the commands of the routes of the `AdminHandler` named `name`
+    /// to their own command bus
+    pub fn with_named_command_bus(
+        mut self,
+        name: impl Into<String>,
+        command_bus: CommandBus,
+    ) -> Self {
+        self.named_command_buses.insert(name.into(), command_bus);
+        self
+    }
+
+    fn command_bus(&self, name: Option<&AdminHandlerName>) -> Result<&CommandBus, ProblemDetails> {
+        match name {
+            None => Ok(&self.command_bus),
+            // The routes of a named handler are mounted without its command
+            // bus, which may not have been registered on this state
+            Some(AdminHandlerName(name)) => self.named_command_buses.get(name).ok_or_else(|| {
+                ProblemDetails::not_found(format!("No admin handler named {}", name))
+            }),
+        }
+    }
 }
 
 pub trait HasAdminAppState {
@@ -37,55 +92,584 @@ where
 
 impl<DLS, P> AdminHandler<DLS, P>
 where
-    DLS: DeadLetterStore + Send + Sync + 'static,
-    P: Project + Send + Sync + 'static,
+    DLS: DeadLetterStore + Clone + Send + Sync + 'static,
+    P: DeadLetterProjector + Clone + 'static,
 {
-    /// Define all the common admin routes
+    /// Define all the common admin routes, without authentication
     pub fn setup_router<S>(self, router: &mut Router<S>, endpoint: &str)
     where
         S: HasAdminAppState + Clone + Send + Sync + 'static,
     {
-        let admin_path = format!("{}/admin", endpoint.trim_end_matches("/"));
-
-        let new_router = std::mem::take(router)
-            .route(
-                &format!("{}/dead-letters/replay/{{aggregate_id}}", admin_path),
-                patch(replay_one_handler::<S>),
-            )
-            .route(
-                &format!("{}/dead-letters/replay-all", admin_path),
-                post(replay_all_handler::<S>),
+        Self::mount(router, endpoint, self.name.as_deref(), None);
+    }
+
+    /// Define all the common admin routes, each one requiring the
+    /// `AdminPermission` of its command
+    pub fn setup_router_with_auth<S>(self, router: &mut Router<S>, endpoint: &str, auth: AdminAuth)
+    where
+        S: HasAdminAppState + Clone + Send + Sync + 'static,
+    {
+        Self::mount(router, endpoint, self.name.as_deref(), Some(&auth));
+    }
+
+    fn mount<S>(
+        router: &mut Router<S>,
+        endpoint: &str,
+        name: Option<&str>,
+        auth: Option<&AdminAuth>,
+    ) where
+        S: HasAdminAppState + Clone + Send + Sync + 'static,
+    {
+        let admin_path = match name {
+            Some(name) => format!("{}/admin/{}", endpoint.trim_end_matches("/"), name),
+            None => format!("{}/admin", endpoint.trim_end_matches("/")),
+        };
+        let mut routes = Router::new();
+        for route in admin_routes::<S>() {
+            routes = routes.route(
+                &format!("{}{}", admin_path, route.path),
+                AdminAuth::guard(auth, route.permission, route.handler),
             );
+        }
+        let routes = match name {
+            Some(name) => routes.layer(Extension(AdminHandlerName(name.to_string()))),
+            None => routes,
+        };
+
+        let new_router = std::mem::take(router).merge(routes);
 
         *router = new_router;
     }
 }
 
+/// A route mounted by `AdminHandler::setup_router`, every one of them is
+/// described by the OpenAPI document
+pub(crate) struct AdminRoute<S> {
+    pub method: Method,
+    /// Relative to `{endpoint}/admin`, or `{endpoint}/admin/{name}` for a
+    /// named handler
+    pub path: &'static str,
+    pub permission: AdminPermission,
+    pub handler: MethodRouter<S>,
+}
+
+impl<S> AdminRoute<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn new<H, T>(
+        method: Method,
+        path: &'static str,
+        permission: AdminPermission,
+        handler: H,
+    ) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        let filter = MethodFilter::try_from(method.clone())
+            .expect("admin routes only use the standard methods");
+        Self {
+            method,
+            path,
+            permission,
+            handler: on(filter, handler),
+        }
+    }
+
+    /// Replace the default limit of axum on the size of the request body
+    fn body_limit(mut self, limit: usize) -> Self {
+        self.handler = self.handler.layer(DefaultBodyLimit::max(limit));
+        self
+    }
+}
+
+pub(crate) fn admin_routes<S>() -> Vec<AdminRoute<S>>
+where
+    S: HasAdminAppState + Clone + Send + Sync + 'static,
+{
+    use AdminPermission::{Audit, Import, Inspect, Purge, Rebuild, Replay};
+
+    vec![
+        AdminRoute::new(
+            Method::GET,
+            "/dead-letters",
+            Inspect,
+            list_dead_letters_handler::<S>,
+        ),
+        AdminRoute::new(
+            Method::GET,
+            "/dead-letters/stats",
+            Inspect,
+            dead_letter_stats_handler::<S>,
+        ),
+        AdminRoute::new(
+            Method::GET,
+            "/dead-letters/export",
+            Inspect,
+            export_dead_letters_handler::<S>,
+        ),
+        AdminRoute::new(
+            Method::POST,
+            "/dead-letters/import",
+            Import,
+            import_dead_letters_handler::<S>,
+        )
+        .body_limit(IMPORT_BODY_LIMIT),
+        AdminRoute::new(
+            Method::PATCH,
+            "/dead-letters/replay/{aggregate_id}",
+            Replay,
+            replay_one_handler::<S>,
+        ),
+        AdminRoute::new(
+            Method::POST,
+            "/dead-letters/{id}/edit-and-replay",
+            Replay,
+            edit_and_replay_handler::<S>,
+        ),
+        AdminRoute::new(
+            Method::POST,
+            "/dead-letters/replay",
+            Replay,
+            replay_filtered_handler::<S>,
+        ),
+        AdminRoute::new(
+            Method::POST,
+            "/dead-letters/replay-all",
+            Replay,
+            replay_all_handler::<S>,
+        ),
+        AdminRoute::new(
+            Method::POST,
+            "/dead-letters/purge",
+            Purge,
+            purge_handler::<S>,
+        ),
+        AdminRoute::new(
+            Method::GET,
+            "/dead-letters/{id}",
+            Inspect,
+            get_dead_letter_handler::<S>,
+        ),
+        AdminRoute::new(
+            Method::DELETE,
+            "/dead-letters/{id}",
+            Purge,
+            purge_one_handler::<S>,
+        ),
+        AdminRoute::new(
+            Method::GET,
+            "/replay-jobs/{id}",
+            Replay,
+            get_replay_job_handler::<S>,
+        ),
+        AdminRoute::new(
+            Method::POST,
+            "/replay-jobs/{id}/cancel",
+            Replay,
+            cancel_replay_job_handler::<S>,
+        ),
+        AdminRoute::new(Method::GET, "/audit", Audit, list_audit_handler::<S>),
+        AdminRoute::new(
+            Method::POST,
+            "/views/{view}/rebuild",
+            Rebuild,
+            rebuild_view_handler::<S>,
+        ),
+    ]
+}
+
+/// Name of the `AdminHandler` a route was mounted for, see
+/// `AdminHandler::with_name`
+#[derive(Debug, Clone)]
+pub struct AdminHandlerName(pub String);
+
+/// Caller of an admin route: the principal authenticated by the `AdminAuth`
+/// and the admin handler the route belongs to
+#[derive(Debug, Clone, Default)]
+pub struct AdminCaller {
+    pub principal: Option<AdminPrincipal>,
+    pub handler: Option<AdminHandlerName>,
+}
+
+impl<S> FromRequestParts<S> for AdminCaller
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            principal: parts.extensions.get::<AdminPrincipal>().cloned(),
+            handler: parts.extensions.get::<AdminHandlerName>().cloned(),
+        })
+    }
+}
+
+/// Dead letters matching the filter given in the query string, oldest first
+pub async fn list_dead_letters_handler<S>(
+    State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    Query(filter): Query<DeadLetterFilter>,
+) -> Result<Json<Vec<DeadLetterView>>, ProblemDetails>
+where
+    S: HasAdminAppState,
+{
+    let command = AdminCommands::ListDeadLetters { filter };
+
+    let output = dispatch(&admin_app_state, caller, command).await?;
+
+    match output {
+        AdminCommandsOutput::DeadLetters(dead_letters) => Ok(Json(dead_letters)),
+        _ => Err(unexpected_output()),
+    }
+}
+
+pub async fn get_dead_letter_handler<S>(
+    State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    Path(id): Path<Uuid>,
+) -> Result<Json<DeadLetterView>, ProblemDetails>
+where
+    S: HasAdminAppState,
+{
+    let command = AdminCommands::GetDeadLetter { id };
+
+    let output = dispatch(&admin_app_state, caller, command).await?;
+
+    match output {
+        AdminCommandsOutput::DeadLetter(dead_letter) => Ok(Json(dead_letter)),
+        _ => Err(unexpected_output()),
+    }
+}
+
+/// Counts of the dead letters matching the filter by stream, consumer, event
+/// name, error and hour
+pub async fn dead_letter_stats_handler<S>(
+    State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    Query(filter): Query<DeadLetterFilter>,
+) -> Result<Json<DeadLetterStatsSummary>, ProblemDetails>
+where
+    S: HasAdminAppState,
+{
+    let command = AdminCommands::DeadLetterStats { filter };
+
+    let output = dispatch(&admin_app_state, caller, command).await?;
+
+    match output {
+        AdminCommandsOutput::Stats(stats) => Ok(Json(stats)),
+        _ => Err(unexpected_output()),
+    }
+}
+
+/// Dead letters matching the filter as newline delimited JSON, one
+/// `DeadLetterRecord` per line
+pub async fn export_dead_letters_handler<S>(
+    State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    Query(filter): Query<DeadLetterFilter>,
+) -> Result<([(header::HeaderName, &'static str); 1], String), ProblemDetails>
+where
+    S: HasAdminAppState,
+{
+    let command = AdminCommands::ExportDeadLetters { filter };
+
+    let output = dispatch(&admin_app_state, caller, command).await?;
+
+    let AdminCommandsOutput::Export(records) = output else {
+        return Err(unexpected_output());
+    };
+    let ndjson = DeadLetterRecord::to_ndjson(&records).map_err(|e| {
+        ProblemDetails::internal_server_error(format!("Failed to serialize export: {}", e))
+    })?;
+
+    Ok(([(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)], ndjson))
+}
+
+/// Store the dead letters of an NDJSON export, the ones already present are
+/// skipped
+pub async fn import_dead_letters_handler<S>(
+    State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    body: String,
+) -> Result<Json<ImportSummary>, ProblemDetails>
+where
+    S: HasAdminAppState,
+{
+    let records = DeadLetterRecord::from_ndjson(&body)
+        .map_err(|e| ProblemDetails::validation_error(e.to_string()))?;
+    let command = AdminCommands::ImportDeadLetters { records };
+
+    let output = dispatch(&admin_app_state, caller, command).await?;
+
+    match output {
+        AdminCommandsOutput::Import(summary) => Ok(Json(summary)),
+        _ => Err(unexpected_output()),
+    }
+}
+
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Maximum size of the body of an import, which is parsed in memory. Larger
+/// exports can be split with the filters of the export route.
+pub const IMPORT_BODY_LIMIT: usize = 32 * 1024 * 1024;
+
+/// Start a background job replaying the dead letters of one aggregate
 pub async fn replay_one_handler<S>(
     State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
     Path(aggregate_id): Path<Uuid>,
-) -> Result<Json<ReplaySummary>, ProblemDetails>
+    Query(options): Query<ReplayOptions>,
+) -> Result<(StatusCode, Json<ReplayJob>), ProblemDetails>
+where
+    S: HasAdminAppState,
+{
+    let command = AdminCommands::StartReplayJob {
+        target: ReplayJobTarget::Aggregate { aggregate_id },
+        options,
+    };
+
+    let output = dispatch(&admin_app_state, caller, command).await?;
+
+    Ok((StatusCode::ACCEPTED, Json(replay_job(output)?)))
+}
+
+/// Start a background job replaying the dead letters matching the filter
+/// given in the body, e.g. the ones listed by the dashboard
+pub async fn replay_filtered_handler<S>(
+    State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    Query(options): Query<ReplayOptions>,
+    Json(filter): Json<DeadLetterFilter>,
+) -> Result<(StatusCode, Json<ReplayJob>), ProblemDetails>
 where
     S: HasAdminAppState,
 {
-    let command = AdminCommands::ReplayOneDeadLetter { aggregate_id };
+    if filter.is_empty() {
+        return Err(ProblemDetails::validation_error(
+            "At least one filter field must be set, replay-all replays every dead letter"
+                .to_string(),
+        ));
+    }
+    let command = AdminCommands::StartReplayJob {
+        target: ReplayJobTarget::Filtered { filter },
+        options,
+    };
 
-    let summary = admin_app_state.command_bus.dispatch(command).await?;
+    let output = dispatch(&admin_app_state, caller, command).await?;
 
-    Ok(Json(summary))
+    Ok((StatusCode::ACCEPTED, Json(replay_job(output)?)))
 }
 
+/// Start a background job replaying the dead letters of every aggregate. The
+/// options are a JSON body, which a cross-origin page can not send without a
+/// CORS preflight.
 pub async fn replay_all_handler<S>(
     State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    Json(options): Json<ReplayOptions>,
+) -> Result<(StatusCode, Json<ReplayJob>), ProblemDetails>
+where
+    S: HasAdminAppState,
+{
+    let command = AdminCommands::StartReplayJob {
+        target: ReplayJobTarget::All,
+        options,
+    };
+
+    let output = dispatch(&admin_app_state, caller, command).await?;
+
+    Ok((StatusCode::ACCEPTED, Json(replay_job(output)?)))
+}
+
+/// Replay a corrected version of a dead letter with the other dead letters its
+/// consumer kept for the aggregate, the original is kept in the edit log
+pub async fn edit_and_replay_handler<S>(
+    State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    Path(id): Path<Uuid>,
+    Query(options): Query<ReplayOptions>,
+    Json(edit): Json<DeadLetterEdit>,
 ) -> Result<Json<ReplaySummary>, ProblemDetails>
 where
     S: HasAdminAppState,
 {
-    let command = AdminCommands::ReplayAllDeadLetter {};
+    let command = AdminCommands::EditAndReplayDeadLetter { id, edit, options };
 
-    let summary = admin_app_state.command_bus.dispatch(command).await?;
+    let output = dispatch(&admin_app_state, caller, command).await?;
 
-    Ok(Json(summary))
+    match output {
+        AdminCommandsOutput::Replay(summary) => Ok(Json(summary)),
+        _ => Err(unexpected_output()),
+    }
+}
+
+pub async fn get_replay_job_handler<S>(
+    State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ReplayJob>, ProblemDetails>
+where
+    S: HasAdminAppState,
+{
+    let command = AdminCommands::GetReplayJob { id };
+
+    let output = dispatch(&admin_app_state, caller, command).await?;
+
+    replay_job(output).map(Json)
+}
+
+pub async fn cancel_replay_job_handler<S>(
+    State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ReplayJob>, ProblemDetails>
+where
+    S: HasAdminAppState,
+{
+    let command = AdminCommands::CancelReplayJob { id };
+
+    let output = dispatch(&admin_app_state, caller, command).await?;
+
+    replay_job(output).map(Json)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PurgeOneRequest {
+    pub reason: String,
+}
+
+pub async fn purge_one_handler<S>(
+    State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    Path(id): Path<Uuid>,
+    Json(request): Json<PurgeOneRequest>,
+) -> Result<Json<PurgeSummary>, ProblemDetails>
+where
+    S: HasAdminAppState,
+{
+    let command = AdminCommands::PurgeOneDeadLetter {
+        id,
+        reason: request.reason,
+    };
+
+    let output = dispatch(&admin_app_state, caller, command).await?;
+
+    purge_summary(output).map(Json)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PurgeRequest {
+    #[serde(flatten)]
+    pub filter: DeadLetterFilter,
+    pub reason: String,
+}
+
+pub async fn purge_handler<S>(
+    State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    Json(request): Json<PurgeRequest>,
+) -> Result<Json<PurgeSummary>, ProblemDetails>
+where
+    S: HasAdminAppState,
+{
+    let command = AdminCommands::PurgeDeadLetters {
+        filter: request.filter,
+        reason: request.reason,
+    };
+
+    let output = dispatch(&admin_app_state, caller, command).await?;
+
+    purge_summary(output).map(Json)
+}
+
+/// Admin actions, most recent first, filtered by actor, command, outcome and
+/// time range
+pub async fn list_audit_handler<S>(
+    State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    Query(filter): Query<AuditFilter>,
+) -> Result<Json<Vec<AdminAuditEntry>>, ProblemDetails>
+where
+    S: HasAdminAppState,
+{
+    let command = AdminCommands::ListAuditEntries { filter };
+
+    let output = dispatch(&admin_app_state, caller, command).await?;
+
+    match output {
+        AdminCommandsOutput::AuditEntries(entries) => Ok(Json(entries)),
+        _ => Err(unexpected_output()),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RebuildViewRequest {
+    pub aggregate_id: Option<Uuid>,
+}
+
+/// Rebuild a view registered with `AdminHandler::with_views` from its stream,
+/// only the rows of `aggregate_id` when it is given. The request is a JSON
+/// body, which a cross-origin page can not send without a CORS preflight.
+pub async fn rebuild_view_handler<S>(
+    State(admin_app_state): State<AdminAppState>,
+    caller: AdminCaller,
+    Path(view): Path<String>,
+    Json(request): Json<RebuildViewRequest>,
+) -> Result<Json<RebuildSummary>, ProblemDetails>
+where
+    S: HasAdminAppState,
+{
+    let command = AdminCommands::RebuildView {
+        view,
+        aggregate_id: request.aggregate_id,
+    };
+
+    let output = dispatch(&admin_app_state, caller, command).await?;
+
+    match output {
+        AdminCommandsOutput::Rebuild(summary) => Ok(Json(summary)),
+        _ => Err(unexpected_output()),
+    }
+}
+
+/// Dispatch the command to the command bus of the caller's admin handler,
+/// with the authenticated principal as its actor in the audit log
+async fn dispatch(
+    admin_app_state: &AdminAppState,
+    caller: AdminCaller,
+    command: AdminCommands,
+) -> Result<AdminCommandsOutput, ProblemDetails> {
+    let command_bus = admin_app_state.command_bus(caller.handler.as_ref())?;
+    let actor = caller
+        .principal
+        .map(|principal| principal.subject)
+        .unwrap_or_else(|| ANONYMOUS_ACTOR.to_string());
+
+    Ok(with_actor(actor, command_bus.dispatch(command)).await?)
+}
+
+/// Actor recorded for requests to routes mounted without authentication
+pub const ANONYMOUS_ACTOR: &str = "anonymous";
+
+fn replay_job(output: AdminCommandsOutput) -> Result<ReplayJob, ProblemDetails> {
+    match output {
+        AdminCommandsOutput::ReplayJob(job) => Ok(job),
+        _ => Err(unexpected_output()),
+    }
+}
+
+fn purge_summary(output: AdminCommandsOutput) -> Result<PurgeSummary, ProblemDetails> {
+    match output {
+        AdminCommandsOutput::Purge(summary) => Ok(summary),
+        _ => Err(unexpected_output()),
+    }
+}
+
+fn unexpected_output() -> ProblemDetails {
+    ProblemDetails::internal_server_error("Unexpected admin command output".to_string())
 }
 
 impl From<AdminCommandsError> for ProblemDetails {
@@ -95,13 +679,127 @@ impl From<AdminCommandsError> for ProblemDetails {
                 ReplayDeadLetterError::NotFound => {
                     ProblemDetails::not_found("No dead letter events found".to_string())
                 },
-                ReplayDeadLetterError::NatsJetstream(e) => {
-                    ProblemDetails::internal_server_error(format!("NATS JetStream error: {}", e))
-                },
                 ReplayDeadLetterError::DeadLetterStore(e) => {
                     ProblemDetails::internal_server_error(format!("Dead Letter Store error: {}", e))
                 },
             },
+            AdminCommandsError::EditDeadLetterError(e) => match e {
+                EditDeadLetterError::NotFound => {
+                    ProblemDetails::not_found("No dead letter events found".to_string())
+                },
+                EditDeadLetterError::MissingReason
+                | EditDeadLetterError::EmptyEdit
+                | EditDeadLetterError::MissingAggregateId(_)
+                | EditDeadLetterError::InvalidPayload(_)
+                | EditDeadLetterError::Invalid(_) => {
+                    ProblemDetails::validation_error(e.to_string())
+                },
+                EditDeadLetterError::DeadLetterStore(e) => {
+                    ProblemDetails::internal_server_error(format!("Dead Letter Store error: {}", e))
+                },
+                EditDeadLetterError::EditLog(e) => {
+                    ProblemDetails::internal_server_error(format!("Edit log error: {}", e))
+                },
+                EditDeadLetterError::Headers(e) => {
+                    ProblemDetails::internal_server_error(format!("Edit log error: {}", e))
+                },
+            },
+            AdminCommandsError::PurgeDeadLetterError(e) => match e {
+                PurgeDeadLetterError::NotFound => {
+                    ProblemDetails::not_found("No dead letter events found".to_string())
+                },
+                PurgeDeadLetterError::MissingReason | PurgeDeadLetterError::EmptyFilter => {
+                    ProblemDetails::validation_error(e.to_string())
+                },
+                PurgeDeadLetterError::DeadLetterStore(e) => {
+                    ProblemDetails::internal_server_error(format!("Dead Letter Store error: {}", e))
+                },
+                PurgeDeadLetterError::AuditLog(e) => {
+                    ProblemDetails::internal_server_error(format!("Purge audit log error: {}", e))
+                },
+                PurgeDeadLetterError::Headers(e) => {
+                    ProblemDetails::internal_server_error(format!("Purge audit log error: {}", e))
+                },
+            },
+            AdminCommandsError::InspectDeadLetterError(e) => match e {
+                InspectDeadLetterError::NotFound => {
+                    ProblemDetails::not_found("No dead letter events found".to_string())
+                },
+                InspectDeadLetterError::DeadLetterStore(e) => {
+                    ProblemDetails::internal_server_error(format!("Dead Letter Store error: {}", e))
+                },
+                InspectDeadLetterError::FailureLog(e) => {
+                    ProblemDetails::internal_server_error(format!("Failure log error: {}", e))
+                },
+            },
+            AdminCommandsError::DeadLetterStatsError(e) => match e {
+                DeadLetterStatsError::DeadLetterStore(e) => {
+                    ProblemDetails::internal_server_error(format!("Dead Letter Store error: {}", e))
+                },
+                DeadLetterStatsError::FailureLog(e) => {
+                    ProblemDetails::internal_server_error(format!("Failure log error: {}", e))
+                },
+            },
+            AdminCommandsError::TransferDeadLetterError(e) => match e {
+                TransferDeadLetterError::InvalidLine { .. } => {
+                    ProblemDetails::validation_error(e.to_string())
+                },
+                TransferDeadLetterError::DeadLetterStore(e) => {
+                    ProblemDetails::internal_server_error(format!("Dead Letter Store error: {}", e))
+                },
+            },
+            AdminCommandsError::ReplayJobError(e) => match e {
+                ReplayJobError::NotFound(_) => ProblemDetails::not_found(e.to_string()),
+                ReplayJobError::InvalidStatus(_)
+                | ReplayJobError::DatabaseError(_)
+                | ReplayJobError::SerializationError(_) => {
+                    ProblemDetails::internal_server_error(format!("Replay job error: {}", e))
+                },
+            },
+            AdminCommandsError::RebuildViewError(e) => match e {
+                RebuildViewError::NotRegistered(_) => ProblemDetails::not_found(e.to_string()),
+                RebuildViewError::Reset(_) | RebuildViewError::Stream(_) => {
+                    ProblemDetails::internal_server_error(format!("Rebuild error: {}", e))
+                },
+            },
+            AdminCommandsError::AuditLogError(e) => {
+                ProblemDetails::internal_server_error(format!("Audit log error: {}", e))
+            },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use discern::registry::CommandHandlerRegistry;
+
+    use super::*;
+
+    fn command_bus() -> CommandBus {
+        CommandBus::new(CommandHandlerRegistry::new())
+    }
+
+    #[test]
+    fn named_handler_uses_its_command_bus() {
+        let state =
+            AdminAppState::new(command_bus()).with_named_command_bus("billing", command_bus());
+
+        assert!(state.command_bus(None).is_ok());
+        assert!(state
+            .command_bus(Some(&AdminHandlerName("billing".to_string())))
+            .is_ok());
+    }
+
+    #[test]
+    fn unknown_handler_name_is_not_found() {
+        let state =
+            AdminAppState::new(command_bus()).with_named_command_bus("billing", command_bus());
+
+        let error = state
+            .command_bus(Some(&AdminHandlerName("orders".to_string())))
+            .err()
+            .unwrap();
+
+        assert_eq!(error.status, 404);
+    }
+}
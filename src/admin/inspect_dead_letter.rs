@@ -0,0 +1,145 @@
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use esrc::Envelope;
+use nats_dead_letter::{DeadLetter, DeadLetterStore};
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::admin::filter::DeadLetterFilter;
+use crate::dead_letter::decoder::DeadLetterDecoder;
+use crate::dead_letter::failure::{
+    failure_keys, DeadLetterFailure, DeadLetterFailureLog, FailureKey,
+};
+
+/// Read-only view of a dead letter, with the payload decoded as JSON when
+/// possible so that it can be shown to an operator
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetterView {
+    pub id: Option<Uuid>,
+    pub aggregate_id: Option<Uuid>,
+    pub stream: String,
+    pub consumer: String,
+    pub subject: String,
+    /// Name of the event, `None` when the subject can not be decoded
+    pub event_name: Option<String>,
+    pub event_version: Option<usize>,
+    pub delivery_count: u64,
+    pub stream_sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    pub headers: Option<Value>,
+    /// JSON payload, or the lossy UTF-8 text of a payload that is not JSON
+    pub payload: Value,
+    /// Error of the projector that dead lettered the message, when it was
+    /// recorded, see `Feature::with_failure_log`
+    pub failure: Option<DeadLetterFailure>,
+}
+
+impl From<&DeadLetter> for DeadLetterView {
+    fn from(dead_letter: &DeadLetter) -> Self {
+        let envelope = DeadLetterDecoder::envelope(dead_letter).ok();
+        let payload = DeadLetterDecoder::payload(dead_letter).unwrap_or_else(|_| {
+            Value::String(String::from_utf8_lossy(&dead_letter.payload).into_owned())
+        });
+
+        Self {
+            id: dead_letter.id,
+            aggregate_id: dead_letter.aggregate_id,
+            stream: dead_letter.stream.clone(),
+            consumer: dead_letter.consumer.clone(),
+            subject: dead_letter.subject.clone(),
+            event_name: envelope.as_ref().map(|e| e.name().to_string()),
+            event_version: envelope.as_ref().map(|e| e.version()),
+            delivery_count: dead_letter.delivery_count as u64,
+            stream_sequence: dead_letter.stream_sequence,
+            timestamp: SystemTime::from(dead_letter.timestamp).into(),
+            headers: dead_letter
+                .headers
+                .as_ref()
+                .and_then(|headers| serde_json::to_value(headers).ok()),
+            payload,
+            failure: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct InspectDeadLetter<DLS>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+{
+    dead_letter_store: DLS,
+    failure_log: DeadLetterFailureLog,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InspectDeadLetterError {
+    #[error("No dead letter events found")]
+    NotFound,
+    #[error(transparent)]
+    DeadLetterStore(Box<dyn std::error::Error + Send + Sync>),
+    #[error("Failure log error: {0}")]
+    FailureLog(#[from] sqlx::Error),
+}
+
+impl<DLS> InspectDeadLetter<DLS>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+{
+    pub fn new(dead_letter_store: DLS, failure_log: DeadLetterFailureLog) -> Self {
+        Self {
+            dead_letter_store,
+            failure_log,
+        }
+    }
+
+    /// Dead letters matching the filter, oldest first
+    pub async fn list(
+        &self,
+        filter: &DeadLetterFilter,
+    ) -> Result<Vec<DeadLetterView>, InspectDeadLetterError> {
+        let mut events = self
+            .dead_letter_store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .map_err(|e| InspectDeadLetterError::DeadLetterStore(e.into()))?
+            .into_iter()
+            .filter(|e| filter.matches(e))
+            .collect::<Vec<_>>();
+        events.sort_by_key(|e| e.timestamp);
+
+        self.views(&events).await
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<DeadLetterView, InspectDeadLetterError> {
+        let event = self
+            .dead_letter_store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .map_err(|e| InspectDeadLetterError::DeadLetterStore(e.into()))?
+            .into_iter()
+            .find(|e| e.id == Some(id))
+            .ok_or(InspectDeadLetterError::NotFound)?;
+
+        let mut views = self.views(&[event]).await?;
+        Ok(views.remove(0))
+    }
+
+    /// Views of the dead letters with the failure recorded for their
+    /// consumer and stream sequence, see `FailureKey`
+    async fn views(
+        &self,
+        events: &[DeadLetter],
+    ) -> Result<Vec<DeadLetterView>, InspectDeadLetterError> {
+        let failures = self.failure_log.find(&failure_keys(events)).await?;
+
+        Ok(events
+            .iter()
+            .map(|event| DeadLetterView {
+                failure: failures.get(&FailureKey::of(event)).cloned(),
+                ..DeadLetterView::from(event)
+            })
+            .collect())
+    }
+}
@@ -1,71 +1,498 @@
 use async_nats::jetstream;
 use discern::command::Command;
 use discern::command::CommandHandler;
-use esrc::project::Project;
 use nats_dead_letter::DeadLetterStore;
+use serde::Serialize;
 use uuid::Uuid;
 
-use crate::admin::replay_dead_letter::{ReplayDeadLetter, ReplayDeadLetterError, ReplaySummary};
+use crate::admin::audit::{AdminAuditEntry, AdminAuditLog, AuditFilter, AuditedCommandHandler};
+use crate::admin::auto_retry::{AutoRetryPolicy, AutoRetryScheduler, RetryAttemptLog};
+use crate::admin::dead_letter_stats::{
+    DeadLetterStats, DeadLetterStatsError, DeadLetterStatsSummary,
+};
+use crate::admin::edit_dead_letter::{
+    DeadLetterEdit, DeadLetterEditLog, EditDeadLetter, EditDeadLetterError,
+};
+use crate::admin::filter::DeadLetterFilter;
+use crate::admin::inspect_dead_letter::{
+    DeadLetterView, InspectDeadLetter, InspectDeadLetterError,
+};
+use crate::admin::projector_registry::DeadLetterProjector;
+use crate::admin::purge_dead_letter::{
+    DeadLetterPurgeLog, PurgeDeadLetter, PurgeDeadLetterError, PurgeSummary,
+};
+use crate::admin::rebuild_view::{RebuildSummary, RebuildViewError, ViewRegistry};
+use crate::admin::replay_dead_letter::{
+    ReplayDeadLetter, ReplayDeadLetterError, ReplayOptions, ReplaySummary,
+};
+use crate::admin::replay_jobs::{
+    ReplayJob, ReplayJobError, ReplayJobRunner, ReplayJobStore, ReplayJobTarget,
+};
+use crate::admin::transfer_dead_letter::{
+    DeadLetterRecord, ImportSummary, TransferDeadLetter, TransferDeadLetterError,
+};
+use crate::dead_letter::failure::DeadLetterFailureLog;
 
+pub mod audit;
+pub mod auth;
+pub mod auto_retry;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
+pub mod dead_letter_stats;
+pub mod edit_dead_letter;
+pub mod filter;
 pub mod http;
+pub mod inspect_dead_letter;
+pub mod openapi;
+pub mod projector_registry;
+pub mod purge_dead_letter;
+pub mod rebuild_view;
 pub mod replay_dead_letter;
+pub mod replay_jobs;
+pub mod retention;
+pub mod transfer_dead_letter;
 
 #[derive(Clone)]
 pub struct AdminHandler<DLS, P>
 where
-    DLS: DeadLetterStore + Send + Sync + 'static,
-    P: Project + Send + Sync + 'static,
+    DLS: DeadLetterStore + Clone + Send + Sync + 'static,
+    P: DeadLetterProjector + Clone + 'static,
 {
     dead_letter_replay: ReplayDeadLetter<DLS, P>,
+    dead_letter_purge: PurgeDeadLetter<DLS>,
+    dead_letter_inspect: InspectDeadLetter<DLS>,
+    dead_letter_transfer: TransferDeadLetter<DLS>,
+    dead_letter_stats: DeadLetterStats<DLS>,
+    dead_letter_edit: EditDeadLetter<DLS, P>,
+    purge_log: DeadLetterPurgeLog,
+    edit_log: DeadLetterEditLog,
+    failure_log: DeadLetterFailureLog,
+    retry_attempts: RetryAttemptLog,
+    replay_jobs: ReplayJobRunner<DLS, P>,
+    audit_log: AdminAuditLog,
+    views: ViewRegistry,
+    name: Option<String>,
 }
 
 impl<DLS, P> AdminHandler<DLS, P>
 where
-    DLS: DeadLetterStore + Send + Sync + 'static,
-    P: Project + Send + Sync + 'static,
+    DLS: DeadLetterStore + Clone + Send + Sync + 'static,
+    P: DeadLetterProjector + Clone + 'static,
 {
-    pub fn new(dead_letter_store: DLS, project: P, context: jetstream::Context) -> Self {
-        let dead_letter_replay = ReplayDeadLetter::new(dead_letter_store, project, context);
+    /// Every table of the admin commands lives in `db`, create them with
+    /// `setup`. Optional behaviour is configured with the `with_*` methods,
+    /// so this signature stays the same as the handler grows.
+    pub fn new(
+        dead_letter_store: DLS,
+        project: P,
+        context: jetstream::Context,
+        db: sqlx::PgPool,
+    ) -> Self {
+        let purge_log = DeadLetterPurgeLog::new(db.clone());
+        let dead_letter_purge = PurgeDeadLetter::new(dead_letter_store.clone(), purge_log.clone());
+        let failure_log = DeadLetterFailureLog::new(db.clone());
+        let dead_letter_inspect =
+            InspectDeadLetter::new(dead_letter_store.clone(), failure_log.clone());
+        let dead_letter_transfer = TransferDeadLetter::new(dead_letter_store.clone());
+        let dead_letter_stats =
+            DeadLetterStats::new(dead_letter_store.clone(), failure_log.clone());
+        let dead_letter_replay = ReplayDeadLetter::new(dead_letter_store.clone(), project, context);
+        let edit_log = DeadLetterEditLog::new(db.clone());
+        let dead_letter_edit = EditDeadLetter::new(
+            dead_letter_store,
+            dead_letter_replay.clone(),
+            edit_log.clone(),
+        );
+        let audit_log = AdminAuditLog::new(db.clone());
+        let replay_jobs =
+            ReplayJobRunner::new(dead_letter_replay.clone(), ReplayJobStore::new(db.clone()))
+                .with_audit_log(audit_log.clone());
 
-        Self { dead_letter_replay }
+        Self {
+            dead_letter_replay,
+            dead_letter_purge,
+            dead_letter_inspect,
+            dead_letter_transfer,
+            dead_letter_stats,
+            dead_letter_edit,
+            purge_log,
+            edit_log,
+            failure_log,
+            retry_attempts: RetryAttemptLog::new(db.clone()),
+            replay_jobs,
+            audit_log,
+            views: ViewRegistry::new(),
+            name: None,
+        }
+    }
+
+    /// Name the handler so that several of them can be mounted on the same
+    /// router: its routes are defined under `{endpoint}/admin/{name}` and
+    /// dispatched to the command bus registered under `name` with
+    /// `AdminAppState::with_named_command_bus`
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        let job_store = self.replay_jobs.store().clone().with_handler(name.clone());
+        self.replay_jobs = ReplayJobRunner::new(self.dead_letter_replay.clone(), job_store)
+            .with_audit_log(self.audit_log.clone());
+        self.name = Some(name);
+        self
+    }
+
+    /// Prefix of the subjects dead letters are republished to with
+    /// `ReplayStrategy::Republish`, see `ReplayDeadLetter::redelivery_subject`
+    pub fn with_redelivery_prefix(mut self, prefix: impl Into<String>) -> Self {
+        let dead_letter_replay = self.dead_letter_replay.with_redelivery_prefix(prefix);
+        self.dead_letter_edit = EditDeadLetter::new(
+            dead_letter_replay.dead_letter_store().clone(),
+            dead_letter_replay.clone(),
+            self.edit_log.clone(),
+        );
+        self.replay_jobs =
+            ReplayJobRunner::new(dead_letter_replay.clone(), self.replay_jobs.store().clone())
+                .with_audit_log(self.audit_log.clone());
+        self.dead_letter_replay = dead_letter_replay;
+        self
+    }
+
+    /// Views that can be rebuilt from their stream with
+    /// `AdminCommands::RebuildView`, under the name they are registered with
+    pub fn with_views(mut self, views: ViewRegistry) -> Self {
+        self.views = views;
+        self
+    }
+
+    /// Wrap the handler so that the dispatches changing the dead letters are
+    /// recorded in the audit log, register the result in the
+    /// `CommandHandlerRegistry`
+    pub fn audited(self) -> AuditedCommandHandler<Self> {
+        let audit_log = self.audit_log.clone();
+        AuditedCommandHandler::new(self, audit_log)
+    }
+
+    /// Scheduler retrying the dead letters matching the policy with the
+    /// projectors of this handler, start it with `Feature::start_auto_retry`
+    pub fn auto_retry(&self, policy: AutoRetryPolicy) -> AutoRetryScheduler<DLS, P> {
+        AutoRetryScheduler::new(
+            self.dead_letter_replay.dead_letter_store().clone(),
+            self.dead_letter_replay.clone(),
+            self.failure_log.clone(),
+            self.retry_attempts.clone(),
+            policy,
+        )
+    }
+
+    /// Create the tables used by the admin commands
+    pub async fn setup(&self) -> Result<(), sqlx::Error> {
+        self.purge_log.setup().await?;
+        self.edit_log.setup().await?;
+        self.failure_log.setup().await?;
+        self.retry_attempts.setup().await?;
+        self.replay_jobs.store().setup().await?;
+        self.audit_log.setup().await?;
+        Ok(())
+    }
+
+    /// Restart the replay jobs that were interrupted by a shutdown
+    pub async fn resume_replay_jobs(&self) -> Result<usize, ReplayJobError> {
+        self.replay_jobs.resume().await
     }
 }
 
 #[discern::async_trait]
 impl<DLS, P> CommandHandler<AdminCommands> for AdminHandler<DLS, P>
 where
-    DLS: DeadLetterStore + Send + Sync + 'static,
-    P: Project + Send + Sync + 'static,
+    DLS: DeadLetterStore + Clone + Send + Sync + 'static,
+    P: DeadLetterProjector + Clone + 'static,
 {
-    async fn handle(&self, command: AdminCommands) -> Result<ReplaySummary, AdminCommandsError> {
+    async fn handle(
+        &self,
+        command: AdminCommands,
+    ) -> Result<AdminCommandsOutput, AdminCommandsError> {
         match command {
-            AdminCommands::ReplayOneDeadLetter { aggregate_id } => {
-                let summary = self.dead_letter_replay.replay_one(aggregate_id).await?;
+            AdminCommands::ReplayOneDeadLetter {
+                aggregate_id,
+                options,
+            } => {
+                let summary = self
+                    .dead_letter_replay
+                    .replay_one(aggregate_id, options)
+                    .await?;
+
+                Ok(AdminCommandsOutput::Replay(summary))
+            },
+            AdminCommands::ReplayAllDeadLetter { options } => {
+                let summary = self.dead_letter_replay.replay_all(options).await?;
+
+                Ok(AdminCommandsOutput::Replay(summary))
+            },
+            AdminCommands::EditAndReplayDeadLetter { id, edit, options } => {
+                let summary = self
+                    .dead_letter_edit
+                    .edit_and_replay(id, edit, options)
+                    .await?;
+
+                Ok(AdminCommandsOutput::Replay(summary))
+            },
+            AdminCommands::PurgeOneDeadLetter { id, reason } => {
+                let summary = self.dead_letter_purge.purge_one(id, reason).await?;
+
+                Ok(AdminCommandsOutput::Purge(summary))
+            },
+            AdminCommands::PurgeDeadLetters { filter, reason } => {
+                let summary = self
+                    .dead_letter_purge
+                    .purge_filtered(filter, reason)
+                    .await?;
+
+                Ok(AdminCommandsOutput::Purge(summary))
+            },
+            AdminCommands::ListDeadLetters { filter } => {
+                let dead_letters = self.dead_letter_inspect.list(&filter).await?;
+
+                Ok(AdminCommandsOutput::DeadLetters(dead_letters))
+            },
+            AdminCommands::GetDeadLetter { id } => {
+                let dead_letter = self.dead_letter_inspect.get(id).await?;
+
+                Ok(AdminCommandsOutput::DeadLetter(dead_letter))
+            },
+            AdminCommands::DeadLetterStats { filter } => {
+                let stats = self.dead_letter_stats.summary(&filter).await?;
+
+                Ok(AdminCommandsOutput::Stats(stats))
+            },
+            AdminCommands::ExportDeadLetters { filter } => {
+                let records = self.dead_letter_transfer.export(&filter).await?;
 
-                Ok(summary)
+                Ok(AdminCommandsOutput::Export(records))
             },
-            AdminCommands::ReplayAllDeadLetter {} => {
-                let summary = self.dead_letter_replay.replay_all().await?;
+            AdminCommands::ImportDeadLetters { records } => {
+                let summary = self.dead_letter_transfer.import(records).await?;
 
-                Ok(summary)
+                Ok(AdminCommandsOutput::Import(summary))
+            },
+            AdminCommands::StartReplayJob { target, options } => {
+                let job = self.replay_jobs.start(target, options).await?;
+
+                Ok(AdminCommandsOutput::ReplayJob(job))
+            },
+            AdminCommands::GetReplayJob { id } => {
+                let job = self.replay_jobs.store().get(id).await?;
+
+                Ok(AdminCommandsOutput::ReplayJob(job))
+            },
+            AdminCommands::CancelReplayJob { id } => {
+                let job = self.replay_jobs.cancel(id).await?;
+
+                Ok(AdminCommandsOutput::ReplayJob(job))
+            },
+            AdminCommands::ListAuditEntries { filter } => {
+                let entries = self
+                    .audit_log
+                    .list(&filter)
+                    .await
+                    .map_err(AdminCommandsError::AuditLogError)?;
+
+                Ok(AdminCommandsOutput::AuditEntries(entries))
+            },
+            AdminCommands::RebuildView { view, aggregate_id } => {
+                let summary = self.views.rebuild(&view, aggregate_id).await?;
+
+                Ok(AdminCommandsOutput::Rebuild(summary))
             },
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+#[serde(tag = "command", content = "parameters", rename_all = "kebab-case")]
 pub enum AdminCommands {
-    ReplayOneDeadLetter { aggregate_id: Uuid },
-    ReplayAllDeadLetter {},
+    ReplayOneDeadLetter {
+        aggregate_id: Uuid,
+        options: ReplayOptions,
+    },
+    ReplayAllDeadLetter {
+        options: ReplayOptions,
+    },
+    EditAndReplayDeadLetter {
+        id: Uuid,
+        edit: DeadLetterEdit,
+        options: ReplayOptions,
+    },
+    PurgeOneDeadLetter {
+        id: Uuid,
+        reason: String,
+    },
+    PurgeDeadLetters {
+        filter: DeadLetterFilter,
+        reason: String,
+    },
+    ListDeadLetters {
+        filter: DeadLetterFilter,
+    },
+    GetDeadLetter {
+        id: Uuid,
+    },
+    DeadLetterStats {
+        filter: DeadLetterFilter,
+    },
+    ExportDeadLetters {
+        filter: DeadLetterFilter,
+    },
+    ImportDeadLetters {
+        records: Vec<DeadLetterRecord>,
+    },
+    StartReplayJob {
+        target: ReplayJobTarget,
+        options: ReplayOptions,
+    },
+    GetReplayJob {
+        id: Uuid,
+    },
+    CancelReplayJob {
+        id: Uuid,
+    },
+    ListAuditEntries {
+        filter: AuditFilter,
+    },
+    RebuildView {
+        view: String,
+        aggregate_id: Option<Uuid>,
+    },
+}
+
+impl AdminCommands {
+    /// Name and parameters of the command as recorded in the audit log, or
+    /// `None` for the reads that return no dead letter payload. Reads that
+    /// do, like an export, are recorded since they take data out of the
+    /// store. Dead letters are recorded by ID, never with their payload.
+    pub fn audit_parameters(&self) -> Option<(String, serde_json::Value)> {
+        let parameters = match self {
+            AdminCommands::DeadLetterStats { .. }
+            | AdminCommands::GetReplayJob { .. }
+            | AdminCommands::ListAuditEntries { .. } => return None,
+            AdminCommands::EditAndReplayDeadLetter { id, edit, options } => serde_json::json!({
+                "id": id,
+                "reason": edit.reason,
+                "payload_edited": edit.payload.is_some(),
+                "edited_headers": edit
+                    .headers
+                    .iter()
+                    .flat_map(|headers| headers.keys())
+                    .collect::<Vec<_>>(),
+                "options": options,
+            }),
+            AdminCommands::ImportDeadLetters { records } => serde_json::json!({
+                "total_records": records.len(),
+                "ids": records.iter().filter_map(|record| record.id).collect::<Vec<_>>(),
+            }),
+            _ => match serde_json::to_value(self) {
+                Ok(serde_json::Value::Object(mut command)) => command
+                    .remove("parameters")
+                    .unwrap_or(serde_json::Value::Null),
+                _ => serde_json::Value::Null,
+            },
+        };
+
+        Some((self.name().to_string(), parameters))
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            AdminCommands::ReplayOneDeadLetter { .. } => "replay-one-dead-letter",
+            AdminCommands::ReplayAllDeadLetter { .. } => "replay-all-dead-letter",
+            AdminCommands::EditAndReplayDeadLetter { .. } => "edit-and-replay-dead-letter",
+            AdminCommands::PurgeOneDeadLetter { .. } => "purge-one-dead-letter",
+            AdminCommands::PurgeDeadLetters { .. } => "purge-dead-letters",
+            AdminCommands::ListDeadLetters { .. } => "list-dead-letters",
+            AdminCommands::GetDeadLetter { .. } => "get-dead-letter",
+            AdminCommands::DeadLetterStats { .. } => "dead-letter-stats",
+            AdminCommands::ExportDeadLetters { .. } => "export-dead-letters",
+            AdminCommands::ImportDeadLetters { .. } => "import-dead-letters",
+            AdminCommands::StartReplayJob { .. } => "start-replay-job",
+            AdminCommands::GetReplayJob { .. } => "get-replay-job",
+            AdminCommands::CancelReplayJob { .. } => "cancel-replay-job",
+            AdminCommands::ListAuditEntries { .. } => "list-audit-entries",
+            AdminCommands::RebuildView { .. } => "rebuild-view",
+        }
+    }
+}
+
+/// Result of an `AdminCommands` dispatch, one variant per kind of command
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum AdminCommandsOutput {
+    Replay(ReplaySummary),
+    Purge(PurgeSummary),
+    DeadLetters(Vec<DeadLetterView>),
+    DeadLetter(DeadLetterView),
+    Stats(DeadLetterStatsSummary),
+    Export(Vec<DeadLetterRecord>),
+    Import(ImportSummary),
+    ReplayJob(ReplayJob),
+    AuditEntries(Vec<AdminAuditEntry>),
+    Rebuild(RebuildSummary),
+}
+
+impl AdminCommandsOutput {
+    /// Summary of the output as recorded in the audit log, `None` for the
+    /// outputs of the reads that are not audited. Dead letters read are
+    /// recorded by ID.
+    pub fn audit_result(&self) -> Option<serde_json::Value> {
+        match self {
+            AdminCommandsOutput::Replay(summary) => Some(audit::replay_result(summary)),
+            AdminCommandsOutput::DeadLetters(dead_letters) => Some(serde_json::json!({
+                "total_dead_letters": dead_letters.len(),
+                "ids": dead_letters.iter().filter_map(|view| view.id).collect::<Vec<_>>(),
+            })),
+            AdminCommandsOutput::DeadLetter(dead_letter) => Some(serde_json::json!({
+                "id": dead_letter.id,
+            })),
+            AdminCommandsOutput::Export(records) => Some(serde_json::json!({
+                "total_records": records.len(),
+                "ids": records.iter().filter_map(|record| record.id).collect::<Vec<_>>(),
+            })),
+            AdminCommandsOutput::Purge(summary) => serde_json::to_value(summary).ok(),
+            AdminCommandsOutput::Import(summary) => serde_json::to_value(summary).ok(),
+            AdminCommandsOutput::ReplayJob(job) => Some(serde_json::json!({
+                "job_id": job.id,
+                "status": job.status,
+            })),
+            AdminCommandsOutput::Rebuild(summary) => Some(serde_json::json!({
+                "view": summary.view,
+                "total_events": summary.total_events,
+                "projected": summary.projected,
+                "failed": summary.errors.len(),
+            })),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum AdminCommandsError {
     #[error(transparent)]
     ReplayDeadLetterError(#[from] ReplayDeadLetterError),
+    #[error(transparent)]
+    EditDeadLetterError(#[from] EditDeadLetterError),
+    #[error(transparent)]
+    PurgeDeadLetterError(#[from] PurgeDeadLetterError),
+    #[error(transparent)]
+    InspectDeadLetterError(#[from] InspectDeadLetterError),
+    #[error(transparent)]
+    DeadLetterStatsError(#[from] DeadLetterStatsError),
+    #[error(transparent)]
+    TransferDeadLetterError(#[from] TransferDeadLetterError),
+    #[error(transparent)]
+    ReplayJobError(#[from] ReplayJobError),
+    #[error(transparent)]
+    RebuildViewError(#[from] RebuildViewError),
+    #[error("Audit log error: {0}")]
+    AuditLogError(sqlx::Error),
 }
 
 impl Command for AdminCommands {
-    type Metadata = ReplaySummary;
+    type Metadata = AdminCommandsOutput;
     type Error = AdminCommandsError;
 }
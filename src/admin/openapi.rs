@@ -0,0 +1,1059 @@
+use axum::{routing::get, Json, Router};
+use serde_json::{json, Map, Value};
+
+/// OpenAPI 3.1 document describing the routes of the admin handlers mounted
+/// on an endpoint. Every handler mounted on the router is added with
+/// `with_handler` or `with_authenticated_handler`, so that the document
+/// lists its routes under its name and only asks for a bearer token where
+/// the routes check one.
+#[derive(Debug, Clone)]
+pub struct AdminOpenApi {
+    endpoint: String,
+    handlers: Vec<DocumentedHandler>,
+}
+
+#[derive(Debug, Clone)]
+struct DocumentedHandler {
+    name: Option<String>,
+    authenticated: bool,
+}
+
+impl AdminOpenApi {
+    /// `endpoint` is the server URL, the one given to
+    /// `AdminHandler::setup_router`
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Routes of a handler mounted with `AdminHandler::setup_router`, `name`
+    /// is the one given to `AdminHandler::with_name`
+    pub fn with_handler(mut self, name: Option<&str>) -> Self {
+        self.handlers.push(DocumentedHandler {
+            name: name.map(str::to_string),
+            authenticated: false,
+        });
+        self
+    }
+
+    /// Routes of a handler mounted with
+    /// `AdminHandler::setup_router_with_auth`
+    pub fn with_authenticated_handler(mut self, name: Option<&str>) -> Self {
+        self.handlers.push(DocumentedHandler {
+            name: name.map(str::to_string),
+            authenticated: true,
+        });
+        self
+    }
+
+    pub fn document(&self) -> Value {
+        let mut paths = Map::new();
+        for handler in &self.handlers {
+            let prefix = match &handler.name {
+                Some(name) => format!("/admin/{}", name),
+                None => "/admin".to_string(),
+            };
+            let Value::Object(operations) = operations() else {
+                unreachable!("the operations are a JSON object");
+            };
+
+            for (path, mut item) in operations {
+                for operation in item
+                    .as_object_mut()
+                    .into_iter()
+                    .flat_map(|item| item.values_mut())
+                {
+                    handler.document(operation);
+                }
+                paths.insert(format!("{}{}", prefix, path), item);
+            }
+        }
+
+        json!({
+            "openapi": "3.1.0",
+            "info": {
+                "title": "esrc-ext admin API",
+                "version": env!("CARGO_PKG_VERSION"),
+                "description": "Dead letter replay, purge and audit administration"
+            },
+            "servers": [{ "url": self.endpoint }],
+            "paths": paths,
+            "components": {
+                "securitySchemes": {
+                    "bearerAuth": { "type": "http", "scheme": "bearer" }
+                },
+                "schemas": schemas()
+            }
+        })
+    }
+}
+
+impl DocumentedHandler {
+    fn document(&self, operation: &mut Value) {
+        if let Some(name) = &self.name {
+            // Operation IDs are unique across the document
+            let id = operation["operationId"].as_str().unwrap_or_default();
+            operation["operationId"] = json!(format!("{}_{}", name, id));
+        }
+
+        if self.authenticated {
+            operation["security"] = json!([{ "bearerAuth": [] }]);
+        } else if let Some(responses) = operation["responses"].as_object_mut() {
+            responses.remove("401");
+            responses.remove("403");
+        }
+    }
+}
+
+/// Operations of the routes of an admin handler, keyed by their path
+/// relative to the admin path of the handler
+fn operations() -> Value {
+    let problem = |description: &str| {
+        json!({
+            "description": description,
+            "content": {
+                "application/problem+json": {
+                    "schema": { "$ref": "#/components/schemas/ProblemDetails" }
+                }
+            }
+        })
+    };
+    let json_body = |schema: &str| {
+        json!({
+            "content": {
+                "application/json": {
+                    "schema": { "$ref": format!("#/components/schemas/{}", schema) }
+                }
+            }
+        })
+    };
+    // OpenAPI 3.1 has no schema for the items of a stream, newline
+    // delimited JSON is described as an opaque body
+    let ndjson = json!({ "type": "string", "format": "binary" });
+    let ok = |description: &str, schema: &str| {
+        let mut response = json_body(schema);
+        response["description"] = json!(description);
+        response
+    };
+    let uuid_path = |name: &str, description: &str| {
+        json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "description": description,
+            "schema": { "type": "string", "format": "uuid" }
+        })
+    };
+    let replay_query = json!([
+        {
+            "name": "policy",
+            "in": "query",
+            "schema": { "$ref": "#/components/schemas/ReplayPolicy" }
+        },
+        {
+            "name": "concurrency",
+            "in": "query",
+            "description": "Maximum number of aggregates replayed at the same time",
+            "schema": { "type": "integer", "minimum": 1, "default": 1 }
+        },
+        {
+            "name": "strategy",
+            "in": "query",
+            "schema": { "$ref": "#/components/schemas/ReplayStrategy" }
+        }
+    ]);
+    let mut replay_one_parameters = vec![uuid_path("aggregate_id", "Aggregate to replay")];
+    replay_one_parameters.extend(replay_query.as_array().cloned().unwrap_or_default());
+    let filter_query = json!([
+        { "name": "stream", "in": "query", "schema": { "type": "string" } },
+        { "name": "consumer", "in": "query", "schema": { "type": "string" } },
+        {
+            "name": "aggregate_id",
+            "in": "query",
+            "schema": { "type": "string", "format": "uuid" }
+        },
+        { "name": "subject", "in": "query", "schema": { "type": "string" } }
+    ]);
+    let mut edit_parameters = vec![uuid_path("id", "Dead letter to correct")];
+    edit_parameters.extend(replay_query.as_array().cloned().unwrap_or_default());
+
+    json!({
+        "/dead-letters": {
+            "get": {
+                "operationId": "listDeadLetters",
+                "summary": "Dead letters matching a filter, oldest first",
+                "tags": ["inspect"],
+                "parameters": filter_query,
+                "responses": {
+                    "200": {
+                        "description": "Dead letters",
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "array",
+                                    "items": { "$ref": "#/components/schemas/DeadLetterView" }
+                                }
+                            }
+                        }
+                    },
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The inspect permission is required"),
+                    "500": problem("Internal error")
+                }
+            }
+        },
+        "/dead-letters/stats": {
+            "get": {
+                "operationId": "deadLetterStats",
+                "summary": "Counts of the dead letters matching a filter by stream, consumer, event name, error and hour",
+                "tags": ["inspect"],
+                "parameters": filter_query,
+                "responses": {
+                    "200": ok("Dead letter statistics", "DeadLetterStatsSummary"),
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The inspect permission is required"),
+                    "500": problem("Internal error")
+                }
+            }
+        },
+        "/dead-letters/export": {
+            "get": {
+                "operationId": "exportDeadLetters",
+                "summary": "Dead letters matching a filter as newline delimited JSON",
+                "tags": ["inspect"],
+                "parameters": filter_query,
+                "responses": {
+                    "200": {
+                        "description": "One DeadLetterRecord per line",
+                        "content": {
+                            "application/x-ndjson": { "schema": ndjson }
+                        }
+                    },
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The inspect permission is required"),
+                    "500": problem("Internal error")
+                }
+            }
+        },
+        "/dead-letters/import": {
+            "post": {
+                "operationId": "importDeadLetters",
+                "summary": "Store the dead letters of an export, skipping the ones already present",
+                "tags": ["import"],
+                "requestBody": {
+                    "description": "One DeadLetterRecord per line, as exported",
+                    "required": true,
+                    "content": {
+                        "application/x-ndjson": { "schema": ndjson }
+                    }
+                },
+                "responses": {
+                    "200": ok("Dead letters imported", "ImportSummary"),
+                    "400": problem("Invalid record"),
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The import permission is required"),
+                    "413": { "description": "The body is larger than 32 MiB" },
+                    "500": problem("Internal error")
+                }
+            }
+        },
+        "/dead-letters/replay/{aggregate_id}": {
+            "patch": {
+                "operationId": "replayAggregate",
+                "summary": "Start a job replaying the dead letters of one aggregate",
+                "tags": ["replay"],
+                "parameters": replay_one_parameters,
+                "responses": {
+                    "202": ok("Replay job started", "ReplayJob"),
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The replay permission is required"),
+                    "500": problem("Internal error")
+                }
+            }
+        },
+        "/dead-letters/replay": {
+            "post": {
+                "operationId": "replayFiltered",
+                "summary": "Start a job replaying the dead letters matching a filter",
+                "tags": ["replay"],
+                "parameters": replay_query,
+                "requestBody": json_body("DeadLetterFilter"),
+                "responses": {
+                    "202": ok("Replay job started", "ReplayJob"),
+                    "400": problem("Empty filter"),
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The replay permission is required"),
+                    "500": problem("Internal error")
+                }
+            }
+        },
+        "/dead-letters/replay-all": {
+            "post": {
+                "operationId": "replayAll",
+                "summary": "Start a job replaying the dead letters of every aggregate",
+                "tags": ["replay"],
+                "requestBody": json_body("ReplayOptions"),
+                "responses": {
+                    "202": ok("Replay job started", "ReplayJob"),
+                    "415": { "description": "The body is not JSON" },
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The replay permission is required"),
+                    "500": problem("Internal error")
+                }
+            }
+        },
+        "/dead-letters/{id}/edit-and-replay": {
+            "post": {
+                "operationId": "editAndReplayDeadLetter",
+                "summary": "Replay a corrected version of a dead letter, the original is kept in the edit log",
+                "description": "The other dead letters its consumer kept for the aggregate are replayed with it, in stream order",
+                "tags": ["replay"],
+                "parameters": edit_parameters,
+                "requestBody": json_body("DeadLetterEdit"),
+                "responses": {
+                    "200": ok("Edited dead letter replayed", "ReplaySummary"),
+                    "400": problem("Missing reason, empty edit or payload not matching the event group"),
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The replay permission is required"),
+                    "404": problem("Dead letter not found"),
+                    "500": problem("Internal error")
+                }
+            }
+        },
+        "/dead-letters/purge": {
+            "post": {
+                "operationId": "purgeDeadLetters",
+                "summary": "Discard the dead letters matching a filter",
+                "tags": ["purge"],
+                "requestBody": json_body("PurgeRequest"),
+                "responses": {
+                    "200": ok("Dead letters discarded", "PurgeSummary"),
+                    "400": problem("Missing reason or empty filter"),
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The purge permission is required"),
+                    "404": problem("No dead letter matches the filter"),
+                    "500": problem("Internal error")
+                }
+            }
+        },
+        "/dead-letters/{id}": {
+            "get": {
+                "operationId": "getDeadLetter",
+                "summary": "Inspect one dead letter",
+                "tags": ["inspect"],
+                "parameters": [uuid_path("id", "Dead letter to inspect")],
+                "responses": {
+                    "200": ok("Dead letter", "DeadLetterView"),
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The inspect permission is required"),
+                    "404": problem("Dead letter not found"),
+                    "500": problem("Internal error")
+                }
+            },
+            "delete": {
+                "operationId": "purgeDeadLetter",
+                "summary": "Discard one dead letter",
+                "tags": ["purge"],
+                "parameters": [uuid_path("id", "Dead letter to discard")],
+                "requestBody": json_body("PurgeOneRequest"),
+                "responses": {
+                    "200": ok("Dead letter discarded", "PurgeSummary"),
+                    "400": problem("Missing reason"),
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The purge permission is required"),
+                    "404": problem("Dead letter not found"),
+                    "500": problem("Internal error")
+                }
+            }
+        },
+        "/replay-jobs/{id}": {
+            "get": {
+                "operationId": "getReplayJob",
+                "summary": "Progress and summary of a replay job",
+                "tags": ["replay"],
+                "parameters": [uuid_path("id", "Replay job")],
+                "responses": {
+                    "200": ok("Replay job", "ReplayJob"),
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The replay permission is required"),
+                    "404": problem("Replay job not found"),
+                    "500": problem("Internal error")
+                }
+            }
+        },
+        "/replay-jobs/{id}/cancel": {
+            "post": {
+                "operationId": "cancelReplayJob",
+                "summary": "Cancel a replay job",
+                "tags": ["replay"],
+                "parameters": [uuid_path("id", "Replay job")],
+                "responses": {
+                    "200": ok("Cancellation requested", "ReplayJob"),
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The replay permission is required"),
+                    "404": problem("Replay job not found"),
+                    "500": problem("Internal error")
+                }
+            }
+        },
+        "/audit": {
+            "get": {
+                "operationId": "listAuditEntries",
+                "summary": "Admin actions, most recent first",
+                "tags": ["audit"],
+                "parameters": [
+                    { "name": "actor", "in": "query", "schema": { "type": "string" } },
+                    { "name": "command", "in": "query", "schema": { "type": "string" } },
+                    {
+                        "name": "outcome",
+                        "in": "query",
+                        "schema": { "$ref": "#/components/schemas/AuditOutcome" }
+                    },
+                    {
+                        "name": "from",
+                        "in": "query",
+                        "schema": { "type": "string", "format": "date-time" }
+                    },
+                    {
+                        "name": "to",
+                        "in": "query",
+                        "schema": { "type": "string", "format": "date-time" }
+                    },
+                    {
+                        "name": "limit",
+                        "in": "query",
+                        "schema": { "type": "integer", "default": 100 }
+                    },
+                    {
+                        "name": "offset",
+                        "in": "query",
+                        "schema": { "type": "integer", "default": 0 }
+                    }
+                ],
+                "responses": {
+                    "200": {
+                        "description": "Audit entries",
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "array",
+                                    "items": { "$ref": "#/components/schemas/AdminAuditEntry" }
+                                }
+                            }
+                        }
+                    },
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The audit permission is required"),
+                    "500": problem("Internal error")
+                }
+            }
+        },
+        "/views/{view}/rebuild": {
+            "post": {
+                "operationId": "rebuildView",
+                "summary": "Rebuild a view from the events of its stream",
+                "tags": ["rebuild"],
+                "parameters": [
+                    {
+                        "name": "view",
+                        "in": "path",
+                        "required": true,
+                        "description": "Name the view is registered under",
+                        "schema": { "type": "string" }
+                    }
+                ],
+                "requestBody": json_body("RebuildViewRequest"),
+                "responses": {
+                    "200": ok("View rebuilt", "RebuildSummary"),
+                    "415": { "description": "The body is not JSON" },
+                    "401": problem("Missing or invalid credentials"),
+                    "403": problem("The rebuild permission is required"),
+                    "404": problem("No view registered under this name"),
+                    "500": problem("Internal error")
+                }
+            }
+        }
+    })
+}
+
+fn schemas() -> Value {
+    let uuid = json!({ "type": "string", "format": "uuid" });
+    let date_time = json!({ "type": "string", "format": "date-time" });
+    let stats_buckets = json!({
+        "type": "array",
+        "items": { "$ref": "#/components/schemas/DeadLetterStatsBucket" }
+    });
+    let string_enum = |values: &[&str]| json!({ "type": "string", "enum": values });
+
+    json!({
+        "ProblemDetails": {
+            "type": "object",
+            "description": "RFC 7807 problem details",
+            "required": ["type", "title", "status"],
+            "properties": {
+                "type": { "type": "string" },
+                "title": { "type": "string" },
+                "status": { "type": "integer" },
+                "detail": { "type": "string" },
+                "instance": { "type": "string" }
+            },
+            "additionalProperties": true
+        },
+        "DeadLetterView": {
+            "type": "object",
+            "required": [
+                "stream",
+                "consumer",
+                "subject",
+                "delivery_count",
+                "stream_sequence",
+                "timestamp",
+                "payload"
+            ],
+            "properties": {
+                "id": { "type": ["string", "null"], "format": "uuid" },
+                "aggregate_id": { "type": ["string", "null"], "format": "uuid" },
+                "stream": { "type": "string" },
+                "consumer": { "type": "string" },
+                "subject": { "type": "string" },
+                "event_name": { "type": ["string", "null"] },
+                "event_version": { "type": ["integer", "null"] },
+                "delivery_count": { "type": "integer" },
+                "stream_sequence": { "type": "integer" },
+                "timestamp": date_time,
+                "headers": { "type": ["object", "null"] },
+                "payload": {},
+                "failure": {
+                    "oneOf": [
+                        { "$ref": "#/components/schemas/DeadLetterFailure" },
+                        { "type": "null" }
+                    ]
+                }
+            }
+        },
+        "DeadLetterFailure": {
+            "type": "object",
+            "required": [
+                "consumer",
+                "stream_sequence",
+                "aggregate_id",
+                "error",
+                "attempts",
+                "first_failed_at",
+                "last_failed_at"
+            ],
+            "properties": {
+                "consumer": { "type": "string" },
+                "stream_sequence": { "type": "integer" },
+                "aggregate_id": uuid,
+                "error": {
+                    "type": "string",
+                    "description": "Display text of the projector error, or the panic message when error_type is null"
+                },
+                "error_type": {
+                    "type": ["string", "null"],
+                    "description": "Rust type of the projector error, null for a panic"
+                },
+                "attempts": { "type": "integer" },
+                "first_failed_at": date_time,
+                "last_failed_at": date_time
+            }
+        },
+        "DeadLetterRecord": {
+            "type": "object",
+            "required": [
+                "stream",
+                "consumer",
+                "subject",
+                "stream_sequence",
+                "delivery_count",
+                "timestamp",
+                "payload"
+            ],
+            "properties": {
+                "id": { "type": ["string", "null"], "format": "uuid" },
+                "aggregate_id": { "type": ["string", "null"], "format": "uuid" },
+                "prefix": { "type": ["string", "null"] },
+                "stream": { "type": "string" },
+                "consumer": { "type": "string" },
+                "subject": { "type": "string" },
+                "stream_sequence": { "type": "integer" },
+                "delivery_count": { "type": "integer" },
+                "timestamp": date_time,
+                "headers": {
+                    "type": ["object", "null"],
+                    "description": "Every value of each header",
+                    "additionalProperties": { "type": "array", "items": { "type": "string" } }
+                },
+                "payload": { "type": "string", "contentEncoding": "base64" }
+            }
+        },
+        "DeadLetterStatsBucket": {
+            "type": "object",
+            "required": ["key", "count", "oldest", "newest"],
+            "properties": {
+                "key": { "type": "string" },
+                "count": { "type": "integer" },
+                "oldest": date_time,
+                "newest": date_time
+            }
+        },
+        "DeadLetterStatsSummary": {
+            "type": "object",
+            "required": [
+                "total",
+                "by_stream",
+                "by_consumer",
+                "by_event_name",
+                "by_error",
+                "by_error_type",
+                "by_hour"
+            ],
+            "properties": {
+                "total": { "type": "integer" },
+                "by_stream": stats_buckets,
+                "by_consumer": stats_buckets,
+                "by_event_name": stats_buckets,
+                "by_error": stats_buckets,
+                "by_error_type": stats_buckets,
+                "by_hour": stats_buckets
+            }
+        },
+        "ImportSummary": {
+            "type": "object",
+            "required": ["total_records", "imported", "skipped", "errors"],
+            "properties": {
+                "total_records": { "type": "integer" },
+                "imported": { "type": "array", "items": uuid },
+                "skipped": { "type": "array", "items": uuid },
+                "errors": { "type": "array", "items": { "type": "string" } }
+            }
+        },
+        "ReplayPolicy": string_enum(&["continue-on-failure", "halt-aggregate-on-failure"]),
+        "ReplayStrategy": string_enum(&["in-process", "republish"]),
+        "ReplayOutcome": string_enum(&["replayed", "republished", "failed", "skipped"]),
+        "ReplayErrorKind": string_enum(&[
+            "no-projector",
+            "decode",
+            "projection",
+            "publish",
+            "removal"
+        ]),
+        "ReplayOptions": {
+            "type": "object",
+            "properties": {
+                "policy": { "$ref": "#/components/schemas/ReplayPolicy" },
+                "concurrency": { "type": "integer", "minimum": 1 },
+                "strategy": { "$ref": "#/components/schemas/ReplayStrategy" }
+            }
+        },
+        "ReplayEventResult": {
+            "type": "object",
+            "required": ["aggregate_id", "stream_sequence", "outcome", "removed"],
+            "properties": {
+                "dead_letter_id": { "type": ["string", "null"], "format": "uuid" },
+                "aggregate_id": uuid,
+                "stream_sequence": { "type": "integer" },
+                "outcome": { "$ref": "#/components/schemas/ReplayOutcome" },
+                "error_kind": { "$ref": "#/components/schemas/ReplayErrorKind" },
+                "error_message": { "type": "string" },
+                "removed": { "type": "boolean" }
+            }
+        },
+        "ReplaySummary": {
+            "type": "object",
+            "required": [
+                "total_events",
+                "successful_replays",
+                "failed_replays",
+                "skipped_replays",
+                "processed_aggregates",
+                "results",
+                "cancelled",
+                "started_at",
+                "finished_at",
+                "duration_ms"
+            ],
+            "properties": {
+                "total_events": { "type": "integer" },
+                "successful_replays": { "type": "integer" },
+                "failed_replays": { "type": "integer" },
+                "skipped_replays": { "type": "integer" },
+                "processed_aggregates": { "type": "array", "items": uuid },
+                "results": {
+                    "type": "array",
+                    "items": { "$ref": "#/components/schemas/ReplayEventResult" }
+                },
+                "cancelled": { "type": "boolean" },
+                "started_at": date_time,
+                "finished_at": date_time,
+                "duration_ms": { "type": "integer" }
+            }
+        },
+        "ReplayJobStatus": string_enum(&["pending", "running", "completed", "failed", "cancelled"]),
+        "ReplayJobTarget": {
+            "oneOf": [
+                {
+                    "type": "object",
+                    "required": ["type", "aggregate_id"],
+                    "properties": {
+                        "type": { "const": "aggregate" },
+                        "aggregate_id": uuid
+                    }
+                },
+                {
+                    "type": "object",
+                    "required": ["type", "filter"],
+                    "properties": {
+                        "type": { "const": "filtered" },
+                        "filter": { "$ref": "#/components/schemas/DeadLetterFilter" }
+                    }
+                },
+                {
+                    "type": "object",
+                    "required": ["type"],
+                    "properties": { "type": { "const": "all" } }
+                }
+            ]
+        },
+        "ReplayJob": {
+            "type": "object",
+            "required": [
+                "id",
+                "target",
+                "options",
+                "status",
+                "total_aggregates",
+                "completed_aggregates",
+                "successful_replays",
+                "failed_replays",
+                "skipped_replays",
+                "cancel_requested",
+                "created_at",
+                "updated_at"
+            ],
+            "properties": {
+                "id": uuid,
+                "target": { "$ref": "#/components/schemas/ReplayJobTarget" },
+                "options": { "$ref": "#/components/schemas/ReplayOptions" },
+                "status": { "$ref": "#/components/schemas/ReplayJobStatus" },
+                "total_aggregates": { "type": "integer" },
+                "completed_aggregates": { "type": "integer" },
+                "successful_replays": { "type": "integer" },
+                "failed_replays": { "type": "integer" },
+                "skipped_replays": { "type": "integer" },
+                "cancel_requested": { "type": "boolean" },
+                "summary": { "$ref": "#/components/schemas/ReplaySummary" },
+                "error": { "type": "string" },
+                "audit_entry_id": uuid,
+                "created_at": date_time,
+                "updated_at": date_time
+            }
+        },
+        "RebuildViewRequest": {
+            "type": "object",
+            "properties": {
+                "aggregate_id": {
+                    "type": "string",
+                    "format": "uuid",
+                    "description": "Only rebuild the rows of this aggregate"
+                }
+            }
+        },
+        "RebuildSummary": {
+            "type": "object",
+            "required": [
+                "view",
+                "total_events",
+                "projected",
+                "errors",
+                "started_at",
+                "finished_at",
+                "duration_ms"
+            ],
+            "properties": {
+                "view": { "type": "string" },
+                "aggregate_id": uuid,
+                "total_events": { "type": "integer" },
+                "projected": { "type": "integer" },
+                "errors": { "type": "array", "items": { "type": "string" } },
+                "started_at": date_time,
+                "finished_at": date_time,
+                "duration_ms": { "type": "integer" }
+            }
+        },
+        "DeadLetterEdit": {
+            "type": "object",
+            "required": ["reason"],
+            "description": "At least one of payload and headers must be set",
+            "properties": {
+                "payload": { "description": "Replaces the payload of the dead letter" },
+                "headers": {
+                    "type": "object",
+                    "description": "Added to the headers of the dead letter",
+                    "additionalProperties": { "type": "string" }
+                },
+                "reason": { "type": "string", "minLength": 1 }
+            }
+        },
+        "PurgeOneRequest": {
+            "type": "object",
+            "required": ["reason"],
+            "properties": {
+                "reason": { "type": "string", "minLength": 1 }
+            }
+        },
+        "DeadLetterFilter": {
+            "type": "object",
+            "description": "At least one field must be set",
+            "properties": {
+                "stream": { "type": "string" },
+                "consumer": { "type": "string" },
+                "aggregate_id": uuid,
+                "subject": { "type": "string" }
+            }
+        },
+        "PurgeRequest": {
+            "type": "object",
+            "required": ["reason"],
+            "description": "At least one filter field must be set",
+            "properties": {
+                "reason": { "type": "string", "minLength": 1 },
+                "stream": { "type": "string" },
+                "consumer": { "type": "string" },
+                "aggregate_id": uuid,
+                "subject": { "type": "string" }
+            }
+        },
+        "PurgeOutcome": string_enum(&["purged", "failed", "skipped"]),
+        "PurgeErrorKind": string_enum(&["audit-log", "removal"]),
+        "PurgeEventResult": {
+            "type": "object",
+            "required": ["stream_sequence", "outcome"],
+            "properties": {
+                "dead_letter_id": { "type": ["string", "null"], "format": "uuid" },
+                "aggregate_id": { "type": ["string", "null"], "format": "uuid" },
+                "stream_sequence": { "type": "integer" },
+                "outcome": { "$ref": "#/components/schemas/PurgeOutcome" },
+                "error_kind": { "$ref": "#/components/schemas/PurgeErrorKind" },
+                "error_message": { "type": "string" }
+            }
+        },
+        "PurgeSummary": {
+            "type": "object",
+            "required": ["reason", "total_events", "purged", "failed", "skipped", "results"],
+            "properties": {
+                "reason": { "type": "string" },
+                "total_events": { "type": "integer" },
+                "purged": { "type": "integer" },
+                "failed": { "type": "integer" },
+                "skipped": { "type": "integer" },
+                "results": {
+                    "type": "array",
+                    "items": { "$ref": "#/components/schemas/PurgeEventResult" }
+                }
+            }
+        },
+        "AuditOutcome": string_enum(&["pending", "success", "failure", "cancelled"]),
+        "AdminAuditEntry": {
+            "type": "object",
+            "required": [
+                "id",
+                "actor",
+                "command",
+                "parameters",
+                "outcome",
+                "duration_ms",
+                "created_at"
+            ],
+            "properties": {
+                "id": uuid,
+                "actor": { "type": "string" },
+                "command": { "type": "string" },
+                "parameters": {},
+                "outcome": { "$ref": "#/components/schemas/AuditOutcome" },
+                "result": {},
+                "error": { "type": "string" },
+                "duration_ms": { "type": "integer" },
+                "created_at": date_time
+            }
+        }
+    })
+}
+
+/// Serve the admin OpenAPI document at `{endpoint}/admin/openapi.json`
+pub fn setup_router<S>(router: &mut Router<S>, openapi: &AdminOpenApi)
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let document = openapi.document();
+    let admin_path = format!("{}/admin", openapi.endpoint);
+
+    let new_router = std::mem::take(router).route(
+        &format!("{}/openapi.json", admin_path),
+        get(move || {
+            let document = document.clone();
+            async move { Json(document) }
+        }),
+    );
+
+    *router = new_router;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::admin::http::{admin_routes, AdminAppState, HasAdminAppState};
+    use crate::admin::replay_dead_letter::{
+        ReplayErrorKind, ReplayEventResult, ReplayOutcome, ReplaySummary,
+    };
+
+    /// State type of the routes, never built
+    #[derive(Clone)]
+    #[allow(dead_code)]
+    struct TestState(AdminAppState);
+
+    impl HasAdminAppState for TestState {
+        fn admin_state(&self) -> &AdminAppState {
+            &self.0
+        }
+    }
+
+    /// Method and path of every route `AdminHandler::setup_router` mounts
+    /// under `prefix`
+    fn mounted(prefix: &str) -> BTreeSet<(String, String)> {
+        admin_routes::<TestState>()
+            .into_iter()
+            .map(|route| {
+                (
+                    route.method.as_str().to_lowercase(),
+                    format!("{}{}", prefix, route.path),
+                )
+            })
+            .collect()
+    }
+
+    fn documented(document: &Value) -> BTreeSet<(String, String)> {
+        document["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| {
+                item.as_object()
+                    .unwrap()
+                    .keys()
+                    .map(move |method| (method.clone(), path.clone()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_mounted_route_is_documented() {
+        let document = AdminOpenApi::new("/api/v1")
+            .with_handler(None)
+            .with_authenticated_handler(Some("billing"))
+            .document();
+
+        let mut expected = mounted("/admin");
+        expected.extend(mounted("/admin/billing"));
+        assert_eq!(documented(&document), expected);
+    }
+
+    #[test]
+    fn operation_ids_are_unique() {
+        let document = AdminOpenApi::new("/api/v1")
+            .with_handler(None)
+            .with_handler(Some("billing"))
+            .document();
+
+        let ids = document["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .flat_map(|item| item.as_object().unwrap().values())
+            .map(|operation| operation["operationId"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(ids.len(), ids.iter().collect::<BTreeSet<_>>().len());
+    }
+
+    #[test]
+    fn only_authenticated_routes_require_a_bearer_token() {
+        let document = AdminOpenApi::new("/api/v1")
+            .with_handler(None)
+            .with_authenticated_handler(Some("billing"))
+            .document();
+
+        assert!(document.get("security").is_none());
+        let open = &document["paths"]["/admin/dead-letters"]["get"];
+        assert!(open.get("security").is_none());
+        assert!(open["responses"].get("401").is_none());
+        let guarded = &document["paths"]["/admin/billing/dead-letters"]["get"];
+        assert_eq!(guarded["security"], json!([{ "bearerAuth": [] }]));
+        assert!(guarded["responses"].get("401").is_some());
+    }
+
+    fn keys(value: &Value) -> BTreeSet<String> {
+        value.as_object().unwrap().keys().cloned().collect()
+    }
+
+    #[test]
+    fn replay_summary_schema_matches_the_serialized_summary() {
+        let now = chrono::Utc::now();
+        let aggregate_id = uuid::Uuid::now_v7();
+        let summary = serde_json::to_value(ReplaySummary {
+            total_events: 1,
+            successful_replays: 0,
+            failed_replays: 1,
+            skipped_replays: 0,
+            processed_aggregates: vec![aggregate_id],
+            results: vec![ReplayEventResult {
+                dead_letter_id: Some(uuid::Uuid::now_v7()),
+                aggregate_id,
+                stream_sequence: 1,
+                outcome: ReplayOutcome::Failed,
+                error_kind: Some(ReplayErrorKind::Projection),
+                error_message: Some("projection failed".to_string()),
+                removed: false,
+            }],
+            cancelled: false,
+            started_at: now,
+            finished_at: now,
+            duration_ms: 0,
+        })
+        .unwrap();
+        let schemas = schemas();
+
+        for (schema, value) in [
+            (&schemas["ReplaySummary"], &summary),
+            (&schemas["ReplayEventResult"], &summary["results"][0]),
+        ] {
+            assert_eq!(keys(&schema["properties"]), keys(value));
+            for required in schema["required"].as_array().unwrap() {
+                assert!(value.get(required.as_str().unwrap()).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn document_is_openapi_3_1() {
+        let document = AdminOpenApi::new("/api/v1").with_handler(None).document();
+
+        assert_eq!(document["openapi"], json!("3.1.0"));
+        assert_eq!(
+            document["paths"]["/admin/dead-letters/export"]["get"]["responses"]["200"]["content"]
+                ["application/x-ndjson"]["schema"],
+            json!({ "type": "string", "format": "binary" })
+        );
+    }
+
+    #[test]
+    fn server_url_is_the_endpoint() {
+        let document = AdminOpenApi::new("/api/v1/").with_handler(None).document();
+
+        assert_eq!(document["servers"], json!([{ "url": "/api/v1" }]));
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use esrc::project::Project;
+use futures::future::BoxFuture;
+use nats_dead_letter::DeadLetter;
+
+use crate::dead_letter::decoder::{DeadLetterDecodeError, DeadLetterDecoder};
+use crate::dead_letter::envelope::DeadLetterEnvelope;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProjectorError {
+    #[error("No projector registered for stream {stream} and consumer {consumer}")]
+    NotRegistered { stream: String, consumer: String },
+    #[error(transparent)]
+    Decode(#[from] DeadLetterDecodeError),
+    #[error("{0}")]
+    Projection(String),
+}
+
+/// Projects a dead letter envelope, object safe so different projections can
+/// be stored side by side in a `ProjectorRegistry`
+pub trait DeadLetterProjector: Send + Sync {
+    fn project_dead_letter<'a>(
+        &'a self,
+        dead_letter: &'a DeadLetter,
+        envelope: &'a DeadLetterEnvelope,
+    ) -> BoxFuture<'a, Result<(), ProjectorError>>;
+
+    /// Check that the envelope deserializes into the event group of the
+    /// projector, without projecting it
+    fn validate_dead_letter(
+        &self,
+        dead_letter: &DeadLetter,
+        envelope: &DeadLetterEnvelope,
+    ) -> Result<(), ProjectorError>;
+}
+
+impl<P> DeadLetterProjector for P
+where
+    P: Project + Send + Sync + 'static,
+{
+    fn project_dead_letter<'a>(
+        &'a self,
+        _dead_letter: &'a DeadLetter,
+        envelope: &'a DeadLetterEnvelope,
+    ) -> BoxFuture<'a, Result<(), ProjectorError>> {
+        Box::pin(async move {
+            let context = DeadLetterDecoder::context::<P::EventGroup>(envelope)?;
+
+            let mut project = self.clone();
+            project
+                .project(context)
+                .await
+                .map_err(|e| ProjectorError::Projection(e.to_string()))
+        })
+    }
+
+    fn validate_dead_letter(
+        &self,
+        _dead_letter: &DeadLetter,
+        envelope: &DeadLetterEnvelope,
+    ) -> Result<(), ProjectorError> {
+        DeadLetterDecoder::context::<P::EventGroup>(envelope)?;
+        Ok(())
+    }
+}
+
+/// Maps the (stream, consumer) pair of the consumer that dead lettered an
+/// event to the projector able to replay it
+#[derive(Clone, Default)]
+pub struct ProjectorRegistry {
+    projectors: HashMap<(String, String), Arc<dyn DeadLetterProjector>>,
+}
+
+impl ProjectorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the project consuming `stream` through the durable consumer
+    /// `consumer`, usually the feature name given to `Feature`
+    pub fn register<P>(&mut self, stream: &str, consumer: &str, project: P) -> &mut Self
+    where
+        P: Project + Send + Sync + 'static,
+    {
+        self.projectors.insert(
+            (stream.to_string(), consumer.to_string()),
+            Arc::new(project),
+        );
+        self
+    }
+
+    pub fn get(&self, stream: &str, consumer: &str) -> Option<&Arc<dyn DeadLetterProjector>> {
+        self.projectors
+            .get(&(stream.to_string(), consumer.to_string()))
+    }
+}
+
+impl DeadLetterProjector for ProjectorRegistry {
+    fn project_dead_letter<'a>(
+        &'a self,
+        dead_letter: &'a DeadLetter,
+        envelope: &'a DeadLetterEnvelope,
+    ) -> BoxFuture<'a, Result<(), ProjectorError>> {
+        match self.get(&dead_letter.stream, &dead_letter.consumer) {
+            Some(projector) => projector.project_dead_letter(dead_letter, envelope),
+            None => Box::pin(futures::future::ready(Err(ProjectorError::NotRegistered {
+                stream: dead_letter.stream.clone(),
+                consumer: dead_letter.consumer.clone(),
+            }))),
+        }
+    }
+
+    fn validate_dead_letter(
+        &self,
+        dead_letter: &DeadLetter,
+        envelope: &DeadLetterEnvelope,
+    ) -> Result<(), ProjectorError> {
+        match self.get(&dead_letter.stream, &dead_letter.consumer) {
+            Some(projector) => projector.validate_dead_letter(dead_letter, envelope),
+            None => Err(ProjectorError::NotRegistered {
+                stream: dead_letter.stream.clone(),
+                consumer: dead_letter.consumer.clone(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use esrc::{
+        project::Context,
+        version::{DeserializeVersion, SerializeVersion},
+        Envelope, Event,
+    };
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::testing::TestEnvelope;
+
+    #[derive(Event, Serialize, Deserialize, Debug, Clone, SerializeVersion, DeserializeVersion)]
+    #[esrc(event(name = "User"))]
+    enum UserEvent {
+        Created { name: String },
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("Rejected {0}")]
+    struct Rejected(String);
+
+    /// Records the names it projects and rejects "Eve"
+    #[derive(Clone, Default)]
+    struct UserProject {
+        projected: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Project for UserProject {
+        type EventGroup = UserEvent;
+        type Error = Rejected;
+
+        async fn project<'de, E: Envelope>(
+            &mut self,
+            context: Context<'de, E, Self::EventGroup>,
+        ) -> Result<(), Self::Error> {
+            let UserEvent::Created { name } = (*context).clone();
+            if name == "Eve" {
+                return Err(Rejected(name));
+            }
+            self.projected.lock().unwrap().push(name);
+            Ok(())
+        }
+    }
+
+    fn dead_letter(consumer: &str, name: &str) -> (DeadLetter, DeadLetterEnvelope) {
+        let dead_letter = TestEnvelope::new(&UserEvent::Created {
+            name: name.to_string(),
+        })
+        .dead_letter("users", consumer);
+        let envelope = DeadLetterEnvelope::try_from_dead_letter(&dead_letter).unwrap();
+        (dead_letter, envelope)
+    }
+
+    #[tokio::test]
+    async fn dispatches_to_the_projector_of_the_stream_and_consumer() {
+        let users = UserProject::default();
+        let audit = UserProject::default();
+        let mut registry = ProjectorRegistry::new();
+        registry
+            .register("users", "user-projector", users.clone())
+            .register("users", "user-audit", audit.clone());
+        let (dead_letter, envelope) = dead_letter("user-projector", "Ada");
+
+        registry
+            .project_dead_letter(&dead_letter, &envelope)
+            .await
+            .unwrap();
+
+        assert_eq!(*users.projected.lock().unwrap(), ["Ada"]);
+        assert!(audit.projected.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn projection_errors_are_reported() {
+        let mut registry = ProjectorRegistry::new();
+        registry.register("users", "user-projector", UserProject::default());
+        let (dead_letter, envelope) = dead_letter("user-projector", "Eve");
+
+        assert!(matches!(
+            registry.project_dead_letter(&dead_letter, &envelope).await,
+            Err(ProjectorError::Projection(message)) if message == "Rejected Eve"
+        ));
+    }
+
+    #[tokio::test]
+    async fn unregistered_consumer_is_not_projected() {
+        let mut registry = ProjectorRegistry::new();
+        registry.register("users", "user-projector", UserProject::default());
+        let (dead_letter, envelope) = dead_letter("billing", "Ada");
+
+        assert!(matches!(
+            registry.project_dead_letter(&dead_letter, &envelope).await,
+            Err(ProjectorError::NotRegistered { stream, consumer })
+                if stream == "users" && consumer == "billing"
+        ));
+        assert!(matches!(
+            registry.validate_dead_letter(&dead_letter, &envelope),
+            Err(ProjectorError::NotRegistered { stream, consumer })
+                if stream == "users" && consumer == "billing"
+        ));
+    }
+
+    #[test]
+    fn validate_decodes_the_event_without_projecting_it() {
+        let users = UserProject::default();
+        let mut registry = ProjectorRegistry::new();
+        registry.register("users", "user-projector", users.clone());
+        let (mut dead_letter, envelope) = dead_letter("user-projector", "Ada");
+
+        registry
+            .validate_dead_letter(&dead_letter, &envelope)
+            .unwrap();
+        assert!(users.projected.lock().unwrap().is_empty());
+
+        dead_letter.payload = br#"{"Deleted":{}}"#.to_vec();
+        let envelope = DeadLetterEnvelope::try_from_dead_letter(&dead_letter).unwrap();
+        assert!(matches!(
+            registry.validate_dead_letter(&dead_letter, &envelope),
+            Err(ProjectorError::Decode(_))
+        ));
+    }
+}
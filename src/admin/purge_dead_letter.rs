@@ -0,0 +1,608 @@
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use nats_dead_letter::{DeadLetter, DeadLetterStore};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::admin::filter::DeadLetterFilter;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PurgeSummary {
+    pub reason: String,
+    pub total_events: usize,
+    pub purged: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    /// Outcome of every dead letter matched by the purge
+    pub results: Vec<PurgeEventResult>,
+}
+
+impl PurgeSummary {
+    fn new(reason: String, total_events: usize) -> Self {
+        Self {
+            reason,
+            total_events,
+            purged: 0,
+            failed: 0,
+            skipped: 0,
+            results: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, result: PurgeEventResult) {
+        match result.outcome {
+            PurgeOutcome::Purged => self.purged += 1,
+            PurgeOutcome::Failed => self.failed += 1,
+            PurgeOutcome::Skipped => self.skipped += 1,
+        }
+        self.results.push(result);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PurgeEventResult {
+    pub dead_letter_id: Option<Uuid>,
+    pub aggregate_id: Option<Uuid>,
+    pub stream_sequence: u64,
+    pub outcome: PurgeOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<PurgeErrorKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+}
+
+impl PurgeEventResult {
+    fn new(event: &DeadLetter, outcome: PurgeOutcome) -> Self {
+        Self {
+            dead_letter_id: event.id,
+            aggregate_id: event.aggregate_id,
+            stream_sequence: event.stream_sequence,
+            outcome,
+            error_kind: None,
+            error_message: None,
+        }
+    }
+
+    fn with_error(mut self, kind: PurgeErrorKind, message: String) -> Self {
+        self.error_kind = Some(kind);
+        self.error_message = Some(message);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PurgeOutcome {
+    Purged,
+    /// Left in the dead letter store, see the error of the result
+    Failed,
+    /// Left in the dead letter store because it has no ID to remove it by
+    Skipped,
+}
+
+impl PurgeOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PurgeOutcome::Purged => "purged",
+            PurgeOutcome::Failed => "failed",
+            PurgeOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PurgeErrorKind {
+    /// The purge could not be recorded, the dead letter was not removed
+    AuditLog,
+    /// The dead letter could not be removed from the store
+    Removal,
+}
+
+/// Audit trail of a `PurgeDeadLetter`, object safe so that tests can keep it
+/// in memory. A purge is recorded as pending before the dead letter is
+/// removed and completed with the outcome of the removal, so a failure in
+/// between never drops an event without a trace.
+pub trait PurgeLog: Send + Sync {
+    /// Record a pending purge, returns the ID of the record to complete
+    fn record<'a>(
+        &'a self,
+        dead_letter_id: Uuid,
+        dead_letter: &'a DeadLetter,
+        reason: &'a str,
+    ) -> BoxFuture<'a, Result<Uuid, PurgeDeadLetterError>>;
+
+    /// Outcome of the removal of a pending purge
+    fn complete<'a>(
+        &'a self,
+        id: Uuid,
+        outcome: PurgeOutcome,
+        error: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<(), PurgeDeadLetterError>>;
+}
+
+/// Audit trail of the dead letters that were discarded, so that it is
+/// possible to explain later why an event was dropped.
+#[derive(Clone)]
+pub struct DeadLetterPurgeLog {
+    db: sqlx::PgPool,
+}
+
+impl DeadLetterPurgeLog {
+    pub fn new(db: sqlx::PgPool) -> Self {
+        Self { db }
+    }
+
+    pub async fn setup(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS dead_letter_purges(
+                id uuid                         NOT NULL,
+                dead_letter_id uuid             NOT NULL,
+                aggregate_id uuid,
+                stream text                     NOT NULL,
+                consumer text                   NOT NULL,
+                subject text                    NOT NULL,
+                stream_sequence bigint          NOT NULL,
+                headers jsonb,
+                payload bytea                   NOT NULL,
+                reason text                     NOT NULL,
+                outcome text                    NOT NULL DEFAULT 'pending',
+                error text,
+                purged_at timestamptz           NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (id)
+            );",
+        )
+        .execute(&self.db)
+        .await?;
+        sqlx::query(
+            "ALTER TABLE dead_letter_purges
+                ADD COLUMN IF NOT EXISTS outcome text NOT NULL DEFAULT 'pending',
+                ADD COLUMN IF NOT EXISTS error text;",
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Keep a full copy of the dead letter together with the reason it is
+    /// discarded, returns the ID of the pending record
+    pub async fn record(
+        &self,
+        dead_letter_id: Uuid,
+        dead_letter: &DeadLetter,
+        reason: &str,
+    ) -> Result<Uuid, PurgeDeadLetterError> {
+        let headers = dead_letter
+            .headers
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()?;
+        let id = Uuid::now_v7();
+
+        sqlx::query(
+            "INSERT INTO dead_letter_purges
+                (id, dead_letter_id, aggregate_id, stream, consumer, subject, stream_sequence, headers, payload, reason)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        )
+        .bind(id)
+        .bind(dead_letter_id)
+        .bind(dead_letter.aggregate_id)
+        .bind(&dead_letter.stream)
+        .bind(&dead_letter.consumer)
+        .bind(&dead_letter.subject)
+        .bind(dead_letter.stream_sequence as i64)
+        .bind(headers)
+        .bind(&dead_letter.payload)
+        .bind(reason)
+        .execute(&self.db)
+        .await?;
+        Ok(id)
+    }
+
+    /// Outcome of the removal of a pending purge
+    pub async fn complete(
+        &self,
+        id: Uuid,
+        outcome: PurgeOutcome,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE dead_letter_purges SET outcome = $2, error = $3
+                WHERE id = $1 AND outcome = 'pending'",
+        )
+        .bind(id)
+        .bind(outcome.as_str())
+        .bind(error)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+}
+
+impl PurgeLog for DeadLetterPurgeLog {
+    fn record<'a>(
+        &'a self,
+        dead_letter_id: Uuid,
+        dead_letter: &'a DeadLetter,
+        reason: &'a str,
+    ) -> BoxFuture<'a, Result<Uuid, PurgeDeadLetterError>> {
+        Box::pin(DeadLetterPurgeLog::record(
+            self,
+            dead_letter_id,
+            dead_letter,
+            reason,
+        ))
+    }
+
+    fn complete<'a>(
+        &'a self,
+        id: Uuid,
+        outcome: PurgeOutcome,
+        error: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<(), PurgeDeadLetterError>> {
+        Box::pin(async move {
+            DeadLetterPurgeLog::complete(self, id, outcome, error).await?;
+            Ok(())
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct PurgeDeadLetter<DLS>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+{
+    dead_letter_store: DLS,
+    purge_log: Arc<dyn PurgeLog>,
+}
+
+impl<DLS> PurgeDeadLetter<DLS>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+{
+    pub fn new(dead_letter_store: DLS, purge_log: impl PurgeLog + 'static) -> Self {
+        Self {
+            dead_letter_store,
+            purge_log: Arc::new(purge_log),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PurgeDeadLetterError {
+    #[error("No dead letter events found")]
+    NotFound,
+    #[error("A reason is required to purge dead letters")]
+    MissingReason,
+    #[error("A filter is required to purge dead letters in bulk")]
+    EmptyFilter,
+    #[error(transparent)]
+    DeadLetterStore(Box<dyn std::error::Error + Send + Sync>),
+    #[error(transparent)]
+    AuditLog(#[from] sqlx::Error),
+    #[error("Failed to serialize the headers of the dead letter: {0}")]
+    Headers(#[from] serde_json::Error),
+}
+
+impl<DLS> PurgeDeadLetter<DLS>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+{
+    /// Discard a single dead letter by its ID
+    pub async fn purge_one(
+        &self,
+        id: Uuid,
+        reason: String,
+    ) -> Result<PurgeSummary, PurgeDeadLetterError> {
+        if reason.trim().is_empty() {
+            return Err(PurgeDeadLetterError::MissingReason);
+        }
+
+        let events = self
+            .dead_letter_store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .map_err(|e| PurgeDeadLetterError::DeadLetterStore(e.into()))?;
+
+        let event = events
+            .into_iter()
+            .find(|e| e.id == Some(id))
+            .ok_or(PurgeDeadLetterError::NotFound)?;
+
+        let record_id = self.purge_log.record(id, &event, &reason).await?;
+        let removed = self
+            .dead_letter_store
+            .remove_dead_letter(&id.to_string())
+            .await
+            .map_err(|e| PurgeDeadLetterError::DeadLetterStore(e.into()));
+        self.complete(record_id, id, removed.as_ref().err()).await;
+        removed?;
+
+        let mut summary = PurgeSummary::new(reason, 1);
+        summary.push(PurgeEventResult::new(&event, PurgeOutcome::Purged));
+        Ok(summary)
+    }
+
+    /// Discard every dead letter matching the filter
+    pub async fn purge_filtered(
+        &self,
+        filter: DeadLetterFilter,
+        reason: String,
+    ) -> Result<PurgeSummary, PurgeDeadLetterError> {
+        if reason.trim().is_empty() {
+            return Err(PurgeDeadLetterError::MissingReason);
+        }
+        if filter.is_empty() {
+            return Err(PurgeDeadLetterError::EmptyFilter);
+        }
+
+        let events = self
+            .dead_letter_store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .map_err(|e| PurgeDeadLetterError::DeadLetterStore(e.into()))?
+            .into_iter()
+            .filter(|e| filter.matches(e))
+            .collect::<Vec<_>>();
+
+        if events.is_empty() {
+            return Err(PurgeDeadLetterError::NotFound);
+        }
+
+        let mut summary = PurgeSummary::new(reason, events.len());
+
+        for event in events {
+            let Some(id) = event.id else {
+                summary.push(PurgeEventResult::new(&event, PurgeOutcome::Skipped));
+                continue;
+            };
+
+            let record_id = match self.purge_log.record(id, &event, &summary.reason).await {
+                Ok(record_id) => record_id,
+                Err(e) => {
+                    summary.push(
+                        PurgeEventResult::new(&event, PurgeOutcome::Failed).with_error(
+                            PurgeErrorKind::AuditLog,
+                            format!("Failed to record purge of dead letter {}: {}", id, e),
+                        ),
+                    );
+                    continue;
+                },
+            };
+
+            let removed = self
+                .dead_letter_store
+                .remove_dead_letter(&id.to_string())
+                .await
+                .map_err(|e| PurgeDeadLetterError::DeadLetterStore(e.into()));
+            self.complete(record_id, id, removed.as_ref().err()).await;
+            summary.push(match removed {
+                Ok(_) => PurgeEventResult::new(&event, PurgeOutcome::Purged),
+                Err(e) => PurgeEventResult::new(&event, PurgeOutcome::Failed).with_error(
+                    PurgeErrorKind::Removal,
+                    format!("Failed to remove dead letter {}: {}", id, e),
+                ),
+            });
+        }
+
+        Ok(summary)
+    }
+
+    /// Complete the record of a purge with the outcome of the removal. The
+    /// record stays pending when this fails, which is logged since the dead
+    /// letter itself was handled.
+    async fn complete(
+        &self,
+        record_id: Uuid,
+        dead_letter_id: Uuid,
+        error: Option<&PurgeDeadLetterError>,
+    ) {
+        let (outcome, error) = match error {
+            None => (PurgeOutcome::Purged, None),
+            Some(e) => (PurgeOutcome::Failed, Some(e.to_string())),
+        };
+        if let Err(e) = self
+            .purge_log
+            .complete(record_id, outcome, error.as_deref())
+            .await
+        {
+            tracing::warn!(%dead_letter_id, "Failed to complete purge record: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use esrc::{
+        version::{DeserializeVersion, SerializeVersion},
+        Event,
+    };
+
+    use super::*;
+    use crate::dead_letter::memory::InMemoryDeadLetterStore;
+    use crate::testing::TestEnvelope;
+
+    #[derive(Event, Serialize, Deserialize, Debug, Clone, SerializeVersion, DeserializeVersion)]
+    #[esrc(event(name = "User"))]
+    enum UserEvent {
+        Created { name: String },
+    }
+
+    #[derive(Debug, Clone)]
+    struct PurgeRecord {
+        id: Uuid,
+        dead_letter_id: Uuid,
+        reason: String,
+        outcome: Option<PurgeOutcome>,
+    }
+
+    #[derive(Clone, Default)]
+    struct InMemoryPurgeLog {
+        records: Arc<Mutex<Vec<PurgeRecord>>>,
+    }
+
+    impl InMemoryPurgeLog {
+        fn records(&self) -> Vec<PurgeRecord> {
+            self.records.lock().unwrap().clone()
+        }
+    }
+
+    impl PurgeLog for InMemoryPurgeLog {
+        fn record<'a>(
+            &'a self,
+            dead_letter_id: Uuid,
+            _dead_letter: &'a DeadLetter,
+            reason: &'a str,
+        ) -> BoxFuture<'a, Result<Uuid, PurgeDeadLetterError>> {
+            Box::pin(async move {
+                let id = Uuid::now_v7();
+                self.records.lock().unwrap().push(PurgeRecord {
+                    id,
+                    dead_letter_id,
+                    reason: reason.to_string(),
+                    outcome: None,
+                });
+                Ok(id)
+            })
+        }
+
+        fn complete<'a>(
+            &'a self,
+            id: Uuid,
+            outcome: PurgeOutcome,
+            _error: Option<&'a str>,
+        ) -> BoxFuture<'a, Result<(), PurgeDeadLetterError>> {
+            Box::pin(async move {
+                for record in self.records.lock().unwrap().iter_mut() {
+                    if record.id == id {
+                        record.outcome = Some(outcome);
+                    }
+                }
+                Ok(())
+            })
+        }
+    }
+
+    fn dead_letter(consumer: &str) -> DeadLetter {
+        TestEnvelope::new(&UserEvent::Created {
+            name: "Ada".to_string(),
+        })
+        .dead_letter("users", consumer)
+    }
+
+    fn purge(
+        store: &InMemoryDeadLetterStore,
+    ) -> (PurgeDeadLetter<InMemoryDeadLetterStore>, InMemoryPurgeLog) {
+        let purge_log = InMemoryPurgeLog::default();
+        (
+            PurgeDeadLetter::new(store.clone(), purge_log.clone()),
+            purge_log,
+        )
+    }
+
+    #[tokio::test]
+    async fn purge_requires_a_reason() {
+        let store = InMemoryDeadLetterStore::new();
+        let id = store.insert(dead_letter("user-projector"));
+        let (purge, purge_log) = purge(&store);
+
+        let error = purge.purge_one(id, "  ".to_string()).await.unwrap_err();
+
+        assert!(matches!(error, PurgeDeadLetterError::MissingReason));
+        assert_eq!(store.len(), 1);
+        assert!(purge_log.records().is_empty());
+    }
+
+    #[tokio::test]
+    async fn bulk_purge_requires_a_filter() {
+        let store = InMemoryDeadLetterStore::with_dead_letters([dead_letter("user-projector")]);
+        let (purge, _) = purge(&store);
+
+        let error = purge
+            .purge_filtered(DeadLetterFilter::default(), "obsolete".to_string())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, PurgeDeadLetterError::EmptyFilter));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn purge_of_an_unknown_id_is_not_found() {
+        let store = InMemoryDeadLetterStore::with_dead_letters([dead_letter("user-projector")]);
+        let (purge, _) = purge(&store);
+
+        let error = purge
+            .purge_one(Uuid::now_v7(), "obsolete".to_string())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, PurgeDeadLetterError::NotFound));
+    }
+
+    #[tokio::test]
+    async fn purge_records_the_dead_letter_it_removes() {
+        let store = InMemoryDeadLetterStore::new();
+        let id = store.insert(dead_letter("user-projector"));
+        let (purge, purge_log) = purge(&store);
+
+        let summary = purge.purge_one(id, "obsolete".to_string()).await.unwrap();
+
+        assert_eq!(summary.purged, 1);
+        assert_eq!(summary.results[0].dead_letter_id, Some(id));
+        assert!(store.is_empty());
+        let records = purge_log.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].dead_letter_id, id);
+        assert_eq!(records[0].reason, "obsolete");
+        assert_eq!(records[0].outcome, Some(PurgeOutcome::Purged));
+    }
+
+    #[tokio::test]
+    async fn failed_removal_is_recorded_as_failed() {
+        let store = InMemoryDeadLetterStore::new();
+        let id = store.insert(dead_letter("user-projector"));
+        store.fail_on_remove(true);
+        let (purge, purge_log) = purge(&store);
+
+        let error = purge
+            .purge_one(id, "obsolete".to_string())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, PurgeDeadLetterError::DeadLetterStore(_)));
+        assert_eq!(store.len(), 1);
+        assert_eq!(purge_log.records()[0].outcome, Some(PurgeOutcome::Failed));
+    }
+
+    #[tokio::test]
+    async fn bulk_purge_reports_each_dead_letter() {
+        let store = InMemoryDeadLetterStore::with_dead_letters([
+            dead_letter("user-projector"),
+            dead_letter("user-projector"),
+            dead_letter("user-mailer"),
+        ]);
+        let (purge, purge_log) = purge(&store);
+        let filter = DeadLetterFilter {
+            consumer: Some("user-projector".to_string()),
+            ..DeadLetterFilter::default()
+        };
+
+        let summary = purge
+            .purge_filtered(filter, "obsolete".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(summary.total_events, 2);
+        assert_eq!(summary.purged, 2);
+        assert!(summary
+            .results
+            .iter()
+            .all(|result| result.outcome == PurgeOutcome::Purged));
+        assert_eq!(store.dead_letters()[0].consumer, "user-mailer");
+        assert_eq!(purge_log.records().len(), 2);
+    }
+}
@@ -0,0 +1,489 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_nats::jetstream::{
+    self,
+    consumer::{pull::OrderedConfig, DeliverPolicy},
+};
+use chrono::{DateTime, Utc};
+use esrc::event::event_model::view::View;
+use futures::{future::BoxFuture, StreamExt};
+use nats_dead_letter::DeadLetter;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::admin::projector_registry::DeadLetterProjector;
+use crate::dead_letter::decoder::DeadLetterDecoder;
+use crate::postgres::PgViewProjector;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RebuildSummary {
+    pub view: String,
+    /// Only the rows of this aggregate were rebuilt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aggregate_id: Option<Uuid>,
+    pub total_events: usize,
+    pub projected: usize,
+    /// Events the projector failed on, as `{subject} ({stream sequence}): {error}`
+    pub errors: Vec<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration_ms: u64,
+}
+
+impl RebuildSummary {
+    fn new(view: &str, aggregate_id: Option<Uuid>) -> Self {
+        let now = Utc::now();
+        Self {
+            view: view.to_string(),
+            aggregate_id,
+            total_events: 0,
+            projected: 0,
+            errors: Vec::new(),
+            started_at: now,
+            finished_at: now,
+            duration_ms: 0,
+        }
+    }
+
+    fn finish(&mut self) {
+        self.finished_at = Utc::now();
+        self.duration_ms = (self.finished_at - self.started_at)
+            .num_milliseconds()
+            .max(0) as u64;
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RebuildViewError {
+    #[error("No view registered under the name {0}")]
+    NotRegistered(String),
+    #[error("Failed to reset the view: {0}")]
+    Reset(Box<dyn std::error::Error + Send + Sync>),
+    #[error("Failed to read the stream: {0}")]
+    Stream(String),
+}
+
+/// Rebuilds a view from the events of its stream, object safe so the views of
+/// an application can be stored side by side in a `ViewRegistry`
+pub trait ViewRebuilder: Send + Sync {
+    /// Rebuild the rows of `summary.aggregate_id`, or the whole view, and
+    /// count the projected events in the summary
+    fn rebuild<'a>(
+        &'a self,
+        summary: &'a mut RebuildSummary,
+    ) -> BoxFuture<'a, Result<(), RebuildViewError>>;
+}
+
+/// Removes the rows of a view before it is rebuilt
+pub trait ResetView: Send + Sync {
+    /// Remove the rows of `aggregate_id`, or every row when it is `None`
+    fn reset_view(
+        &self,
+        aggregate_id: Option<Uuid>,
+    ) -> BoxFuture<'_, Result<(), Box<dyn std::error::Error + Send + Sync>>>;
+}
+
+impl<V> ResetView for PgViewProjector<V>
+where
+    V: View + Send + Sync,
+{
+    fn reset_view(
+        &self,
+        aggregate_id: Option<Uuid>,
+    ) -> BoxFuture<'_, Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+        Box::pin(async move {
+            match aggregate_id {
+                Some(id) => self.delete_one(id).await?,
+                None => self.delete().await?,
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Rebuilds a view by projecting every event of its stream again, read with an
+/// ordered consumer from the first message published under `prefix`. The
+/// durable consumer of the view is left untouched.
+///
+/// Without `with_reset` the events are projected on top of the existing rows,
+/// which is only correct for projectors that overwrite them.
+#[derive(Clone)]
+pub struct StreamViewRebuilder<P> {
+    context: jetstream::Context,
+    stream: String,
+    prefix: String,
+    project: P,
+    reset: Option<Arc<dyn ResetView>>,
+}
+
+impl<P> StreamViewRebuilder<P>
+where
+    P: DeadLetterProjector,
+{
+    /// `prefix` is the one the events of the stream are published under, as
+    /// `{prefix}.{event name}.{aggregate id}`, and `project` the projector of
+    /// the view
+    pub fn new(
+        context: jetstream::Context,
+        stream: impl Into<String>,
+        prefix: impl Into<String>,
+        project: P,
+    ) -> Self {
+        Self {
+            context,
+            stream: stream.into(),
+            prefix: prefix.into(),
+            project,
+            reset: None,
+        }
+    }
+
+    pub fn with_reset<R>(mut self, reset: R) -> Self
+    where
+        R: ResetView + 'static,
+    {
+        self.reset = Some(Arc::new(reset));
+        self
+    }
+
+    /// The message decoded like a dead letter, which keeps the same fields
+    fn dead_letter(&self, message: &jetstream::Message) -> Result<DeadLetter, RebuildViewError> {
+        let info = message
+            .info()
+            .map_err(|e| RebuildViewError::Stream(e.to_string()))?;
+        let headers = message
+            .headers
+            .iter()
+            .flat_map(|headers| headers.iter())
+            .filter_map(|(name, values)| {
+                Some((name.to_string(), values.last()?.as_str().to_string()))
+            })
+            .collect::<Vec<_>>();
+
+        Ok(DeadLetter {
+            id: None,
+            aggregate_id: None,
+            prefix: Some(self.prefix.clone()),
+            stream: self.stream.clone(),
+            consumer: info.consumer.to_string(),
+            subject: message.subject.to_string(),
+            stream_sequence: info.stream_sequence,
+            delivery_count: 1,
+            timestamp: SystemTime::from(info.published).into(),
+            headers: Some(headers.into_iter().collect()),
+            payload: message.payload.to_vec(),
+        })
+    }
+
+    /// Subjects read by the ordered consumer, the events of one aggregate or
+    /// of the whole stream
+    fn filter_subject(&self, aggregate_id: Option<Uuid>) -> String {
+        match aggregate_id {
+            Some(id) => format!("{}.*.{}", self.prefix, id),
+            None => format!("{}.>", self.prefix),
+        }
+    }
+
+    /// Project one event of the stream, counted in the summary as projected
+    /// or as an error
+    async fn project(&self, dead_letter: &DeadLetter, summary: &mut RebuildSummary) {
+        summary.total_events += 1;
+
+        let projected = match DeadLetterDecoder::envelope(dead_letter) {
+            Ok(envelope) => self
+                .project
+                .project_dead_letter(dead_letter, &envelope)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match projected {
+            Ok(()) => summary.projected += 1,
+            Err(e) => summary.errors.push(format!(
+                "{} ({}): {}",
+                dead_letter.subject, dead_letter.stream_sequence, e
+            )),
+        }
+    }
+}
+
+impl<P> ViewRebuilder for StreamViewRebuilder<P>
+where
+    P: DeadLetterProjector,
+{
+    fn rebuild<'a>(
+        &'a self,
+        summary: &'a mut RebuildSummary,
+    ) -> BoxFuture<'a, Result<(), RebuildViewError>> {
+        Box::pin(async move {
+            let stream_error = |e: &dyn std::fmt::Display| RebuildViewError::Stream(e.to_string());
+
+            if let Some(reset) = &self.reset {
+                reset
+                    .reset_view(summary.aggregate_id)
+                    .await
+                    .map_err(RebuildViewError::Reset)?;
+            }
+
+            let filter_subject = self.filter_subject(summary.aggregate_id);
+            let mut consumer = self
+                .context
+                .get_stream(&self.stream)
+                .await
+                .map_err(|e| stream_error(&e))?
+                .create_consumer(OrderedConfig {
+                    filter_subject,
+                    deliver_policy: DeliverPolicy::All,
+                    ..Default::default()
+                })
+                .await
+                .map_err(|e| stream_error(&e))?;
+            // Events published during the rebuild are projected by the
+            // durable consumer of the view
+            let mut pending = consumer
+                .info()
+                .await
+                .map_err(|e| stream_error(&e))?
+                .num_pending;
+            let mut messages = consumer.messages().await.map_err(|e| stream_error(&e))?;
+
+            while pending > 0 {
+                let Some(message) = messages.next().await else {
+                    break;
+                };
+                let message = message.map_err(|e| stream_error(&e))?;
+                pending = message.info().map_err(|e| stream_error(&e))?.pending;
+
+                let dead_letter = self.dead_letter(&message)?;
+                self.project(&dead_letter, summary).await;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// Maps the name of a view to its `ViewRebuilder`, the name is the one given
+/// to the rebuild route and command
+#[derive(Clone, Default)]
+pub struct ViewRegistry {
+    views: HashMap<String, Arc<dyn ViewRebuilder>>,
+}
+
+impl ViewRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<R>(&mut self, name: &str, rebuilder: R) -> &mut Self
+    where
+        R: ViewRebuilder + 'static,
+    {
+        self.views.insert(name.to_string(), Arc::new(rebuilder));
+        self
+    }
+
+    /// Rebuild the view registered under `name`, only the rows of
+    /// `aggregate_id` when it is set
+    pub async fn rebuild(
+        &self,
+        name: &str,
+        aggregate_id: Option<Uuid>,
+    ) -> Result<RebuildSummary, RebuildViewError> {
+        let rebuilder = self
+            .views
+            .get(name)
+            .ok_or_else(|| RebuildViewError::NotRegistered(name.to_string()))?;
+
+        let mut summary = RebuildSummary::new(name, aggregate_id);
+        tracing::info!(view = name, ?aggregate_id, "Rebuilding view");
+        rebuilder.rebuild(&mut summary).await?;
+        summary.finish();
+        tracing::info!(
+            view = name,
+            total_events = summary.total_events,
+            projected = summary.projected,
+            failed = summary.errors.len(),
+            "Rebuilt view"
+        );
+
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use esrc::{
+        version::{DeserializeVersion, SerializeVersion},
+        Event,
+    };
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::admin::projector_registry::ProjectorError;
+    use crate::dead_letter::envelope::DeadLetterEnvelope;
+    use crate::testing::{TestEnvelope, TEST_PREFIX};
+
+    #[derive(Event, Serialize, Deserialize, Debug, Clone, SerializeVersion, DeserializeVersion)]
+    #[esrc(event(name = "User"))]
+    enum UserEvent {
+        Created { name: String },
+    }
+
+    /// Fails on the given stream sequences
+    #[derive(Clone, Default)]
+    struct FakeProjector {
+        failing: Vec<u64>,
+    }
+
+    impl DeadLetterProjector for FakeProjector {
+        fn project_dead_letter<'a>(
+            &'a self,
+            dead_letter: &'a DeadLetter,
+            _envelope: &'a DeadLetterEnvelope,
+        ) -> BoxFuture<'a, Result<(), ProjectorError>> {
+            Box::pin(async move {
+                if self.failing.contains(&dead_letter.stream_sequence) {
+                    return Err(ProjectorError::Projection("projection failed".to_string()));
+                }
+                Ok(())
+            })
+        }
+
+        fn validate_dead_letter(
+            &self,
+            _dead_letter: &DeadLetter,
+            _envelope: &DeadLetterEnvelope,
+        ) -> Result<(), ProjectorError> {
+            Ok(())
+        }
+    }
+
+    /// Records the aggregate it was asked to reset, then fails
+    #[derive(Clone, Default)]
+    struct FailingReset {
+        reset: Arc<Mutex<Vec<Option<Uuid>>>>,
+    }
+
+    impl ResetView for FailingReset {
+        fn reset_view(
+            &self,
+            aggregate_id: Option<Uuid>,
+        ) -> BoxFuture<'_, Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+            self.reset.lock().unwrap().push(aggregate_id);
+            Box::pin(async { Err("table is locked".into()) })
+        }
+    }
+
+    /// Rebuilder of a client that never reaches a server
+    async fn rebuilder(project: FakeProjector) -> StreamViewRebuilder<FakeProjector> {
+        let client = async_nats::ConnectOptions::new()
+            .retry_on_initial_connect()
+            .connect("nats://127.0.0.1:1")
+            .await
+            .unwrap();
+        StreamViewRebuilder::new(jetstream::new(client), "users", TEST_PREFIX, project)
+    }
+
+    fn event(sequence: u64) -> DeadLetter {
+        TestEnvelope::new(&UserEvent::Created {
+            name: "Ada".to_string(),
+        })
+        .sequence(sequence)
+        .dead_letter("users", "rebuild")
+    }
+
+    #[tokio::test]
+    async fn stream_rebuilder_reads_one_aggregate_or_the_whole_prefix() {
+        let rebuilder = rebuilder(FakeProjector::default()).await;
+        let aggregate_id = Uuid::now_v7();
+
+        assert_eq!(
+            rebuilder.filter_subject(Some(aggregate_id)),
+            format!("test.*.{}", aggregate_id)
+        );
+        assert_eq!(rebuilder.filter_subject(None), "test.>");
+    }
+
+    #[tokio::test]
+    async fn stream_rebuilder_counts_projected_and_failed_events() {
+        let rebuilder = rebuilder(FakeProjector { failing: vec![2] }).await;
+        let mut summary = RebuildSummary::new("users", None);
+        let mut undecodable = event(3);
+        undecodable.subject = "other.User".to_string();
+
+        for dead_letter in [event(1), event(2), undecodable] {
+            rebuilder.project(&dead_letter, &mut summary).await;
+        }
+
+        assert_eq!(summary.total_events, 3);
+        assert_eq!(summary.projected, 1);
+        assert_eq!(summary.errors.len(), 2);
+        assert!(summary.errors[0].ends_with("(2): projection failed"));
+        assert!(summary.errors[1].starts_with("other.User (3): "));
+    }
+
+    #[tokio::test]
+    async fn stream_rebuilder_stops_when_the_reset_fails() {
+        let reset = FailingReset::default();
+        let rebuilder = rebuilder(FakeProjector::default())
+            .await
+            .with_reset(reset.clone());
+        let aggregate_id = Uuid::now_v7();
+        let mut summary = RebuildSummary::new("users", Some(aggregate_id));
+
+        let error = rebuilder.rebuild(&mut summary).await.unwrap_err();
+
+        assert!(matches!(error, RebuildViewError::Reset(e) if e.to_string() == "table is locked"));
+        assert_eq!(*reset.reset.lock().unwrap(), [Some(aggregate_id)]);
+        assert_eq!(summary.total_events, 0);
+    }
+
+    struct CountingRebuilder;
+
+    impl ViewRebuilder for CountingRebuilder {
+        fn rebuild<'a>(
+            &'a self,
+            summary: &'a mut RebuildSummary,
+        ) -> BoxFuture<'a, Result<(), RebuildViewError>> {
+            Box::pin(async move {
+                summary.total_events = 2;
+                summary.projected = 1;
+                summary
+                    .errors
+                    .push("users.UserCreated.1 (2): failed".to_string());
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn rebuild_runs_the_registered_view() {
+        let mut views = ViewRegistry::new();
+        views.register("users", CountingRebuilder);
+        let aggregate_id = Uuid::now_v7();
+
+        let summary = views.rebuild("users", Some(aggregate_id)).await.unwrap();
+
+        assert_eq!(summary.view, "users");
+        assert_eq!(summary.aggregate_id, Some(aggregate_id));
+        assert_eq!(summary.total_events, 2);
+        assert_eq!(summary.projected, 1);
+        assert_eq!(summary.errors.len(), 1);
+        assert!(summary.finished_at >= summary.started_at);
+    }
+
+    #[tokio::test]
+    async fn rebuild_rejects_an_unregistered_view() {
+        let mut views = ViewRegistry::new();
+        views.register("users", CountingRebuilder);
+
+        let error = views.rebuild("orders", None).await.unwrap_err();
+
+        assert!(matches!(error, RebuildViewError::NotRegistered(name) if name == "orders"));
+    }
+}
@@ -1,62 +1,387 @@
-use async_nats::{jetstream, HeaderMap, Message, Subject};
-use esrc::{nats::NatsEnvelope, project::Project};
-use nats_dead_letter::DeadLetterStore;
-use serde::Serialize;
+use std::collections::BTreeMap;
 
-#[derive(Debug, Clone, Serialize)]
+use async_nats::{jetstream, HeaderMap};
+use chrono::{DateTime, Utc};
+use esrc::Envelope;
+use futures::StreamExt;
+use nats_dead_letter::{DeadLetter, DeadLetterStore};
+use serde::{Deserialize, Serialize};
+
+use crate::admin::filter::DeadLetterFilter;
+use crate::admin::projector_registry::{DeadLetterProjector, ProjectorError};
+use crate::dead_letter::decoder::{DeadLetterDecodeError, DeadLetterDecoder};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReplaySummary {
     pub total_events: usize,
     pub successful_replays: usize,
     pub failed_replays: usize,
+    pub skipped_replays: usize,
     pub processed_aggregates: Vec<uuid::Uuid>,
-    pub errors: Vec<String>,
+    /// Outcome of every dead letter handled by the replay
+    pub results: Vec<ReplayEventResult>,
+    /// The replay was stopped before every aggregate was processed
+    pub cancelled: bool,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration_ms: u64,
+}
+
+impl ReplaySummary {
+    fn new(processed_aggregates: Vec<uuid::Uuid>) -> Self {
+        let now = Utc::now();
+        Self {
+            total_events: 0,
+            successful_replays: 0,
+            failed_replays: 0,
+            skipped_replays: 0,
+            processed_aggregates,
+            results: Vec::new(),
+            cancelled: false,
+            started_at: now,
+            finished_at: now,
+            duration_ms: 0,
+        }
+    }
+
+    fn merge(&mut self, other: ReplaySummary) {
+        self.total_events += other.total_events;
+        self.successful_replays += other.successful_replays;
+        self.failed_replays += other.failed_replays;
+        self.skipped_replays += other.skipped_replays;
+        self.results.extend(other.results);
+        self.cancelled |= other.cancelled;
+    }
+
+    fn finish(&mut self) {
+        self.finished_at = Utc::now();
+        self.duration_ms = (self.finished_at - self.started_at)
+            .num_milliseconds()
+            .max(0) as u64;
+    }
+
+    fn push(&mut self, result: ReplayEventResult) {
+        match result.outcome {
+            ReplayOutcome::Replayed | ReplayOutcome::Republished => self.successful_replays += 1,
+            ReplayOutcome::Failed => self.failed_replays += 1,
+            ReplayOutcome::Skipped => self.skipped_replays += 1,
+        }
+        self.results.push(result);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayEventResult {
+    pub dead_letter_id: Option<uuid::Uuid>,
+    pub aggregate_id: uuid::Uuid,
+    pub stream_sequence: u64,
+    pub outcome: ReplayOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<ReplayErrorKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    /// Whether the dead letter was removed from the store after the replay
+    pub removed: bool,
+}
+
+impl ReplayEventResult {
+    fn new(event: &DeadLetter, aggregate_id: uuid::Uuid, outcome: ReplayOutcome) -> Self {
+        Self {
+            dead_letter_id: event.id,
+            aggregate_id,
+            stream_sequence: event.stream_sequence,
+            outcome,
+            error_kind: None,
+            error_message: None,
+            removed: false,
+        }
+    }
+
+    fn with_error(mut self, kind: ReplayErrorKind, message: String) -> Self {
+        self.error_kind = Some(kind);
+        self.error_message = Some(message);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReplayOutcome {
+    Replayed,
+    /// Published back to the redelivery subject of its consumer
+    Republished,
+    Failed,
+    /// Left in the dead letter store because an earlier event of the same
+    /// aggregate failed or the replay was cancelled
+    Skipped,
+}
+
+impl ReplayOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReplayOutcome::Replayed => "replayed",
+            ReplayOutcome::Republished => "republished",
+            ReplayOutcome::Failed => "failed",
+            ReplayOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReplayErrorKind {
+    /// No projector is registered for the consumer that dead lettered the
+    /// event
+    NoProjector,
+    /// The event could not be turned into a context for the projector
+    Decode,
+    /// The project returned an error for the event
+    Projection,
+    /// The event could not be republished to the redelivery subject
+    Publish,
+    /// The event was replayed but could not be removed from the store
+    Removal,
+}
+
+/// How a dead letter is handed back to its consumer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReplayStrategy {
+    /// Project the event in this process through the registered projector
+    #[default]
+    InProcess,
+    /// Publish the stored payload and headers to the redelivery subject of
+    /// the original durable consumer, so it goes through the live code path.
+    ///
+    /// The dead letter is removed as soon as the stream acknowledged the
+    /// publish, before the consumer processed it: when the consumer fails
+    /// again the event is only kept if the consumer dead letters it again.
+    Republish,
+}
+
+/// What to do with the remaining events of an aggregate once one of them
+/// fails to replay
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReplayPolicy {
+    /// Keep replaying the following events of the aggregate
+    #[default]
+    ContinueOnFailure,
+    /// Stop at the first failure and leave the following events of the
+    /// aggregate in the dead letter store, see `ReplayGroup`
+    HaltAggregateOnFailure,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReplayOptions {
+    #[serde(default)]
+    pub policy: ReplayPolicy,
+    /// Maximum number of aggregates replayed at the same time, the events of
+    /// a single aggregate are always replayed sequentially
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    #[serde(default)]
+    pub strategy: ReplayStrategy,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            policy: ReplayPolicy::default(),
+            concurrency: default_concurrency(),
+            strategy: ReplayStrategy::default(),
+        }
+    }
+}
+
+fn default_concurrency() -> usize {
+    1
+}
+
+/// Snapshot of a running replay, reported after each aggregate
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayProgress {
+    pub completed_aggregates: usize,
+    pub total_aggregates: usize,
+    pub successful_replays: usize,
+    pub failed_replays: usize,
+    pub skipped_replays: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayControl {
+    Continue,
+    Cancel,
+}
+
+/// Receives the progress of a replay and decides whether it should go on
+pub trait ReplayObserver: Send + Sync {
+    fn on_progress(&self, progress: ReplayProgress) -> impl Future<Output = ReplayControl> + Send;
+
+    /// Checked before each event, once it returns true the remaining events
+    /// are left in the store as skipped. Called often, so it should not wait
+    /// on anything.
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// Observer that lets the replay run to completion
+pub struct NoopReplayObserver;
+
+impl ReplayObserver for NoopReplayObserver {
+    async fn on_progress(&self, _progress: ReplayProgress) -> ReplayControl {
+        ReplayControl::Continue
+    }
+}
+
+/// Dead letters replayed together and in order: the ones of an aggregate
+/// dead lettered by one consumer of one stream. Stream sequences only order
+/// the messages of a single stream, and the failure of one consumer does not
+/// hold back the dead letters of another.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ReplayGroup {
+    pub stream: String,
+    pub consumer: String,
+    pub aggregate_id: uuid::Uuid,
+}
+
+impl ReplayGroup {
+    /// Group of the dead letter, `None` without an aggregate ID
+    pub fn of(dead_letter: &DeadLetter) -> Option<Self> {
+        Some(Self {
+            stream: dead_letter.stream.clone(),
+            consumer: dead_letter.consumer.clone(),
+            aggregate_id: dead_letter.aggregate_id?,
+        })
+    }
+}
+
+/// The dead letters with an aggregate ID by `ReplayGroup`, each group in
+/// stream order
+pub(crate) fn replay_groups(
+    events: impl IntoIterator<Item = DeadLetter>,
+) -> BTreeMap<ReplayGroup, Vec<DeadLetter>> {
+    let mut groups = BTreeMap::<ReplayGroup, Vec<DeadLetter>>::new();
+    for event in events {
+        if let Some(group) = ReplayGroup::of(&event) {
+            groups.entry(group).or_default().push(event);
+        }
+    }
+    for events in groups.values_mut() {
+        events.sort_by_key(|e| e.stream_sequence);
+    }
+    groups
+}
+
+/// IDs of the aggregates of the groups, each one once
+fn aggregate_ids<'a>(groups: impl IntoIterator<Item = &'a ReplayGroup>) -> Vec<uuid::Uuid> {
+    let mut ids = groups
+        .into_iter()
+        .map(|group| group.aggregate_id)
+        .collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    ids
 }
 
 #[derive(Clone)]
 pub struct ReplayDeadLetter<DLS, P>
 where
     DLS: DeadLetterStore + Send + Sync + 'static,
-    P: Project + Send + Sync + 'static,
+    P: DeadLetterProjector + Clone + 'static,
 {
     dead_letter_store: DLS,
     project: P,
     context: jetstream::Context,
+    redelivery_prefix: String,
 }
 
 impl<DLS, P> ReplayDeadLetter<DLS, P>
 where
     DLS: DeadLetterStore + Send + Sync + 'static,
-    P: Project + Send + Sync + 'static,
+    P: DeadLetterProjector + Clone + 'static,
 {
     pub fn new(dead_letter_store: DLS, project: P, context: jetstream::Context) -> Self {
         Self {
             dead_letter_store,
             project,
             context,
+            redelivery_prefix: DEFAULT_REDELIVERY_PREFIX.to_string(),
         }
     }
+
+    pub fn dead_letter_store(&self) -> &DLS {
+        &self.dead_letter_store
+    }
+
+    /// Prefix of the subjects used by `ReplayStrategy::Republish`, see
+    /// `redelivery_subject`
+    pub fn with_redelivery_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.redelivery_prefix = prefix.into();
+        self
+    }
+
+    /// Subject a dead letter is republished to,
+    /// `{redelivery prefix}-{consumer}.{event name}.{aggregate id}`. It keeps
+    /// the `{prefix}.{name}.{id}` format esrc consumers parse, with a prefix
+    /// of its own per consumer: the stream of the durable consumer must
+    /// capture it and the consumer must include it in its filter subjects.
+    pub fn redelivery_subject(
+        &self,
+        dead_letter: &DeadLetter,
+    ) -> Result<String, DeadLetterDecodeError> {
+        let envelope = DeadLetterDecoder::envelope(dead_letter)?;
+        Ok(format!(
+            "{}-{}.{}.{}",
+            self.redelivery_prefix,
+            dead_letter.consumer,
+            envelope.name(),
+            Envelope::id(&envelope)
+        ))
+    }
 }
 
+/// Header JetStream deduplicates messages on
+const NATS_MESSAGE_ID: &str = "Nats-Msg-Id";
+
+pub const DEFAULT_REDELIVERY_PREFIX: &str = "redelivery";
+
 #[derive(Debug, thiserror::Error)]
 pub enum ReplayDeadLetterError {
     #[error("No dead letter events found")]
     NotFound,
     #[error(transparent)]
-    NatsJetstream(Box<dyn std::error::Error + Send + Sync>),
-    #[error(transparent)]
     DeadLetterStore(Box<dyn std::error::Error + Send + Sync>),
 }
 
 impl<DLS, P> ReplayDeadLetter<DLS, P>
 where
     DLS: DeadLetterStore + Send + Sync + 'static,
-    P: Project + Send + Sync + 'static,
+    P: DeadLetterProjector + Clone + 'static,
 {
     /// Replay all the dead letters events for a given aggregate ID
     pub async fn replay_one(
         &self,
         aggregate_id: uuid::Uuid,
+        options: ReplayOptions,
     ) -> Result<ReplaySummary, ReplayDeadLetterError> {
+        self.replay_one_with_observer(aggregate_id, options, &NoopReplayObserver)
+            .await
+    }
+
+    /// Replay all the dead letters events for a given aggregate ID, stopping
+    /// before the next event once the observer is cancelled. The dead letters
+    /// of each stream and consumer are replayed in their own stream order.
+    pub async fn replay_one_with_observer<O>(
+        &self,
+        aggregate_id: uuid::Uuid,
+        options: ReplayOptions,
+        observer: &O,
+    ) -> Result<ReplaySummary, ReplayDeadLetterError>
+    where
+        O: ReplayObserver,
+    {
         // TODO: Add a method on the nats-dead-letter crate to get by aggregate ID
         // Get all the events from the dead letter store, we'll filter later
         let events = self
@@ -65,220 +390,631 @@ where
             .await
             .map_err(|e| ReplayDeadLetterError::DeadLetterStore(e.into()))?;
 
-        let aggregate_events = events
+        let groups = replay_groups(
+            events
+                .into_iter()
+                .filter(|e| e.aggregate_id == Some(aggregate_id)),
+        );
+
+        if groups.is_empty() {
+            return Err(ReplayDeadLetterError::NotFound);
+        }
+
+        let mut summary = ReplaySummary::new(vec![aggregate_id]);
+        for (group, events) in groups {
+            self.replay_group(&group, events, &options, observer, &mut summary)
+                .await;
+        }
+        summary.finish();
+
+        Ok(summary)
+    }
+
+    /// Replay all the dead letters events from all aggregates
+    pub async fn replay_all(
+        &self,
+        options: ReplayOptions,
+    ) -> Result<ReplaySummary, ReplayDeadLetterError> {
+        self.replay_all_with_observer(options, &NoopReplayObserver)
+            .await
+    }
+
+    /// Replay all the dead letters events from all aggregates, reporting the
+    /// progress to the observer after each aggregate
+    pub async fn replay_all_with_observer<O>(
+        &self,
+        options: ReplayOptions,
+        observer: &O,
+    ) -> Result<ReplaySummary, ReplayDeadLetterError>
+    where
+        O: ReplayObserver,
+    {
+        self.replay_filtered_with_observer(&DeadLetterFilter::default(), options, observer)
+            .await
+    }
+
+    /// Replay the dead letters matching the filter, other dead letters of
+    /// their aggregates are left in the store. Reports the progress to the
+    /// observer after each `ReplayGroup`, which the progress counts as
+    /// aggregates.
+    pub async fn replay_filtered_with_observer<O>(
+        &self,
+        filter: &DeadLetterFilter,
+        options: ReplayOptions,
+        observer: &O,
+    ) -> Result<ReplaySummary, ReplayDeadLetterError>
+    where
+        O: ReplayObserver,
+    {
+        // Get all the events from the dead letter store
+        let events = self
+            .dead_letter_store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .map_err(|e| ReplayDeadLetterError::DeadLetterStore(e.into()))?
             .into_iter()
-            .filter(|e| e.aggregate_id == Some(aggregate_id))
-            .collect::<Vec<_>>();
+            .filter(|e| filter.matches(e));
 
-        if aggregate_events.is_empty() {
+        let groups = replay_groups(events);
+        if groups.is_empty() {
             return Err(ReplayDeadLetterError::NotFound);
         }
 
-        let mut summary = ReplaySummary {
-            total_events: aggregate_events.len(),
-            successful_replays: 0,
-            failed_replays: 0,
-            processed_aggregates: vec![aggregate_id],
-            errors: Vec::new(),
-        };
+        let mut summary = ReplaySummary::new(aggregate_ids(groups.keys()));
+        let total_aggregates = groups.len();
+        let concurrency = options.concurrency.max(1);
+        let options = &options;
 
-        for event in aggregate_events {
-            let prefix = event.prefix.ok_or(ReplayDeadLetterError::NatsJetstream(
-                "Event prefix is missing".into(),
-            ))?;
-            let subject = Subject::from(event.subject.clone());
-            let mut headers = HeaderMap::new();
-            for (key, value) in
-                event
-                    .headers
-                    .clone()
-                    .ok_or(ReplayDeadLetterError::NatsJetstream(
-                        "Event headers are missing".into(),
-                    ))?
-            {
-                headers.insert(key, value);
-            }
-            let reply_subject = Subject::from(format!(
-                "$JS.ACK._._.{}.{}.{}.{}.1.{}.0.replay",
-                event.stream,                           // stream name
-                event.consumer,                         // consumer name
-                event.delivery_count,                   // delivered count
-                event.stream_sequence,                  // stream sequence
-                event.timestamp.unix_timestamp_nanos()  // timestamp in nanoseconds
-            ));
-
-            // Create a NATS message from the dead letter event
-            let nats_core_message = Message {
-                subject: subject.clone(),
-                reply: Some(reply_subject),
-                payload: event.payload.clone().into(),
-                headers: Some(headers),
-                status: None,
-                description: None,
-                length: event.payload.len(),
+        tracing::info!(total_aggregates, concurrency, "Replaying dead letters");
+
+        // Replay events for each group, different groups run concurrently
+        // while the events of one group stay ordered
+        let mut replays = futures::stream::iter(groups)
+            .map(|(group, events)| async move {
+                let mut group_summary = ReplaySummary::new(vec![group.aggregate_id]);
+                self.replay_group(&group, events, options, observer, &mut group_summary)
+                    .await;
+                (group, group_summary)
+            })
+            .buffer_unordered(concurrency);
+
+        let mut completed_aggregates = 0;
+        while let Some((group, aggregate_summary)) = replays.next().await {
+            completed_aggregates += 1;
+            tracing::info!(
+                aggregate_id = %group.aggregate_id,
+                stream = group.stream,
+                consumer = group.consumer,
+                completed_aggregates,
+                total_aggregates,
+                successful_replays = aggregate_summary.successful_replays,
+                failed_replays = aggregate_summary.failed_replays,
+                "Replayed dead letters for aggregate"
+            );
+            summary.merge(aggregate_summary);
+
+            let progress = ReplayProgress {
+                completed_aggregates,
+                total_aggregates,
+                successful_replays: summary.successful_replays,
+                failed_replays: summary.failed_replays,
+                skipped_replays: summary.skipped_replays,
             };
+            if observer.on_progress(progress).await == ReplayControl::Cancel || summary.cancelled {
+                tracing::warn!(
+                    completed_aggregates,
+                    total_aggregates,
+                    "Dead letter replay cancelled"
+                );
+                summary.cancelled = true;
+                break;
+            }
+        }
+        summary.finish();
 
-            let jetstream_message = jetstream::Message {
-                message: nats_core_message,
-                context: self.context.clone(),
+        Ok(summary)
+    }
+
+    /// Replay dead letters of one aggregate given by the caller instead of
+    /// read from the store, e.g. an edited version of a stored dead letter.
+    /// Each one replayed successfully is removed from the store by its ID.
+    /// The dead letters of each stream and consumer are replayed in their own
+    /// stream order, those without an aggregate ID are left out.
+    pub async fn replay_dead_letters(
+        &self,
+        aggregate_id: uuid::Uuid,
+        events: Vec<DeadLetter>,
+        options: ReplayOptions,
+    ) -> ReplaySummary {
+        let mut summary = ReplaySummary::new(vec![aggregate_id]);
+        for (group, events) in replay_groups(events) {
+            self.replay_group(&group, events, &options, &NoopReplayObserver, &mut summary)
+                .await;
+        }
+        summary.finish();
+        summary
+    }
+
+    /// Check that the dead letter deserializes into the event group of the
+    /// projector of its consumer, without replaying it
+    pub fn validate(&self, dead_letter: &DeadLetter) -> Result<(), ProjectorError> {
+        let envelope = DeadLetterDecoder::envelope(dead_letter)?;
+        self.project.validate_dead_letter(dead_letter, &envelope)
+    }
+
+    /// Replay the events of a single group in stream order
+    async fn replay_group<O>(
+        &self,
+        group: &ReplayGroup,
+        mut events: Vec<DeadLetter>,
+        options: &ReplayOptions,
+        observer: &O,
+        summary: &mut ReplaySummary,
+    ) where
+        O: ReplayObserver,
+    {
+        let aggregate_id = group.aggregate_id;
+        summary.total_events += events.len();
+
+        // Events must be applied in the order they were published, otherwise a
+        // later event could be projected on top of a missing earlier one
+        events.sort_by_key(|e| e.stream_sequence);
+
+        let mut events = events.into_iter();
+        while let Some(event) = events.next() {
+            if observer.is_cancelled() {
+                summary.cancelled = true;
+                for skipped in std::iter::once(event).chain(events.by_ref()) {
+                    summary.push(ReplayEventResult::new(
+                        &skipped,
+                        aggregate_id,
+                        ReplayOutcome::Skipped,
+                    ));
+                }
+                break;
+            }
+
+            let replayed = match options.strategy {
+                ReplayStrategy::InProcess => self.project_in_process(&event).await,
+                ReplayStrategy::Republish => self.republish(&event).await,
             };
 
-            // Create an escr envelope
-            let envelope =
-                NatsEnvelope::try_from_message(&prefix, jetstream_message).map_err(|e| {
-                    ReplayDeadLetterError::NatsJetstream(
-                        format!("Failed to create envelope: {}", e).into(),
-                    )
-                })?;
-
-            // From the envelope, convert it to a context
-            let context = esrc::project::Context::try_with_envelope(&envelope).map_err(|e| {
-                ReplayDeadLetterError::NatsJetstream(
-                    format!("Failed to create context: {}", e).into(),
-                )
-            })?;
-
-            let mut project = self.project.clone();
-            match project.project(context).await {
-                Ok(_) => {
-                    summary.successful_replays += 1;
-                    if let Some(id) = event.id
-                        && let Err(e) = self
+            match replayed {
+                Ok(outcome) => {
+                    let mut result = ReplayEventResult::new(&event, aggregate_id, outcome);
+                    if let Some(id) = event.id {
+                        match self
                             .dead_letter_store
                             .remove_dead_letter(&id.to_string())
                             .await
-                    {
-                        summary
-                            .errors
-                            .push(format!("Failed to remove dead letter {}: {}", id, e));
+                        {
+                            Ok(_) => result.removed = true,
+                            Err(e) => {
+                                result = result.with_error(
+                                    ReplayErrorKind::Removal,
+                                    format!("Failed to remove dead letter {}: {}", id, e),
+                                )
+                            },
+                        }
                     }
+                    summary.push(result);
                 },
-                Err(e) => {
-                    summary.failed_replays += 1;
-                    summary
-                        .errors
-                        .push(format!("Failed to replay event: {}", e));
+                Err((kind, message)) => {
+                    summary.push(
+                        ReplayEventResult::new(&event, aggregate_id, ReplayOutcome::Failed)
+                            .with_error(kind, message),
+                    );
+
+                    if options.policy == ReplayPolicy::HaltAggregateOnFailure {
+                        for skipped in events.by_ref() {
+                            summary.push(ReplayEventResult::new(
+                                &skipped,
+                                aggregate_id,
+                                ReplayOutcome::Skipped,
+                            ));
+                        }
+                    }
                 },
             }
         }
+    }
 
-        Ok(summary)
+    /// Project the dead letter through the projector of its consumer
+    async fn project_in_process(
+        &self,
+        event: &DeadLetter,
+    ) -> Result<ReplayOutcome, (ReplayErrorKind, String)> {
+        // The envelope is built from the stored data only, nothing is acked
+        // or nacked on the server during a replay
+        let envelope = DeadLetterDecoder::envelope(event)
+            .map_err(|e| (ReplayErrorKind::Decode, e.to_string()))?;
+
+        // Dispatch to the projector of the consumer that failed the event
+        self.project
+            .project_dead_letter(event, &envelope)
+            .await
+            .map(|_| ReplayOutcome::Replayed)
+            .map_err(|e| {
+                let kind = match e {
+                    ProjectorError::NotRegistered { .. } => ReplayErrorKind::NoProjector,
+                    ProjectorError::Decode(_) => ReplayErrorKind::Decode,
+                    ProjectorError::Projection(_) => ReplayErrorKind::Projection,
+                };
+                (kind, e.to_string())
+            })
     }
 
-    /// Replay all the dead letters events from all aggregates
-    pub async fn replay_all(&self) -> Result<ReplaySummary, ReplayDeadLetterError> {
-        // Get all the events from the dead letter store
-        let events = self
-            .dead_letter_store
-            .get_dead_letters(None, None, None, None)
+    /// Publish the dead letter back to the redelivery subject of its durable
+    /// consumer and wait for the stream acknowledgement
+    async fn republish(
+        &self,
+        event: &DeadLetter,
+    ) -> Result<ReplayOutcome, (ReplayErrorKind, String)> {
+        let decode_error = |e: DeadLetterDecodeError| (ReplayErrorKind::Decode, e.to_string());
+        let headers = redelivery_headers(event).map_err(decode_error)?;
+        let subject = self.redelivery_subject(event).map_err(decode_error)?;
+
+        let publish_error = |e: &dyn std::fmt::Display| {
+            (
+                ReplayErrorKind::Publish,
+                format!("Failed to republish to {}: {}", subject, e),
+            )
+        };
+
+        self.context
+            .publish_with_headers(subject.clone(), headers, event.payload.clone().into())
             .await
-            .map_err(|e| ReplayDeadLetterError::DeadLetterStore(e.into()))?;
+            .map_err(|e| publish_error(&e))?
+            .await
+            .map_err(|e| publish_error(&e))?;
 
-        // Filter based on aggregates
-        let mut aggregates_events = std::collections::HashMap::new();
-        for event in events {
-            if let Some(aggregate_id) = event.aggregate_id {
-                aggregates_events
-                    .entry(aggregate_id)
-                    .or_insert_with(Vec::new)
-                    .push(event);
-            }
-        }
+        Ok(ReplayOutcome::Republished)
+    }
+}
 
-        if aggregates_events.is_empty() {
-            return Err(ReplayDeadLetterError::NotFound);
+/// Headers a dead letter is republished with.
+///
+/// The original message ID would make JetStream drop the republish as a
+/// duplicate. The dead letter ID replaces it, so that republishing the same
+/// dead letter twice, e.g. after its removal failed, is deduplicated instead.
+fn redelivery_headers(event: &DeadLetter) -> Result<HeaderMap, DeadLetterDecodeError> {
+    let original = DeadLetterDecoder::headers(event)?;
+    let mut headers = HeaderMap::new();
+    for (name, values) in original.iter() {
+        if name.to_string().eq_ignore_ascii_case(NATS_MESSAGE_ID) {
+            continue;
         }
+        for value in values {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+    if let Some(id) = event.id {
+        headers.insert(NATS_MESSAGE_ID, format!("redelivery-{}", id).as_str());
+    }
+    Ok(headers)
+}
 
-        let mut summary = ReplaySummary {
-            total_events: 0,
-            successful_replays: 0,
-            failed_replays: 0,
-            processed_aggregates: aggregates_events.keys().cloned().collect(),
-            errors: Vec::new(),
-        };
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
 
-        // Replay events for each aggregate
-        for (aggregate_id, events) in aggregates_events {
-            println!("Replaying events for aggregate ID: {}", aggregate_id);
-            summary.total_events += events.len();
-
-            for event in events {
-                let prefix = event.prefix.ok_or("Event prefix is missing").map_err(|e| {
-                    ReplayDeadLetterError::NatsJetstream(
-                        format!("Event prefix is missing: {}", e).into(),
-                    )
-                })?;
-                let subject = Subject::from(event.subject);
-                let mut headers = HeaderMap::new();
-                for (key, value) in event.headers.clone().expect("Headers should be present") {
-                    headers.insert(key, value);
-                }
-                let reply_subject = Subject::from(format!(
-                    "$JS.ACK._._.{}.{}.{}.{}.1.{}.0.replay",
-                    event.stream,                           // stream name
-                    event.consumer,                         // consumer name
-                    event.delivery_count,                   // delivered count
-                    event.stream_sequence,                  // stream sequence
-                    event.timestamp.unix_timestamp_nanos()  // timestamp in nanoseconds
-                ));
-
-                // Create a NATS message from the dead letter event
-                let nats_core_message = Message {
-                    subject: subject.clone(),
-                    reply: Some(reply_subject),
-                    payload: event.payload.clone().into(),
-                    headers: Some(headers),
-                    status: None,
-                    description: None,
-                    length: event.payload.len(),
-                };
-                let jetstream_message = jetstream::Message {
-                    message: nats_core_message,
-                    context: self.context.clone(),
-                };
+    use esrc::{
+        version::{DeserializeVersion, SerializeVersion},
+        Event,
+    };
+    use futures::future::BoxFuture;
+    use uuid::Uuid;
 
-                // Create an escr envelope
-                let envelope =
-                    NatsEnvelope::try_from_message(&prefix, jetstream_message).map_err(|e| {
-                        ReplayDeadLetterError::NatsJetstream(
-                            format!("Failed to create envelope: {}", e).into(),
-                        )
-                    })?;
-
-                // From the envelope, convert it to a context
-                let context =
-                    esrc::project::Context::try_with_envelope(&envelope).map_err(|e| {
-                        ReplayDeadLetterError::NatsJetstream(
-                            format!("Failed to create context: {}", e).into(),
-                        )
-                    })?;
-
-                let mut project = self.project.clone();
-                match project.project(context).await {
-                    Ok(_) => {
-                        summary.successful_replays += 1;
-                        if let Some(id) = event.id
-                            && let Err(e) = self
-                                .dead_letter_store
-                                .remove_dead_letter(&id.to_string())
-                                .await
-                        {
-                            summary
-                                .errors
-                                .push(format!("Failed to remove dead letter {}: {}", id, e));
-                        }
-                    },
-                    Err(e) => {
-                        summary.failed_replays += 1;
-                        summary.errors.push(format!(
-                            "Failed to replay event for aggregate {}: {}",
-                            aggregate_id, e
-                        ));
-                    },
+    use super::*;
+    use crate::dead_letter::envelope::DeadLetterEnvelope;
+    use crate::dead_letter::memory::InMemoryDeadLetterStore;
+    use crate::testing::TestEnvelope;
+
+    #[derive(Event, Serialize, Deserialize, Debug, Clone, SerializeVersion, DeserializeVersion)]
+    #[esrc(event(name = "User"))]
+    enum UserEvent {
+        Created { name: String },
+    }
+
+    /// Records the stream sequences it projects and fails on the given ones
+    #[derive(Clone, Default)]
+    struct FakeProjector {
+        failing: Vec<u64>,
+        projected: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl DeadLetterProjector for FakeProjector {
+        fn project_dead_letter<'a>(
+            &'a self,
+            dead_letter: &'a DeadLetter,
+            _envelope: &'a DeadLetterEnvelope,
+        ) -> BoxFuture<'a, Result<(), ProjectorError>> {
+            Box::pin(async move {
+                if self.failing.contains(&dead_letter.stream_sequence) {
+                    return Err(ProjectorError::Projection("projection failed".to_string()));
                 }
-            }
+                self.projected
+                    .lock()
+                    .unwrap()
+                    .push(dead_letter.stream_sequence);
+                Ok(())
+            })
         }
 
-        Ok(summary)
+        fn validate_dead_letter(
+            &self,
+            _dead_letter: &DeadLetter,
+            _envelope: &DeadLetterEnvelope,
+        ) -> Result<(), ProjectorError> {
+            Ok(())
+        }
+    }
+
+    /// Context of a client that never reaches a server, in process replays
+    /// do not publish anything
+    async fn context() -> jetstream::Context {
+        let client = async_nats::ConnectOptions::new()
+            .retry_on_initial_connect()
+            .connect("nats://127.0.0.1:1")
+            .await
+            .unwrap();
+        jetstream::new(client)
+    }
+
+    fn dead_letter(aggregate_id: Uuid, sequence: u64) -> DeadLetter {
+        TestEnvelope::new(&UserEvent::Created {
+            name: "Ada".to_string(),
+        })
+        .id(aggregate_id)
+        .sequence(sequence)
+        .dead_letter("users", "user-projector")
+    }
+
+    async fn replay(
+        store: &InMemoryDeadLetterStore,
+        projector: FakeProjector,
+    ) -> ReplayDeadLetter<InMemoryDeadLetterStore, FakeProjector> {
+        ReplayDeadLetter::new(store.clone(), projector, context().await)
+    }
+
+    fn outcomes(summary: &ReplaySummary) -> Vec<(u64, ReplayOutcome)> {
+        summary
+            .results
+            .iter()
+            .map(|result| (result.stream_sequence, result.outcome))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn replay_one_projects_in_stream_order_and_removes_the_dead_letters() {
+        let aggregate_id = Uuid::now_v7();
+        let store = InMemoryDeadLetterStore::with_dead_letters([
+            dead_letter(aggregate_id, 3),
+            dead_letter(aggregate_id, 1),
+            dead_letter(Uuid::now_v7(), 2),
+        ]);
+        let projector = FakeProjector::default();
+        let replay = replay(&store, projector.clone()).await;
+
+        let summary = replay
+            .replay_one(aggregate_id, ReplayOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(summary.successful_replays, 2);
+        assert!(summary.results.iter().all(|result| result.removed));
+        assert_eq!(*projector.projected.lock().unwrap(), vec![1, 3]);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn replay_keeps_the_dead_letter_when_its_removal_fails() {
+        let aggregate_id = Uuid::now_v7();
+        let store = InMemoryDeadLetterStore::with_dead_letters([dead_letter(aggregate_id, 1)]);
+        store.fail_on_remove(true);
+        let replay = replay(&store, FakeProjector::default()).await;
+
+        let summary = replay
+            .replay_one(aggregate_id, ReplayOptions::default())
+            .await
+            .unwrap();
+
+        let result = &summary.results[0];
+        assert_eq!(result.outcome, ReplayOutcome::Replayed);
+        assert!(!result.removed);
+        assert_eq!(result.error_kind, Some(ReplayErrorKind::Removal));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn replay_surfaces_a_failed_read_of_the_store() {
+        let store = InMemoryDeadLetterStore::with_dead_letters([dead_letter(Uuid::now_v7(), 1)]);
+        store.fail_on_get(true);
+        let replay = replay(&store, FakeProjector::default()).await;
+
+        let error = replay
+            .replay_all(ReplayOptions::default())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, ReplayDeadLetterError::DeadLetterStore(_)));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn halt_aggregate_on_failure_keeps_the_following_dead_letters() {
+        let aggregate_id = Uuid::now_v7();
+        let store = InMemoryDeadLetterStore::with_dead_letters(
+            (1..=3).map(|sequence| dead_letter(aggregate_id, sequence)),
+        );
+        let projector = FakeProjector {
+            failing: vec![2],
+            ..FakeProjector::default()
+        };
+        let replay = replay(&store, projector.clone()).await;
+        let options = ReplayOptions {
+            policy: ReplayPolicy::HaltAggregateOnFailure,
+            ..ReplayOptions::default()
+        };
+
+        let summary = replay.replay_one(aggregate_id, options).await.unwrap();
+
+        assert_eq!(
+            outcomes(&summary),
+            vec![
+                (1, ReplayOutcome::Replayed),
+                (2, ReplayOutcome::Failed),
+                (3, ReplayOutcome::Skipped),
+            ]
+        );
+        assert_eq!(*projector.projected.lock().unwrap(), vec![1]);
+        let mut kept = store
+            .dead_letters()
+            .iter()
+            .map(|dead_letter| dead_letter.stream_sequence)
+            .collect::<Vec<_>>();
+        kept.sort();
+        assert_eq!(kept, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn halt_aggregate_on_failure_keeps_to_the_failed_consumer() {
+        let aggregate_id = Uuid::now_v7();
+        let mut mailer = dead_letter(aggregate_id, 3);
+        mailer.consumer = "user-mailer".to_string();
+        let store = InMemoryDeadLetterStore::with_dead_letters([
+            dead_letter(aggregate_id, 1),
+            dead_letter(aggregate_id, 2),
+            mailer,
+        ]);
+        let projector = FakeProjector {
+            failing: vec![1],
+            ..FakeProjector::default()
+        };
+        let replay = replay(&store, projector.clone()).await;
+        let options = ReplayOptions {
+            policy: ReplayPolicy::HaltAggregateOnFailure,
+            ..ReplayOptions::default()
+        };
+
+        let summary = replay.replay_one(aggregate_id, options).await.unwrap();
+
+        let mut outcomes = outcomes(&summary);
+        outcomes.sort_by_key(|(sequence, _)| *sequence);
+        assert_eq!(
+            outcomes,
+            vec![
+                (1, ReplayOutcome::Failed),
+                (2, ReplayOutcome::Skipped),
+                (3, ReplayOutcome::Replayed),
+            ]
+        );
+        assert_eq!(summary.processed_aggregates, vec![aggregate_id]);
+    }
+
+    #[test]
+    fn replay_groups_order_each_stream_and_consumer_on_its_own() {
+        let aggregate_id = Uuid::now_v7();
+        let mut orders = dead_letter(aggregate_id, 1);
+        orders.stream = "orders".to_string();
+        let groups = replay_groups([
+            dead_letter(aggregate_id, 7),
+            orders,
+            dead_letter(aggregate_id, 5),
+        ]);
+
+        let groups = groups
+            .iter()
+            .map(|(group, events)| {
+                let sequences = events.iter().map(|e| e.stream_sequence).collect::<Vec<_>>();
+                (group.stream.as_str(), sequences)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(groups, vec![("orders", vec![1]), ("users", vec![5, 7])]);
+    }
+
+    #[tokio::test]
+    async fn continue_on_failure_replays_the_following_dead_letters() {
+        let aggregate_id = Uuid::now_v7();
+        let store = InMemoryDeadLetterStore::with_dead_letters(
+            (1..=3).map(|sequence| dead_letter(aggregate_id, sequence)),
+        );
+        let projector = FakeProjector {
+            failing: vec![2],
+            ..FakeProjector::default()
+        };
+        let replay = replay(&store, projector.clone()).await;
+
+        let summary = replay
+            .replay_one(aggregate_id, ReplayOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(summary.failed_replays, 1);
+        assert_eq!(*projector.projected.lock().unwrap(), vec![1, 3]);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn redelivery_subject_is_prefixed_per_consumer() {
+        let aggregate_id = Uuid::now_v7();
+        let dead_letter = dead_letter(aggregate_id, 1);
+        let replay = replay(&InMemoryDeadLetterStore::new(), FakeProjector::default()).await;
+
+        assert_eq!(
+            replay.redelivery_subject(&dead_letter).unwrap(),
+            format!("redelivery-user-projector.User.{}", aggregate_id)
+        );
+        assert_eq!(
+            replay
+                .with_redelivery_prefix("retry")
+                .redelivery_subject(&dead_letter)
+                .unwrap(),
+            format!("retry-user-projector.User.{}", aggregate_id)
+        );
+    }
+
+    #[test]
+    fn redelivery_headers_replace_the_message_id_with_the_dead_letter_id() {
+        let dead_letter = TestEnvelope::new(&UserEvent::Created {
+            name: "Ada".to_string(),
+        })
+        .metadata(NATS_MESSAGE_ID, "original")
+        .metadata("Trace-Id", "abc")
+        .dead_letter("users", "user-projector");
+
+        let headers = redelivery_headers(&dead_letter).unwrap();
+
+        assert_eq!(
+            headers.get(NATS_MESSAGE_ID).map(|value| value.as_str()),
+            Some(format!("redelivery-{}", dead_letter.id.unwrap()).as_str())
+        );
+        assert_eq!(
+            headers.get("Trace-Id").map(|value| value.as_str()),
+            Some("abc")
+        );
+        assert_eq!(
+            headers
+                .iter()
+                .filter(|(name, _)| name.to_string().eq_ignore_ascii_case(NATS_MESSAGE_ID))
+                .map(|(_, values)| values.len())
+                .sum::<usize>(),
+            1
+        );
+    }
+
+    #[test]
+    fn redelivery_headers_drop_the_message_id_of_a_dead_letter_without_id() {
+        let mut dead_letter = TestEnvelope::new(&UserEvent::Created {
+            name: "Ada".to_string(),
+        })
+        .metadata(NATS_MESSAGE_ID, "original")
+        .dead_letter("users", "user-projector");
+        dead_letter.id = None;
+
+        let headers = redelivery_headers(&dead_letter).unwrap();
+
+        assert!(headers.get(NATS_MESSAGE_ID).is_none());
     }
 }
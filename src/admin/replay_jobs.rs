@@ -0,0 +1,838 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use nats_dead_letter::DeadLetterStore;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::admin::audit::{self, AdminAuditLog, AuditOutcome};
+use crate::admin::filter::DeadLetterFilter;
+use crate::admin::projector_registry::DeadLetterProjector;
+use crate::admin::replay_dead_letter::{
+    ReplayControl, ReplayDeadLetter, ReplayDeadLetterError, ReplayObserver, ReplayOptions,
+    ReplayProgress, ReplaySummary,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReplayJobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl ReplayJobStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ReplayJobStatus::Pending => "pending",
+            ReplayJobStatus::Running => "running",
+            ReplayJobStatus::Completed => "completed",
+            ReplayJobStatus::Failed => "failed",
+            ReplayJobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    fn parse(status: &str) -> Result<Self> {
+        match status {
+            "pending" => Ok(ReplayJobStatus::Pending),
+            "running" => Ok(ReplayJobStatus::Running),
+            "completed" => Ok(ReplayJobStatus::Completed),
+            "failed" => Ok(ReplayJobStatus::Failed),
+            "cancelled" => Ok(ReplayJobStatus::Cancelled),
+            _ => Err(ReplayJobError::InvalidStatus(status.to_string())),
+        }
+    }
+
+    /// Status of a job whose lease was just taken, see `ReplayJobStore::claim`
+    fn claimed(cancel_requested: bool) -> Self {
+        if cancel_requested {
+            ReplayJobStatus::Cancelled
+        } else {
+            ReplayJobStatus::Running
+        }
+    }
+
+    /// Status once a cancellation was requested: a job that has not started
+    /// is cancelled right away, a running one stops on its own
+    fn on_cancel_request(self) -> Self {
+        match self {
+            ReplayJobStatus::Pending => ReplayJobStatus::Cancelled,
+            status => status,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            ReplayJobStatus::Completed | ReplayJobStatus::Failed | ReplayJobStatus::Cancelled
+        )
+    }
+}
+
+/// Which dead letters a replay job works on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ReplayJobTarget {
+    Aggregate {
+        aggregate_id: Uuid,
+    },
+    /// Only the dead letters matching the filter
+    Filtered {
+        filter: DeadLetterFilter,
+    },
+    All,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayJob {
+    pub id: Uuid,
+    pub target: ReplayJobTarget,
+    pub options: ReplayOptions,
+    pub status: ReplayJobStatus,
+    pub total_aggregates: i64,
+    pub completed_aggregates: i64,
+    pub successful_replays: i64,
+    pub failed_replays: i64,
+    pub skipped_replays: i64,
+    pub cancel_requested: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<ReplaySummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Entry of the audit log the job records its final summary in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit_entry_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ReplayJob {
+    /// The job was cancelled before it ran, it has no summary
+    pub fn cancelled_before_start(&self) -> bool {
+        self.status == ReplayJobStatus::Cancelled && self.summary.is_none()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayJobError {
+    #[error("Replay job {0} not found")]
+    NotFound(Uuid),
+    #[error("Unknown replay job status: {0}")]
+    InvalidStatus(String),
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+}
+
+type Result<T> = std::result::Result<T, ReplayJobError>;
+
+/// Postgres persistence of the replay jobs, so their state survives restarts.
+///
+/// A job is run by the instance holding its lease: the lease is taken
+/// atomically when the job starts, renewed while it runs, and a running job
+/// is only resumed by another instance once its lease expired.
+#[derive(Clone)]
+pub struct ReplayJobStore {
+    db: sqlx::PgPool,
+    handler: String,
+}
+
+impl ReplayJobStore {
+    pub fn new(db: sqlx::PgPool) -> Self {
+        Self {
+            db,
+            handler: String::new(),
+        }
+    }
+
+    /// Only see the jobs of the named admin handler, so that handlers sharing
+    /// a database never resume or cancel each other's jobs
+    pub fn with_handler(mut self, handler: impl Into<String>) -> Self {
+        self.handler = handler.into();
+        self
+    }
+
+    pub async fn setup(&self) -> std::result::Result<(), sqlx::Error> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS dead_letter_replay_jobs(
+                id uuid                         NOT NULL,
+                handler text                    NOT NULL DEFAULT '',
+                target jsonb                    NOT NULL,
+                options jsonb                   NOT NULL,
+                status text                     NOT NULL,
+                total_aggregates bigint         NOT NULL DEFAULT 0,
+                completed_aggregates bigint     NOT NULL DEFAULT 0,
+                successful_replays bigint       NOT NULL DEFAULT 0,
+                failed_replays bigint           NOT NULL DEFAULT 0,
+                skipped_replays bigint          NOT NULL DEFAULT 0,
+                cancel_requested boolean        NOT NULL DEFAULT false,
+                owner uuid,
+                lease_expires_at timestamptz,
+                summary jsonb,
+                error text,
+                audit_entry_id uuid,
+                created_at timestamptz          NOT NULL DEFAULT NOW(),
+                updated_at timestamptz          NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (id)
+            );",
+        )
+        .execute(&self.db)
+        .await?;
+        // Tables created before the jobs were scoped by handler, leased and
+        // audited
+        sqlx::query(
+            "ALTER TABLE dead_letter_replay_jobs
+                ADD COLUMN IF NOT EXISTS handler text NOT NULL DEFAULT '',
+                ADD COLUMN IF NOT EXISTS owner uuid,
+                ADD COLUMN IF NOT EXISTS lease_expires_at timestamptz,
+                ADD COLUMN IF NOT EXISTS audit_entry_id uuid",
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn create(
+        &self,
+        target: ReplayJobTarget,
+        options: ReplayOptions,
+        audit_entry_id: Option<Uuid>,
+    ) -> Result<ReplayJob> {
+        let row = sqlx::query(
+            "INSERT INTO dead_letter_replay_jobs
+                (id, handler, target, options, status, audit_entry_id)
+                VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
+        )
+        .bind(Uuid::now_v7())
+        .bind(&self.handler)
+        .bind(serde_json::to_value(&target)?)
+        .bind(serde_json::to_value(&options)?)
+        .bind(ReplayJobStatus::Pending.as_str())
+        .bind(audit_entry_id)
+        .fetch_one(&self.db)
+        .await?;
+
+        Self::from_row(&row)
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<ReplayJob> {
+        let row =
+            sqlx::query("SELECT * FROM dead_letter_replay_jobs WHERE id = $1 AND handler = $2")
+                .bind(id)
+                .bind(&self.handler)
+                .fetch_optional(&self.db)
+                .await?
+                .ok_or(ReplayJobError::NotFound(id))?;
+
+        Self::from_row(&row)
+    }
+
+    /// Take the lease of a pending job, or of a running one whose lease
+    /// expired, for `owner`. Returns `None` when another instance holds the
+    /// job or it is finished, and the job as cancelled when a cancellation
+    /// was requested before it could start.
+    pub async fn claim(&self, id: Uuid, owner: Uuid, lease: Duration) -> Result<Option<ReplayJob>> {
+        let row = sqlx::query(
+            "UPDATE dead_letter_replay_jobs SET
+                status = CASE WHEN cancel_requested THEN $5 ELSE $6 END,
+                owner = $3,
+                lease_expires_at = NOW() + make_interval(secs => $4),
+                updated_at = NOW()
+            WHERE id = $1 AND handler = $2 AND (
+                status = 'pending'
+                OR (status = 'running' AND (lease_expires_at IS NULL OR lease_expires_at < NOW()))
+            ) RETURNING *",
+        )
+        .bind(id)
+        .bind(&self.handler)
+        .bind(owner)
+        .bind(lease.as_secs_f64())
+        .bind(ReplayJobStatus::claimed(true).as_str())
+        .bind(ReplayJobStatus::claimed(false).as_str())
+        .fetch_optional(&self.db)
+        .await?;
+
+        row.as_ref().map(Self::from_row).transpose()
+    }
+
+    /// Take the lease of every job that is pending or whose lease expired,
+    /// e.g. because the instance running it stopped. Jobs locked by another
+    /// instance claiming them at the same time are skipped.
+    pub async fn claim_unfinished(&self, owner: Uuid, lease: Duration) -> Result<Vec<ReplayJob>> {
+        let rows = sqlx::query(
+            "UPDATE dead_letter_replay_jobs SET
+                status = CASE WHEN cancel_requested THEN $4 ELSE $5 END,
+                owner = $2,
+                lease_expires_at = NOW() + make_interval(secs => $3),
+                updated_at = NOW()
+            WHERE id IN (
+                SELECT id FROM dead_letter_replay_jobs
+                WHERE handler = $1 AND (
+                    status = 'pending'
+                    OR (status = 'running' AND (lease_expires_at IS NULL OR lease_expires_at < NOW()))
+                )
+                ORDER BY created_at
+                FOR UPDATE SKIP LOCKED
+            ) RETURNING *",
+        )
+        .bind(&self.handler)
+        .bind(owner)
+        .bind(lease.as_secs_f64())
+        .bind(ReplayJobStatus::claimed(true).as_str())
+        .bind(ReplayJobStatus::claimed(false).as_str())
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut jobs = rows
+            .iter()
+            .map(Self::from_row)
+            .collect::<Result<Vec<_>>>()?;
+        jobs.sort_by_key(|job| job.created_at);
+        Ok(jobs)
+    }
+
+    /// Extend the lease of a job held by `owner`, returns whether the job
+    /// should stop: a cancellation was requested or the lease was lost
+    pub async fn renew_lease(&self, id: Uuid, owner: Uuid, lease: Duration) -> Result<bool> {
+        let row = sqlx::query(
+            "UPDATE dead_letter_replay_jobs SET
+                lease_expires_at = NOW() + make_interval(secs => $3),
+                updated_at = NOW()
+            WHERE id = $1 AND owner = $2 AND status = 'running' RETURNING cancel_requested",
+        )
+        .bind(id)
+        .bind(owner)
+        .bind(lease.as_secs_f64())
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.is_none_or(|row| row.get::<bool, _>("cancel_requested")))
+    }
+
+    /// Store the progress of a job held by `owner` and extend its lease,
+    /// returns whether the job should stop as with `renew_lease`
+    pub async fn update_progress(
+        &self,
+        id: Uuid,
+        owner: Uuid,
+        lease: Duration,
+        progress: &ReplayProgress,
+    ) -> Result<bool> {
+        let row = sqlx::query(
+            "UPDATE dead_letter_replay_jobs SET
+                total_aggregates = $4,
+                completed_aggregates = $5,
+                successful_replays = $6,
+                failed_replays = $7,
+                skipped_replays = $8,
+                lease_expires_at = NOW() + make_interval(secs => $3),
+                updated_at = NOW()
+            WHERE id = $1 AND owner = $2 AND status = 'running' RETURNING cancel_requested",
+        )
+        .bind(id)
+        .bind(owner)
+        .bind(lease.as_secs_f64())
+        .bind(progress.total_aggregates as i64)
+        .bind(progress.completed_aggregates as i64)
+        .bind(progress.successful_replays as i64)
+        .bind(progress.failed_replays as i64)
+        .bind(progress.skipped_replays as i64)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.is_none_or(|row| row.get::<bool, _>("cancel_requested")))
+    }
+
+    /// Store the summary of a job held by `owner`, returns false when the
+    /// lease was lost and another instance took the job over
+    pub async fn complete(&self, id: Uuid, owner: Uuid, summary: &ReplaySummary) -> Result<bool> {
+        let status = if summary.cancelled {
+            ReplayJobStatus::Cancelled
+        } else {
+            ReplayJobStatus::Completed
+        };
+
+        let result = sqlx::query(
+            "UPDATE dead_letter_replay_jobs SET
+                status = $2,
+                summary = $3,
+                successful_replays = $4,
+                failed_replays = $5,
+                skipped_replays = $6,
+                lease_expires_at = NULL,
+                updated_at = NOW()
+            WHERE id = $1 AND owner = $7 AND status = 'running'",
+        )
+        .bind(id)
+        .bind(status.as_str())
+        .bind(serde_json::to_value(summary)?)
+        .bind(summary.successful_replays as i64)
+        .bind(summary.failed_replays as i64)
+        .bind(summary.skipped_replays as i64)
+        .bind(owner)
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Store the error of a job held by `owner`, returns false when the lease
+    /// was lost
+    pub async fn fail(&self, id: Uuid, owner: Uuid, error: &str) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE dead_letter_replay_jobs SET
+                status = $2, error = $3, lease_expires_at = NULL, updated_at = NOW()
+            WHERE id = $1 AND owner = $4 AND status = 'running'",
+        )
+        .bind(id)
+        .bind(ReplayJobStatus::Failed.as_str())
+        .bind(error)
+        .bind(owner)
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Ask a job to stop, a job that has not started yet is cancelled right
+    /// away while a running one stops before its next event
+    pub async fn request_cancel(&self, id: Uuid) -> Result<ReplayJob> {
+        let row = sqlx::query(
+            "UPDATE dead_letter_replay_jobs SET
+                cancel_requested = true,
+                status = CASE WHEN status = 'pending' THEN $3 ELSE status END,
+                updated_at = NOW()
+            WHERE id = $1 AND handler = $2 RETURNING *",
+        )
+        .bind(id)
+        .bind(&self.handler)
+        .bind(ReplayJobStatus::Pending.on_cancel_request().as_str())
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(ReplayJobError::NotFound(id))?;
+
+        Self::from_row(&row)
+    }
+
+    fn from_row(row: &sqlx::postgres::PgRow) -> Result<ReplayJob> {
+        Ok(ReplayJob {
+            id: row.get("id"),
+            target: serde_json::from_value(row.get::<Value, _>("target"))?,
+            options: serde_json::from_value(row.get::<Value, _>("options"))?,
+            status: ReplayJobStatus::parse(row.get::<&str, _>("status"))?,
+            total_aggregates: row.get("total_aggregates"),
+            completed_aggregates: row.get("completed_aggregates"),
+            successful_replays: row.get("successful_replays"),
+            failed_replays: row.get("failed_replays"),
+            skipped_replays: row.get("skipped_replays"),
+            cancel_requested: row.get("cancel_requested"),
+            summary: row
+                .get::<Option<Value>, _>("summary")
+                .map(serde_json::from_value)
+                .transpose()?,
+            error: row.get("error"),
+            audit_entry_id: row.get("audit_entry_id"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        })
+    }
+}
+
+/// Runs replays in the background, tracking them in the `ReplayJobStore`
+#[derive(Clone)]
+pub struct ReplayJobRunner<DLS, P>
+where
+    DLS: DeadLetterStore + Clone + Send + Sync + 'static,
+    P: DeadLetterProjector + Clone + 'static,
+{
+    dead_letter_replay: ReplayDeadLetter<DLS, P>,
+    store: ReplayJobStore,
+    /// Identifies this process as the holder of the jobs it runs
+    owner: Uuid,
+    lease: Duration,
+    audit_log: Option<AdminAuditLog>,
+}
+
+impl<DLS, P> ReplayJobRunner<DLS, P>
+where
+    DLS: DeadLetterStore + Clone + Send + Sync + 'static,
+    P: DeadLetterProjector + Clone + 'static,
+{
+    pub fn new(dead_letter_replay: ReplayDeadLetter<DLS, P>, store: ReplayJobStore) -> Self {
+        Self {
+            dead_letter_replay,
+            store,
+            owner: Uuid::now_v7(),
+            lease: DEFAULT_JOB_LEASE,
+            audit_log: None,
+        }
+    }
+
+    /// Complete the audit entry of the command that started a job with the
+    /// summary of the job once it finishes
+    pub fn with_audit_log(mut self, audit_log: AdminAuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Time after which a running job whose instance stopped renewing it can
+    /// be resumed by another instance, one minute by default. The lease is
+    /// renewed three times per period, which is also how long a cancellation
+    /// of a single aggregate replay can take to be noticed.
+    pub fn with_lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    pub fn store(&self) -> &ReplayJobStore {
+        &self.store
+    }
+
+    /// Create a job and start it in the background
+    pub async fn start(
+        &self,
+        target: ReplayJobTarget,
+        options: ReplayOptions,
+    ) -> Result<ReplayJob> {
+        let job = self
+            .store
+            .create(target, options, audit::current_entry())
+            .await?;
+        let runner = self.clone();
+        let id = job.id;
+        tokio::spawn(async move {
+            match runner.store.claim(id, runner.owner, runner.lease).await {
+                Ok(Some(job)) => runner.run(job).await,
+                Ok(None) => {
+                    tracing::info!(job_id = %id, "Replay job was taken by another instance")
+                },
+                Err(e) => tracing::error!(job_id = %id, error = %e, "Failed to claim replay job"),
+            }
+        });
+
+        Ok(job)
+    }
+
+    /// Ask a job to stop, see `ReplayJobStore::request_cancel`. A job
+    /// cancelled before it started completes its audit entry here, since it
+    /// never runs to do it.
+    pub async fn cancel(&self, id: Uuid) -> Result<ReplayJob> {
+        let job = self.store.request_cancel(id).await?;
+        if job.cancelled_before_start() {
+            self.complete_audit(&job, None).await;
+        }
+        Ok(job)
+    }
+
+    /// Start again the jobs that were interrupted by a restart, dead letters
+    /// that were already replayed have been removed so they are not replayed
+    /// twice. Jobs still held by a live instance are left alone, so every
+    /// instance can call this on startup.
+    pub async fn resume(&self) -> Result<usize> {
+        let jobs = self.store.claim_unfinished(self.owner, self.lease).await?;
+        let mut resumed = 0;
+        for job in jobs {
+            tracing::info!(job_id = %job.id, "Resuming interrupted replay job");
+            let runner = self.clone();
+            resumed += 1;
+            tokio::spawn(async move {
+                runner.run(job).await;
+            });
+        }
+
+        Ok(resumed)
+    }
+
+    #[tracing::instrument(name = "::replay_job", skip_all, fields(job_id = %job.id))]
+    async fn run(&self, job: ReplayJob) {
+        if job.status != ReplayJobStatus::Running {
+            tracing::info!("Replay job was cancelled before it started");
+            self.complete_audit(&job, None).await;
+            return;
+        }
+
+        let observer = Arc::new(JobObserver {
+            store: self.store.clone(),
+            id: job.id,
+            owner: self.owner,
+            lease: self.lease,
+            stop: AtomicBool::new(false),
+        });
+        let heartbeat = tokio::spawn(observer.clone().heartbeat());
+
+        let result = match job.target {
+            ReplayJobTarget::Aggregate { aggregate_id } => {
+                self.dead_letter_replay
+                    .replay_one_with_observer(aggregate_id, job.options, observer.as_ref())
+                    .await
+            },
+            ReplayJobTarget::Filtered { filter } => {
+                self.dead_letter_replay
+                    .replay_filtered_with_observer(&filter, job.options, observer.as_ref())
+                    .await
+            },
+            ReplayJobTarget::All => {
+                self.dead_letter_replay
+                    .replay_all_with_observer(job.options, observer.as_ref())
+                    .await
+            },
+        };
+        heartbeat.abort();
+
+        let stored = match &result {
+            Ok(summary) => self.store.complete(job.id, self.owner, summary).await,
+            Err(e) => {
+                tracing::error!(error = %e, "Replay job failed");
+                self.store.fail(job.id, self.owner, &e.to_string()).await
+            },
+        };
+        match stored {
+            Ok(true) => {},
+            Ok(false) => {
+                tracing::warn!("Replay job lease was lost, its result was not stored");
+                return;
+            },
+            Err(e) => tracing::error!(error = %e, "Failed to store replay job result"),
+        }
+
+        self.complete_audit(&job, Some(&result)).await;
+    }
+
+    /// Complete the audit entry of the command that started the job, `None`
+    /// for a job cancelled before it ran
+    async fn complete_audit(
+        &self,
+        job: &ReplayJob,
+        result: Option<&std::result::Result<ReplaySummary, ReplayDeadLetterError>>,
+    ) {
+        let (Some(audit_log), Some(entry_id)) = (&self.audit_log, job.audit_entry_id) else {
+            return;
+        };
+        let (outcome, summary, error) = audit_completion(result);
+        let duration_ms = (Utc::now() - job.created_at).num_milliseconds();
+        if let Err(e) = audit_log
+            .complete(
+                entry_id,
+                outcome,
+                summary.as_ref(),
+                error.as_deref(),
+                duration_ms,
+            )
+            .await
+        {
+            tracing::error!(error = %e, "Failed to record replay job summary in the audit log");
+        }
+    }
+}
+
+/// Outcome, result and error the audit entry of a job is completed with,
+/// `None` for a job cancelled before it ran
+fn audit_completion(
+    result: Option<&std::result::Result<ReplaySummary, ReplayDeadLetterError>>,
+) -> (AuditOutcome, Option<Value>, Option<String>) {
+    match result {
+        None => (AuditOutcome::Cancelled, None, None),
+        Some(Ok(summary)) if summary.cancelled => (
+            AuditOutcome::Cancelled,
+            Some(audit::replay_result(summary)),
+            None,
+        ),
+        Some(Ok(summary)) => (
+            AuditOutcome::Success,
+            Some(audit::replay_result(summary)),
+            None,
+        ),
+        Some(Err(e)) => (AuditOutcome::Failure, None, Some(e.to_string())),
+    }
+}
+
+pub const DEFAULT_JOB_LEASE: Duration = Duration::from_secs(60);
+
+struct JobObserver {
+    store: ReplayJobStore,
+    id: Uuid,
+    owner: Uuid,
+    lease: Duration,
+    /// Set once the job was cancelled or its lease lost
+    stop: AtomicBool,
+}
+
+impl JobObserver {
+    /// Renew the lease until the task is aborted, picking up cancellations
+    /// between two progress reports
+    async fn heartbeat(self: Arc<Self>) {
+        loop {
+            tokio::time::sleep(self.lease / 3).await;
+            match self
+                .store
+                .renew_lease(self.id, self.owner, self.lease)
+                .await
+            {
+                Ok(stop) => self.stop.fetch_or(stop, Ordering::SeqCst),
+                Err(e) => {
+                    tracing::warn!(error = %e, "Failed to renew replay job lease");
+                    continue;
+                },
+            };
+        }
+    }
+}
+
+impl ReplayObserver for JobObserver {
+    async fn on_progress(&self, progress: ReplayProgress) -> ReplayControl {
+        match self
+            .store
+            .update_progress(self.id, self.owner, self.lease, &progress)
+            .await
+        {
+            Ok(stop) => self.stop.fetch_or(stop, Ordering::SeqCst),
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to store replay job progress");
+                false
+            },
+        };
+
+        if self.is_cancelled() {
+            ReplayControl::Cancel
+        } else {
+            ReplayControl::Continue
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUSES: [ReplayJobStatus; 5] = [
+        ReplayJobStatus::Pending,
+        ReplayJobStatus::Running,
+        ReplayJobStatus::Completed,
+        ReplayJobStatus::Failed,
+        ReplayJobStatus::Cancelled,
+    ];
+
+    fn job(status: ReplayJobStatus, summary: Option<ReplaySummary>) -> ReplayJob {
+        let now = Utc::now();
+        ReplayJob {
+            id: Uuid::new_v4(),
+            target: ReplayJobTarget::All,
+            options: ReplayOptions::default(),
+            status,
+            total_aggregates: 0,
+            completed_aggregates: 0,
+            successful_replays: 0,
+            failed_replays: 0,
+            skipped_replays: 0,
+            cancel_requested: status == ReplayJobStatus::Cancelled,
+            summary,
+            error: None,
+            audit_entry_id: Some(Uuid::new_v4()),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn summary(cancelled: bool) -> ReplaySummary {
+        let now = Utc::now();
+        ReplaySummary {
+            total_events: 1,
+            successful_replays: 1,
+            failed_replays: 0,
+            skipped_replays: 0,
+            processed_aggregates: vec![Uuid::new_v4()],
+            results: Vec::new(),
+            cancelled,
+            started_at: now,
+            finished_at: now,
+            duration_ms: 0,
+        }
+    }
+
+    #[test]
+    fn status_round_trips_through_its_column_value() {
+        for status in STATUSES {
+            assert_eq!(ReplayJobStatus::parse(status.as_str()).unwrap(), status);
+        }
+    }
+
+    #[test]
+    fn unknown_status_is_rejected() {
+        assert!(matches!(
+            ReplayJobStatus::parse("paused"),
+            Err(ReplayJobError::InvalidStatus(status)) if status == "paused"
+        ));
+    }
+
+    #[test]
+    fn only_terminal_statuses_are_finished() {
+        let finished: Vec<_> = STATUSES.into_iter().filter(|s| s.is_finished()).collect();
+        assert_eq!(
+            finished,
+            [
+                ReplayJobStatus::Completed,
+                ReplayJobStatus::Failed,
+                ReplayJobStatus::Cancelled
+            ]
+        );
+    }
+
+    #[test]
+    fn claim_runs_the_job_unless_a_cancellation_was_requested() {
+        assert_eq!(ReplayJobStatus::claimed(false), ReplayJobStatus::Running);
+        assert_eq!(ReplayJobStatus::claimed(true), ReplayJobStatus::Cancelled);
+    }
+
+    #[test]
+    fn cancel_request_only_cancels_a_pending_job_right_away() {
+        assert_eq!(
+            ReplayJobStatus::Pending.on_cancel_request(),
+            ReplayJobStatus::Cancelled
+        );
+        for status in STATUSES.into_iter().skip(1) {
+            assert_eq!(status.on_cancel_request(), status);
+        }
+    }
+
+    #[test]
+    fn job_cancelled_before_start_has_no_summary() {
+        assert!(job(ReplayJobStatus::Cancelled, None).cancelled_before_start());
+        assert!(!job(ReplayJobStatus::Cancelled, Some(summary(true))).cancelled_before_start());
+        assert!(!job(ReplayJobStatus::Pending, None).cancelled_before_start());
+        assert!(!job(ReplayJobStatus::Running, None).cancelled_before_start());
+    }
+
+    #[test]
+    fn early_cancel_completes_the_audit_entry_as_cancelled() {
+        let (outcome, result, error) = audit_completion(None);
+        assert_eq!(outcome, AuditOutcome::Cancelled);
+        assert!(result.is_none());
+        assert!(error.is_none());
+    }
+
+    #[test]
+    fn audit_completion_follows_the_job_result() {
+        let (outcome, result, _) = audit_completion(Some(&Ok(summary(false))));
+        assert_eq!(outcome, AuditOutcome::Success);
+        assert!(result.is_some());
+
+        let (outcome, result, _) = audit_completion(Some(&Ok(summary(true))));
+        assert_eq!(outcome, AuditOutcome::Cancelled);
+        assert!(result.is_some());
+
+        let (outcome, result, error) =
+            audit_completion(Some(&Err(ReplayDeadLetterError::NotFound)));
+        assert_eq!(outcome, AuditOutcome::Failure);
+        assert!(result.is_none());
+        assert_eq!(error.as_deref(), Some("No dead letter events found"));
+    }
+}
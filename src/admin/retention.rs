@@ -0,0 +1,379 @@
+use std::collections::{BTreeSet, HashMap};
+#[cfg(feature = "archive")]
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use nats_dead_letter::DeadLetterStore;
+use serde::Serialize;
+use uuid::Uuid;
+
+#[cfg(feature = "archive")]
+use crate::admin::transfer_dead_letter::DeadLetterRecord;
+
+/// Dead letters removed by a `DeadLetterRetention`, a dead letter is removed
+/// when it matches either limit
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    /// Time between two runs of the retention task
+    pub interval: Duration,
+    /// Age after which a dead letter is removed
+    pub max_age: Option<Duration>,
+    /// Number of dead letters kept per stream, the oldest ones are removed
+    /// first
+    pub max_per_stream: Option<usize>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60 * 60),
+            max_age: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+            max_per_stream: None,
+        }
+    }
+}
+
+/// Outcome of one run of a `DeadLetterRetention`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetentionReport {
+    /// Dead letters older than `max_age`
+    pub expired: usize,
+    /// Dead letters beyond `max_per_stream` once the expired ones are left
+    /// out
+    pub over_cap: usize,
+    pub removed: Vec<Uuid>,
+    /// File the removed dead letters were archived to
+    #[cfg(feature = "archive")]
+    pub archive: Option<PathBuf>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RetentionError {
+    #[error(transparent)]
+    DeadLetterStore(Box<dyn std::error::Error + Send + Sync>),
+    /// Nothing is removed when the archive could not be written
+    #[error("Failed to archive dead letters: {0}")]
+    Archive(#[from] std::io::Error),
+}
+
+/// Removes old dead letters from a `DeadLetterStore`, optionally archiving
+/// them to gzip compressed NDJSON files first. Start it with
+/// `Feature::start_dead_letter_retention`.
+pub struct DeadLetterRetention<DLS>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+{
+    dead_letter_store: DLS,
+    policy: RetentionPolicy,
+    #[cfg(feature = "archive")]
+    archive_dir: Option<PathBuf>,
+}
+
+impl<DLS> DeadLetterRetention<DLS>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+{
+    pub fn new(dead_letter_store: DLS, policy: RetentionPolicy) -> Self {
+        Self {
+            dead_letter_store,
+            policy,
+            #[cfg(feature = "archive")]
+            archive_dir: None,
+        }
+    }
+
+    /// Write the dead letters of each run to
+    /// `{dir}/dead-letters-{timestamp}.ndjson.gz` before removing them, in
+    /// the format of the export endpoint once decompressed
+    #[cfg(feature = "archive")]
+    pub fn with_archive(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.archive_dir = Some(dir.into());
+        self
+    }
+
+    /// Apply the policy forever, a failed run is logged and started again on
+    /// the next interval
+    pub async fn run(self) {
+        loop {
+            match self.apply_once().await {
+                Ok(report) if !report.removed.is_empty() || !report.errors.is_empty() => {
+                    tracing::info!(
+                        expired = report.expired,
+                        over_cap = report.over_cap,
+                        removed = report.removed.len(),
+                        errors = report.errors.len(),
+                        "Applied dead letter retention"
+                    )
+                },
+                Ok(_) => {},
+                Err(e) => tracing::warn!("Failed to apply dead letter retention: {}", e),
+            }
+            tokio::time::sleep(self.policy.interval).await;
+        }
+    }
+
+    pub async fn apply_once(&self) -> Result<RetentionReport, RetentionError> {
+        let mut events = self
+            .dead_letter_store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .map_err(|e| RetentionError::DeadLetterStore(e.into()))?;
+        // Newest first, so that the cap keeps the most recent dead letters
+        events.sort_by_key(|e| std::cmp::Reverse(e.timestamp));
+
+        let mut report = RetentionReport::default();
+        let now = SystemTime::now();
+        let mut selected = BTreeSet::new();
+        let mut per_stream = HashMap::<&str, usize>::new();
+
+        for (index, event) in events.iter().enumerate() {
+            let age = now
+                .duration_since(SystemTime::from(event.timestamp))
+                .unwrap_or_default();
+            if self.policy.max_age.is_some_and(|max_age| age > max_age) {
+                report.expired += 1;
+                selected.insert(index);
+                continue;
+            }
+
+            let kept = per_stream.entry(event.stream.as_str()).or_default();
+            *kept += 1;
+            if self
+                .policy
+                .max_per_stream
+                .is_some_and(|max_per_stream| *kept > max_per_stream)
+            {
+                report.over_cap += 1;
+                selected.insert(index);
+            }
+        }
+
+        let removable = selected
+            .into_iter()
+            .map(|index| &events[index])
+            .collect::<Vec<_>>();
+        if removable.is_empty() {
+            return Ok(report);
+        }
+
+        #[cfg(feature = "archive")]
+        if let Some(dir) = &self.archive_dir {
+            report.archive = Some(archive(dir, &removable).await?);
+        }
+
+        for event in removable {
+            let Some(id) = event.id else {
+                report
+                    .errors
+                    .push("Skipped dead letter without an ID".to_string());
+                continue;
+            };
+
+            match self
+                .dead_letter_store
+                .remove_dead_letter(&id.to_string())
+                .await
+            {
+                Ok(_) => report.removed.push(id),
+                Err(e) => report
+                    .errors
+                    .push(format!("Failed to remove dead letter {}: {}", id, e)),
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Write the dead letters to a new gzip compressed NDJSON file in `dir`
+#[cfg(feature = "archive")]
+async fn archive(
+    dir: &std::path::Path,
+    events: &[&nats_dead_letter::DeadLetter],
+) -> std::io::Result<PathBuf> {
+    use std::io::Write;
+
+    let records = events
+        .iter()
+        .map(|event| DeadLetterRecord::from(*event))
+        .collect::<Vec<_>>();
+    let ndjson = DeadLetterRecord::to_ndjson(&records)?;
+    let path = dir.join(format!(
+        "dead-letters-{}.ndjson.gz",
+        chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+    ));
+
+    let file_path = path.clone();
+    tokio::task::spawn_blocking(move || {
+        std::fs::create_dir_all(file_path.parent().unwrap_or(std::path::Path::new(".")))?;
+        let file = std::fs::File::create_new(&file_path)?;
+        let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        encoder.write_all(ndjson.as_bytes())?;
+        // The file must be complete on disk before anything is removed
+        encoder.finish()?.sync_all()
+    })
+    .await
+    .map_err(std::io::Error::other)??;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use esrc::{
+        version::{DeserializeVersion, SerializeVersion},
+        Event,
+    };
+    use nats_dead_letter::DeadLetter;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::dead_letter::memory::InMemoryDeadLetterStore;
+    use crate::testing::TestEnvelope;
+
+    #[derive(Event, Serialize, Deserialize, Debug, Clone, SerializeVersion, DeserializeVersion)]
+    #[esrc(event(name = "User"))]
+    enum UserEvent {
+        Created { name: String },
+    }
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn dead_letter(stream: &str, age: Duration) -> DeadLetter {
+        TestEnvelope::new(&UserEvent::Created {
+            name: "Ada".to_string(),
+        })
+        .timestamp(SystemTime::now() - age)
+        .dead_letter(stream, "user-projector")
+    }
+
+    fn policy(max_age: Option<Duration>, max_per_stream: Option<usize>) -> RetentionPolicy {
+        RetentionPolicy {
+            max_age,
+            max_per_stream,
+            ..RetentionPolicy::default()
+        }
+    }
+
+    fn ids(dead_letters: &[DeadLetter]) -> Vec<Uuid> {
+        dead_letters.iter().filter_map(|e| e.id).collect()
+    }
+
+    #[tokio::test]
+    async fn removes_the_dead_letters_older_than_max_age() {
+        let expired = dead_letter("users", 3 * DAY);
+        let recent = dead_letter("users", DAY);
+        let store = InMemoryDeadLetterStore::with_dead_letters([expired.clone(), recent.clone()]);
+        let retention = DeadLetterRetention::new(store.clone(), policy(Some(2 * DAY), None));
+
+        let report = retention.apply_once().await.unwrap();
+
+        assert_eq!(report.expired, 1);
+        assert_eq!(report.over_cap, 0);
+        assert_eq!(report.removed, ids(&[expired]));
+        assert_eq!(ids(&store.dead_letters()), ids(&[recent]));
+    }
+
+    #[tokio::test]
+    async fn keeps_the_most_recent_dead_letters_of_each_stream() {
+        let oldest = dead_letter("users", 3 * DAY);
+        let older = dead_letter("users", 2 * DAY);
+        let newest = dead_letter("users", DAY);
+        let other_stream = dead_letter("orders", 4 * DAY);
+        let store = InMemoryDeadLetterStore::with_dead_letters([
+            older.clone(),
+            newest.clone(),
+            oldest.clone(),
+            other_stream.clone(),
+        ]);
+        let retention = DeadLetterRetention::new(store.clone(), policy(None, Some(1)));
+
+        let report = retention.apply_once().await.unwrap();
+
+        assert_eq!(report.expired, 0);
+        assert_eq!(report.over_cap, 2);
+        let mut removed = report.removed.clone();
+        removed.sort();
+        let mut expected = ids(&[oldest, older]);
+        expected.sort();
+        assert_eq!(removed, expected);
+        assert_eq!(ids(&store.dead_letters()), ids(&[other_stream, newest]));
+    }
+
+    #[tokio::test]
+    async fn expired_dead_letters_do_not_count_towards_the_cap() {
+        let expired = dead_letter("users", 3 * DAY);
+        let older = dead_letter("users", DAY);
+        let newest = dead_letter("users", Duration::ZERO);
+        let store =
+            InMemoryDeadLetterStore::with_dead_letters([expired.clone(), older, newest.clone()]);
+        let retention = DeadLetterRetention::new(store.clone(), policy(Some(2 * DAY), Some(1)));
+
+        let report = retention.apply_once().await.unwrap();
+
+        assert_eq!(report.expired, 1);
+        assert_eq!(report.over_cap, 1);
+        assert_eq!(ids(&store.dead_letters()), ids(&[newest]));
+    }
+
+    #[tokio::test]
+    async fn reports_the_dead_letters_that_could_not_be_removed() {
+        let store = InMemoryDeadLetterStore::with_dead_letters([dead_letter("users", 3 * DAY)]);
+        store.fail_on_remove(true);
+        let retention = DeadLetterRetention::new(store.clone(), policy(Some(DAY), None));
+
+        let report = retention.apply_once().await.unwrap();
+
+        assert_eq!(report.expired, 1);
+        assert!(report.removed.is_empty());
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[cfg(feature = "archive")]
+    #[tokio::test]
+    async fn archives_the_dead_letters_before_removing_them() {
+        use std::io::Read;
+
+        let dir = std::env::temp_dir().join(format!("esrc-retention-{}", Uuid::now_v7()));
+        let expired = dead_letter("users", 3 * DAY);
+        let store = InMemoryDeadLetterStore::with_dead_letters([expired.clone()]);
+        let retention =
+            DeadLetterRetention::new(store.clone(), policy(Some(DAY), None)).with_archive(&dir);
+
+        let report = retention.apply_once().await.unwrap();
+
+        let mut ndjson = String::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(report.archive.unwrap()).unwrap())
+            .read_to_string(&mut ndjson)
+            .unwrap();
+        let records = DeadLetterRecord::from_ndjson(&ndjson).unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .filter_map(|record| record.id)
+                .collect::<Vec<_>>(),
+            ids(&[expired])
+        );
+        assert!(store.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "archive")]
+    #[tokio::test]
+    async fn keeps_the_dead_letters_when_the_archive_fails() {
+        // A file where the archive directory should be
+        let dir = std::env::temp_dir().join(format!("esrc-retention-{}", Uuid::now_v7()));
+        std::fs::write(&dir, b"").unwrap();
+        let store = InMemoryDeadLetterStore::with_dead_letters([dead_letter("users", 3 * DAY)]);
+        let retention = DeadLetterRetention::new(store.clone(), policy(Some(DAY), None))
+            .with_archive(dir.join("archive"));
+
+        let error = retention.apply_once().await.unwrap_err();
+
+        assert!(matches!(error, RetentionError::Archive(_)));
+        assert_eq!(store.len(), 1);
+        std::fs::remove_file(dir).unwrap();
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use nats_dead_letter::{DeadLetter, DeadLetterStore};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::admin::filter::DeadLetterFilter;
+use crate::dead_letter::decoder::DeadLetterDecoder;
+
+/// Portable copy of a dead letter, one per line of an NDJSON export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetterRecord {
+    pub id: Option<Uuid>,
+    pub aggregate_id: Option<Uuid>,
+    pub prefix: Option<String>,
+    pub stream: String,
+    pub consumer: String,
+    pub subject: String,
+    pub stream_sequence: u64,
+    pub delivery_count: u64,
+    pub timestamp: DateTime<Utc>,
+    /// Values of each header. A dead letter keeps a single value per header,
+    /// so an export holds one value per name and an import keeps the last one.
+    pub headers: Option<HashMap<String, Vec<String>>>,
+    /// Base64 of the original payload, so that non JSON payloads survive
+    pub payload: String,
+}
+
+impl From<&DeadLetter> for DeadLetterRecord {
+    fn from(dead_letter: &DeadLetter) -> Self {
+        Self {
+            id: dead_letter.id,
+            aggregate_id: dead_letter.aggregate_id,
+            prefix: dead_letter.prefix.clone(),
+            stream: dead_letter.stream.clone(),
+            consumer: dead_letter.consumer.clone(),
+            subject: dead_letter.subject.clone(),
+            stream_sequence: dead_letter.stream_sequence,
+            delivery_count: dead_letter.delivery_count as u64,
+            timestamp: SystemTime::from(dead_letter.timestamp).into(),
+            // Decoded like the headers of a replay, a dead letter without
+            // headers is exported without them
+            headers: DeadLetterDecoder::headers(dead_letter).ok().map(|headers| {
+                headers
+                    .iter()
+                    .map(|(name, values)| {
+                        (
+                            name.to_string(),
+                            values
+                                .iter()
+                                .map(|value| value.as_str().to_string())
+                                .collect(),
+                        )
+                    })
+                    .collect()
+            }),
+            payload: STANDARD.encode(&dead_letter.payload),
+        }
+    }
+}
+
+impl TryFrom<DeadLetterRecord> for DeadLetter {
+    type Error = base64::DecodeError;
+
+    fn try_from(record: DeadLetterRecord) -> Result<Self, Self::Error> {
+        Ok(DeadLetter {
+            id: record.id,
+            aggregate_id: record.aggregate_id,
+            prefix: record.prefix,
+            stream: record.stream,
+            consumer: record.consumer,
+            subject: record.subject,
+            stream_sequence: record.stream_sequence,
+            delivery_count: record.delivery_count as _,
+            timestamp: SystemTime::from(record.timestamp).into(),
+            // A dead letter holds one value per header, the last one is kept
+            // as it is when the headers are decoded for a replay
+            headers: record.headers.map(|headers| {
+                headers
+                    .into_iter()
+                    .filter_map(|(name, values)| Some((name, values.into_iter().last()?)))
+                    .collect()
+            }),
+            payload: STANDARD.decode(record.payload)?,
+        })
+    }
+}
+
+/// Namespace of the IDs given to imported records that have none
+const IMPORT_ID_NAMESPACE: Uuid = Uuid::from_u128(0xcf869d3d_6cbd_4222_82fd_716cfc84f66b);
+
+impl DeadLetterRecord {
+    /// One JSON document per line, each terminated by a newline
+    pub fn to_ndjson(records: &[DeadLetterRecord]) -> Result<String, serde_json::Error> {
+        let mut ndjson = String::new();
+        for record in records {
+            ndjson.push_str(&serde_json::to_string(record)?);
+            ndjson.push('\n');
+        }
+
+        Ok(ndjson)
+    }
+
+    /// Parse an NDJSON export, blank lines are ignored
+    pub fn from_ndjson(ndjson: &str) -> Result<Vec<DeadLetterRecord>, TransferDeadLetterError> {
+        ndjson
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line).map_err(|source| TransferDeadLetterError::InvalidLine {
+                    line: index + 1,
+                    source,
+                })
+            })
+            .collect()
+    }
+
+    /// ID of the record, or one derived from the consumer and the stream
+    /// sequence of the dead letter when it has none, so that importing the
+    /// same record twice gives it the same ID
+    fn import_id(&self) -> Uuid {
+        self.id.unwrap_or_else(|| {
+            let key = format!(
+                "{}\n{}\n{}",
+                self.stream, self.consumer, self.stream_sequence
+            );
+            Uuid::new_v5(&IMPORT_ID_NAMESPACE, key.as_bytes())
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportSummary {
+    pub total_records: usize,
+    pub imported: Vec<Uuid>,
+    /// Records whose ID is already present in the store
+    pub skipped: Vec<Uuid>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransferDeadLetterError {
+    #[error("Invalid dead letter record on line {line}: {source}")]
+    InvalidLine {
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error(transparent)]
+    DeadLetterStore(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Export of dead letters to `DeadLetterRecord`s and import back into a
+/// `DeadLetterStore`, e.g. to reproduce production failures in staging
+#[derive(Clone)]
+pub struct TransferDeadLetter<DLS>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+{
+    dead_letter_store: DLS,
+}
+
+impl<DLS> TransferDeadLetter<DLS>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+{
+    pub fn new(dead_letter_store: DLS) -> Self {
+        Self { dead_letter_store }
+    }
+
+    /// Dead letters matching the filter, oldest first
+    pub async fn export(
+        &self,
+        filter: &DeadLetterFilter,
+    ) -> Result<Vec<DeadLetterRecord>, TransferDeadLetterError> {
+        let mut events = self
+            .dead_letter_store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .map_err(|e| TransferDeadLetterError::DeadLetterStore(e.into()))?
+            .into_iter()
+            .filter(|e| filter.matches(e))
+            .collect::<Vec<_>>();
+        events.sort_by_key(|e| e.timestamp);
+
+        Ok(events.iter().map(DeadLetterRecord::from).collect())
+    }
+
+    /// Store the records, skipping the ones whose ID is already present so
+    /// that importing the same export twice is harmless
+    pub async fn import(
+        &self,
+        records: Vec<DeadLetterRecord>,
+    ) -> Result<ImportSummary, TransferDeadLetterError> {
+        let mut existing = self
+            .dead_letter_store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .map_err(|e| TransferDeadLetterError::DeadLetterStore(e.into()))?
+            .into_iter()
+            .filter_map(|e| e.id)
+            .collect::<HashSet<_>>();
+
+        let mut summary = ImportSummary {
+            total_records: records.len(),
+            imported: Vec::new(),
+            skipped: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        for record in records {
+            // Records without an ID get one, so that they can be replayed
+            // and purged like any other dead letter
+            let id = record.import_id();
+            // Also skips an ID repeated within the records
+            if !existing.insert(id) {
+                summary.skipped.push(id);
+                continue;
+            }
+
+            let dead_letter = match DeadLetter::try_from(DeadLetterRecord {
+                id: Some(id),
+                ..record
+            }) {
+                Ok(dead_letter) => dead_letter,
+                Err(e) => {
+                    summary
+                        .errors
+                        .push(format!("Invalid payload of dead letter {}: {}", id, e));
+                    continue;
+                },
+            };
+
+            match self.dead_letter_store.store_dead_letter(dead_letter).await {
+                Ok(_) => summary.imported.push(id),
+                Err(e) => summary
+                    .errors
+                    .push(format!("Failed to import dead letter {}: {}", id, e)),
+            }
+        }
+
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use esrc::{
+        version::{DeserializeVersion, SerializeVersion},
+        Event,
+    };
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::dead_letter::memory::InMemoryDeadLetterStore;
+    use crate::testing::TestEnvelope;
+
+    #[derive(Event, Serialize, Deserialize, Debug, Clone, SerializeVersion, DeserializeVersion)]
+    #[esrc(event(name = "User"))]
+    enum UserEvent {
+        Created { name: String },
+    }
+
+    fn record() -> DeadLetterRecord {
+        let dead_letter = TestEnvelope::new(&UserEvent::Created {
+            name: "Ada".to_string(),
+        })
+        .metadata("Trace-Id", "abc")
+        .dead_letter("users", "user-projector");
+
+        DeadLetterRecord::from(&dead_letter)
+    }
+
+    #[test]
+    fn ndjson_round_trips_records() {
+        let records = vec![record(), record()];
+
+        let ndjson = DeadLetterRecord::to_ndjson(&records).unwrap();
+        let parsed = DeadLetterRecord::from_ndjson(&format!("\n{}\n", ndjson)).unwrap();
+
+        assert_eq!(ndjson.lines().count(), 2);
+        assert_eq!(
+            parsed.iter().map(|record| record.id).collect::<Vec<_>>(),
+            records.iter().map(|record| record.id).collect::<Vec<_>>()
+        );
+        assert_eq!(parsed[0].payload, records[0].payload);
+    }
+
+    #[test]
+    fn ndjson_reports_the_invalid_line() {
+        let ndjson = format!("{}\nnot json\n", serde_json::to_string(&record()).unwrap());
+
+        let error = DeadLetterRecord::from_ndjson(&ndjson).unwrap_err();
+
+        assert!(matches!(
+            error,
+            TransferDeadLetterError::InvalidLine { line: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn record_holds_the_header_value() {
+        let record = record();
+
+        let headers = record.headers.unwrap();
+        assert_eq!(headers["Trace-Id"], vec!["abc".to_string()]);
+    }
+
+    #[test]
+    fn dead_letter_keeps_the_last_header_value() {
+        let mut record = record();
+        record.headers = Some(HashMap::from([(
+            "Trace-Id".to_string(),
+            vec!["abc".to_string(), "def".to_string()],
+        )]));
+
+        let dead_letter = DeadLetter::try_from(record).unwrap();
+
+        let headers = DeadLetterDecoder::headers(&dead_letter).unwrap();
+        assert_eq!(headers.get("Trace-Id").unwrap().as_str(), "def");
+    }
+
+    #[tokio::test]
+    async fn import_skips_existing_and_repeated_ids() {
+        let existing = record();
+        let store = InMemoryDeadLetterStore::new();
+        store.insert(DeadLetter::try_from(existing.clone()).unwrap());
+        let transfer = TransferDeadLetter::new(store.clone());
+        let new = record();
+
+        let summary = transfer
+            .import(vec![existing.clone(), new.clone(), new.clone()])
+            .await
+            .unwrap();
+
+        assert_eq!(summary.total_records, 3);
+        assert_eq!(summary.imported, vec![new.id.unwrap()]);
+        assert_eq!(summary.skipped, vec![existing.id.unwrap(), new.id.unwrap()]);
+        assert_eq!(store.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn import_gives_an_id_to_records_without_one() {
+        let store = InMemoryDeadLetterStore::new();
+        let transfer = TransferDeadLetter::new(store.clone());
+
+        let summary = transfer
+            .import(vec![DeadLetterRecord {
+                id: None,
+                ..record()
+            }])
+            .await
+            .unwrap();
+
+        assert_eq!(summary.imported.len(), 1);
+        assert_eq!(store.dead_letters()[0].id, Some(summary.imported[0]));
+    }
+
+    #[tokio::test]
+    async fn import_gives_the_same_id_to_the_same_record() {
+        let store = InMemoryDeadLetterStore::new();
+        let transfer = TransferDeadLetter::new(store.clone());
+        let record = DeadLetterRecord {
+            id: None,
+            ..record()
+        };
+        let other_consumer = DeadLetterRecord {
+            consumer: "user-audit".to_string(),
+            ..record.clone()
+        };
+
+        let first = transfer.import(vec![record.clone()]).await.unwrap();
+        let second = transfer.import(vec![record, other_consumer]).await.unwrap();
+
+        assert_eq!(second.skipped, first.imported);
+        assert_eq!(second.imported.len(), 1);
+        assert_ne!(second.imported, first.imported);
+        assert_eq!(store.len(), 2);
+    }
+}
@@ -0,0 +1,183 @@
+use reqwest::{header::CONTENT_TYPE, Method, RequestBuilder, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// Problem details returned by the admin API
+    #[error("{status}: {detail}")]
+    Api { status: StatusCode, detail: String },
+}
+
+/// Thin client of the admin HTTP API, every response is kept as JSON
+pub struct AdminClient {
+    http: reqwest::Client,
+    /// Routes of the admin handler
+    base_url: String,
+    /// Routes shared by every admin handler, e.g. the automations
+    admin_url: String,
+    token: Option<String>,
+}
+
+impl AdminClient {
+    /// `base_url` is the endpoint given to `AdminHandler::setup_router`,
+    /// e.g. `http://localhost:3001/api/v1`, and `handler` the name given to
+    /// `AdminHandler::with_name`
+    pub fn new(base_url: &str, handler: Option<&str>, token: Option<String>) -> Self {
+        let admin_url = format!("{}/admin", base_url.trim_end_matches('/'));
+        Self {
+            http: reqwest::Client::new(),
+            base_url: match handler {
+                Some(handler) => format!("{}/{}", admin_url, handler),
+                None => admin_url.clone(),
+            },
+            admin_url,
+            token,
+        }
+    }
+
+    pub async fn get<Q>(&self, path: &str, query: &Q) -> Result<Value, ClientError>
+    where
+        Q: Serialize + ?Sized,
+    {
+        self.send(self.request(Method::GET, path).query(query))
+            .await
+    }
+
+    /// GET a route mounted once under `{endpoint}/admin` whatever the name of
+    /// the handler, like the automations of the dashboard
+    pub async fn get_shared<Q>(&self, path: &str, query: &Q) -> Result<Value, ClientError>
+    where
+        Q: Serialize + ?Sized,
+    {
+        self.send(
+            self.authorize(self.http.get(format!("{}/{}", self.admin_url, path)))
+                .query(query),
+        )
+        .await
+    }
+
+    pub async fn post<Q, B>(
+        &self,
+        path: &str,
+        query: &Q,
+        body: Option<&B>,
+    ) -> Result<Value, ClientError>
+    where
+        Q: Serialize + ?Sized,
+        B: Serialize + ?Sized,
+    {
+        let mut request = self.request(Method::POST, path).query(query);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        self.send(request).await
+    }
+
+    pub async fn patch<Q>(&self, path: &str, query: &Q) -> Result<Value, ClientError>
+    where
+        Q: Serialize + ?Sized,
+    {
+        self.send(self.request(Method::PATCH, path).query(query))
+            .await
+    }
+
+    pub async fn delete<B>(&self, path: &str, body: &B) -> Result<Value, ClientError>
+    where
+        B: Serialize + ?Sized,
+    {
+        self.send(self.request(Method::DELETE, path).json(body))
+            .await
+    }
+
+    /// Newline delimited JSON export of the dead letters matching the query
+    pub async fn export<Q>(&self, query: &Q) -> Result<String, ClientError>
+    where
+        Q: Serialize + ?Sized,
+    {
+        let response = self
+            .request(Method::GET, "dead-letters/export")
+            .query(query)
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            return Err(Self::api_error(status, &body));
+        }
+
+        Ok(body)
+    }
+
+    pub async fn import(&self, ndjson: String) -> Result<Value, ClientError> {
+        self.send(
+            self.request(Method::POST, "dead-letters/import")
+                .header(CONTENT_TYPE, "application/x-ndjson")
+                .body(ndjson),
+        )
+        .await
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.authorize(
+            self.http
+                .request(method, format!("{}/{}", self.base_url, path)),
+        )
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Value, ClientError> {
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            return Err(Self::api_error(status, &body));
+        }
+
+        Ok(serde_json::from_str(&body).unwrap_or(Value::Null))
+    }
+
+    /// Detail of the problem details returned by the admin API
+    fn api_error(status: StatusCode, body: &str) -> ClientError {
+        let body = serde_json::from_str::<Value>(body).unwrap_or(Value::Null);
+        let detail = body
+            .get("detail")
+            .or_else(|| body.get("title"))
+            .and_then(Value::as_str)
+            .unwrap_or_else(|| status.canonical_reason().unwrap_or("Unknown error"))
+            .to_string();
+
+        ClientError::Api { status, detail }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_handler_routes_are_under_its_name() {
+        let client = AdminClient::new("http://localhost:3001/api/v1/", Some("orders"), None);
+
+        assert_eq!(client.base_url, "http://localhost:3001/api/v1/admin/orders");
+        assert_eq!(client.admin_url, "http://localhost:3001/api/v1/admin");
+    }
+
+    #[test]
+    fn unnamed_handler_routes_are_the_shared_ones() {
+        let client = AdminClient::new("http://localhost:3001/api/v1", None, None);
+
+        assert_eq!(client.base_url, "http://localhost:3001/api/v1/admin");
+        assert_eq!(client.admin_url, client.base_url);
+    }
+}
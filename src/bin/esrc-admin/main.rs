@@ -0,0 +1,375 @@
+//! Command line client of the esrc-ext admin HTTP API
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::client::AdminClient;
+use crate::output::{
+    OutputFormat, AUDIT_COLUMNS, AUTOMATION_COLUMNS, DEAD_LETTER_COLUMNS, IMPORT_COLUMNS,
+    PURGE_COLUMNS, REBUILD_COLUMNS, REPLAY_JOB_COLUMNS, REPLAY_SUMMARY_COLUMNS, STATS_COLUMNS,
+};
+
+mod client;
+mod output;
+
+#[derive(Parser)]
+#[command(
+    name = "esrc-admin",
+    version,
+    about = "Manage the dead letters of an esrc-ext service"
+)]
+struct Cli {
+    /// Endpoint the admin routes are mounted on
+    #[arg(
+        long,
+        env = "ESRC_ADMIN_URL",
+        default_value = "http://localhost:3001/api/v1"
+    )]
+    url: String,
+    /// Name of the admin handler, see `AdminHandler::with_name`
+    #[arg(long, env = "ESRC_ADMIN_HANDLER")]
+    handler: Option<String>,
+    /// Bearer token sent to the admin routes
+    #[arg(long, env = "ESRC_ADMIN_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    #[arg(long, short, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the dead letters matching a filter, oldest first
+    List(FilterArgs),
+    /// Count the dead letters matching a filter, grouped by one field
+    Stats {
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Grouping shown in table output, json output shows all of them
+        #[arg(long, value_parser = ["stream", "consumer", "event-name", "error", "error-type", "hour"], default_value = "error")]
+        by: String,
+    },
+    /// Show a dead letter with its headers and payload
+    Inspect { id: Uuid },
+    /// Start a job replaying the dead letters of one aggregate
+    Replay {
+        aggregate_id: Uuid,
+        #[command(flatten)]
+        options: ReplayArgs,
+    },
+    /// Start a job replaying the dead letters of every aggregate
+    ReplayAll {
+        #[command(flatten)]
+        options: ReplayArgs,
+    },
+    /// Replay a corrected version of a dead letter, the original is kept in
+    /// the edit log of the server
+    Edit {
+        id: Uuid,
+        /// JSON file replacing the payload of the dead letter
+        #[arg(long)]
+        payload: Option<PathBuf>,
+        /// Header added to the dead letter, as `name=value`
+        #[arg(long = "header", value_parser = parse_header)]
+        headers: Vec<(String, String)>,
+        #[arg(long)]
+        reason: String,
+        #[arg(long, value_parser = ["continue-on-failure", "halt-aggregate-on-failure"])]
+        policy: Option<String>,
+    },
+    /// Discard a dead letter
+    Purge {
+        id: Uuid,
+        #[arg(long)]
+        reason: String,
+    },
+    /// Discard every dead letter matching a filter
+    PurgeMatching {
+        #[command(flatten)]
+        filter: FilterArgs,
+        #[arg(long)]
+        reason: String,
+    },
+    /// Write the dead letters matching a filter as newline delimited JSON,
+    /// with their payload in base64
+    Export {
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Write to a file instead of stdout
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Store the dead letters of an export, the ones already present are skipped
+    Import { file: PathBuf },
+    /// Show the progress of a replay job
+    Job {
+        id: Uuid,
+        /// Wait until the job is finished
+        #[arg(long)]
+        wait: bool,
+    },
+    /// Cancel a replay job
+    CancelJob { id: Uuid },
+    /// Rebuild a view from the events of its stream, it must be registered
+    /// with `AdminHandler::with_views` on the server
+    Rebuild {
+        view: String,
+        /// Only rebuild the rows of this aggregate
+        #[arg(long)]
+        aggregate_id: Option<Uuid>,
+    },
+    /// Show the admin actions, most recent first
+    Audit(AuditArgs),
+    /// Show the status of the automations, needs the `dashboard` feature on the server
+    Automations,
+}
+
+#[derive(Args, Serialize)]
+struct FilterArgs {
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<String>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    consumer: Option<String>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aggregate_id: Option<Uuid>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subject: Option<String>,
+}
+
+#[derive(Args, Serialize)]
+struct AuditArgs {
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actor: Option<String>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<i64>,
+}
+
+#[derive(Args, Serialize)]
+struct ReplayArgs {
+    #[arg(long, value_parser = ["continue-on-failure", "halt-aggregate-on-failure"])]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    policy: Option<String>,
+    /// Maximum number of aggregates replayed at the same time
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    concurrency: Option<usize>,
+    #[arg(long, value_parser = ["in-process", "republish"])]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    strategy: Option<String>,
+    /// Wait until the replay job is finished
+    #[arg(long)]
+    #[serde(skip)]
+    wait: bool,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let client = AdminClient::new(&cli.url, cli.handler.as_deref(), cli.token);
+
+    if let Err(e) = run(&client, cli.output, cli.command).await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(
+    client: &AdminClient,
+    format: OutputFormat,
+    command: Command,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let no_query: &[(&str, &str)] = &[];
+
+    match command {
+        Command::List(filter) => {
+            let dead_letters = client.get("dead-letters", &filter).await?;
+            output::print(format, &dead_letters, DEAD_LETTER_COLUMNS)?;
+        },
+        Command::Stats { filter, by } => {
+            let stats = client.get("dead-letters/stats", &filter).await?;
+            match format {
+                OutputFormat::Json => output::print(format, &stats, STATS_COLUMNS)?,
+                OutputFormat::Table => {
+                    let buckets = &stats[format!("by_{}", by.replace('-', "_"))];
+                    output::print(format, buckets, STATS_COLUMNS)?;
+                    println!("\n{} dead letter(s)", stats["total"]);
+                },
+            }
+        },
+        Command::Inspect { id } => {
+            let dead_letter = client
+                .get(&format!("dead-letters/{}", id), no_query)
+                .await?;
+            // Headers and payload do not fit in a table, a dead letter is
+            // always shown in full
+            output::print(OutputFormat::Json, &dead_letter, DEAD_LETTER_COLUMNS)?;
+        },
+        Command::Replay {
+            aggregate_id,
+            options,
+        } => {
+            let job = client
+                .patch(&format!("dead-letters/replay/{}", aggregate_id), &options)
+                .await?;
+            show_job(client, format, job, options.wait).await?;
+        },
+        Command::ReplayAll { options } => {
+            let job = client
+                .post("dead-letters/replay-all", no_query, Some(&options))
+                .await?;
+            show_job(client, format, job, options.wait).await?;
+        },
+        Command::Edit {
+            id,
+            payload,
+            headers,
+            reason,
+            policy,
+        } => {
+            let payload = payload
+                .map(|path| -> Result<Value, Box<dyn std::error::Error>> {
+                    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+                })
+                .transpose()?;
+            let headers =
+                (!headers.is_empty()).then(|| headers.into_iter().collect::<HashMap<_, _>>());
+            let body = json!({ "payload": payload, "headers": headers, "reason": reason });
+            let query = policy
+                .into_iter()
+                .map(|policy| ("policy", policy))
+                .collect::<Vec<_>>();
+            let summary = client
+                .post(
+                    &format!("dead-letters/{}/edit-and-replay", id),
+                    &query,
+                    Some(&body),
+                )
+                .await?;
+            output::print(format, &summary, REPLAY_SUMMARY_COLUMNS)?;
+        },
+        Command::Purge { id, reason } => {
+            let summary = client
+                .delete(
+                    &format!("dead-letters/{}", id),
+                    &json!({ "reason": reason }),
+                )
+                .await?;
+            output::print(format, &summary, PURGE_COLUMNS)?;
+        },
+        Command::PurgeMatching { filter, reason } => {
+            let mut body = serde_json::to_value(&filter)?;
+            body["reason"] = json!(reason);
+            let summary = client
+                .post("dead-letters/purge", no_query, Some(&body))
+                .await?;
+            output::print(format, &summary, PURGE_COLUMNS)?;
+        },
+        Command::Export { filter, file } => {
+            let ndjson = client.export(&filter).await?;
+            match file {
+                Some(path) => {
+                    std::fs::write(&path, &ndjson)?;
+                    eprintln!(
+                        "Exported {} dead letter(s) to {}",
+                        ndjson.lines().count(),
+                        path.display()
+                    );
+                },
+                None => std::io::stdout().write_all(ndjson.as_bytes())?,
+            }
+        },
+        Command::Import { file } => {
+            let ndjson = std::fs::read_to_string(file)?;
+            let summary = client.import(ndjson).await?;
+            output::print(format, &summary, IMPORT_COLUMNS)?;
+        },
+        Command::Job { id, wait } => {
+            let job = client.get(&format!("replay-jobs/{}", id), no_query).await?;
+            show_job(client, format, job, wait).await?;
+        },
+        Command::CancelJob { id } => {
+            let job = client
+                .post(
+                    &format!("replay-jobs/{}/cancel", id),
+                    no_query,
+                    None::<&Value>,
+                )
+                .await?;
+            output::print(format, &job, REPLAY_JOB_COLUMNS)?;
+        },
+        Command::Rebuild { view, aggregate_id } => {
+            let body = json!({ "aggregate_id": aggregate_id });
+            let summary = client
+                .post(&format!("views/{}/rebuild", view), no_query, Some(&body))
+                .await?;
+            output::print(format, &summary, REBUILD_COLUMNS)?;
+        },
+        Command::Audit(filter) => {
+            let entries = client.get("audit", &filter).await?;
+            output::print(format, &entries, AUDIT_COLUMNS)?;
+        },
+        Command::Automations => {
+            // The automations are shared by every admin handler
+            let automations = client.get_shared("automations", no_query).await?;
+            output::print(format, &automations, AUTOMATION_COLUMNS)?;
+        },
+    }
+
+    Ok(())
+}
+
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    header
+        .split_once('=')
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected name=value, got {}", header))
+}
+
+/// Print a replay job, polling it until it is finished when `wait` is set
+async fn show_job(
+    client: &AdminClient,
+    format: OutputFormat,
+    mut job: Value,
+    wait: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let is_finished = |job: &Value| {
+        matches!(
+            job["status"].as_str(),
+            Some("completed" | "failed" | "cancelled")
+        )
+    };
+    let no_query: &[(&str, &str)] = &[];
+
+    while wait && !is_finished(&job) {
+        eprintln!(
+            "Replay job {} {}: {}/{} aggregates",
+            job["id"].as_str().unwrap_or_default(),
+            job["status"].as_str().unwrap_or_default(),
+            job["completed_aggregates"],
+            job["total_aggregates"],
+        );
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let id = job["id"].as_str().unwrap_or_default().to_string();
+        job = client.get(&format!("replay-jobs/{}", id), no_query).await?;
+    }
+
+    output::print(format, &job, REPLAY_JOB_COLUMNS)?;
+    Ok(())
+}
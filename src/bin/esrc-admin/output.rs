@@ -0,0 +1,168 @@
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+/// Columns shown in table output, as `(header, JSON pointer)`
+pub type Columns = &'static [(&'static str, &'static str)];
+
+pub const DEAD_LETTER_COLUMNS: Columns = &[
+    ("ID", "/id"),
+    ("TIMESTAMP", "/timestamp"),
+    ("STREAM", "/stream"),
+    ("CONSUMER", "/consumer"),
+    ("EVENT", "/event_name"),
+    ("AGGREGATE", "/aggregate_id"),
+    ("DELIVERIES", "/delivery_count"),
+    ("ERROR", "/failure/error"),
+];
+
+pub const STATS_COLUMNS: Columns = &[
+    ("KEY", "/key"),
+    ("COUNT", "/count"),
+    ("OLDEST", "/oldest"),
+    ("NEWEST", "/newest"),
+];
+
+pub const REPLAY_JOB_COLUMNS: Columns = &[
+    ("ID", "/id"),
+    ("TARGET", "/target/type"),
+    ("AGGREGATE", "/target/aggregate_id"),
+    ("STATUS", "/status"),
+    ("AGGREGATES", "/total_aggregates"),
+    ("DONE", "/completed_aggregates"),
+    ("REPLAYED", "/successful_replays"),
+    ("FAILED", "/failed_replays"),
+    ("SKIPPED", "/skipped_replays"),
+    ("ERROR", "/error"),
+];
+
+pub const REPLAY_SUMMARY_COLUMNS: Columns = &[
+    ("EVENTS", "/total_events"),
+    ("REPLAYED", "/successful_replays"),
+    ("FAILED", "/failed_replays"),
+    ("SKIPPED", "/skipped_replays"),
+    ("DURATION MS", "/duration_ms"),
+];
+
+pub const PURGE_COLUMNS: Columns = &[
+    ("REASON", "/reason"),
+    ("TOTAL", "/total_events"),
+    ("PURGED", "/purged"),
+    ("FAILED", "/failed"),
+    ("SKIPPED", "/skipped"),
+];
+
+pub const IMPORT_COLUMNS: Columns = &[
+    ("TOTAL", "/total_records"),
+    ("IMPORTED", "/imported"),
+    ("SKIPPED", "/skipped"),
+    ("ERRORS", "/errors"),
+];
+
+pub const REBUILD_COLUMNS: Columns = &[
+    ("VIEW", "/view"),
+    ("AGGREGATE", "/aggregate_id"),
+    ("EVENTS", "/total_events"),
+    ("PROJECTED", "/projected"),
+    ("ERRORS", "/errors"),
+    ("DURATION MS", "/duration_ms"),
+];
+
+pub const AUDIT_COLUMNS: Columns = &[
+    ("CREATED", "/created_at"),
+    ("ACTOR", "/actor"),
+    ("COMMAND", "/command"),
+    ("OUTCOME", "/outcome"),
+    ("DURATION MS", "/duration_ms"),
+    ("ERROR", "/error"),
+];
+
+pub const AUTOMATION_COLUMNS: Columns = &[
+    ("FEATURE", "/feature_name"),
+    ("KIND", "/kind"),
+    ("STATUS", "/status"),
+    ("STARTED", "/started_at"),
+    ("UPDATED", "/updated_at"),
+    ("ERROR", "/error"),
+];
+
+/// Print a response, a JSON array is shown as one row per element
+pub fn print(format: OutputFormat, value: &Value, columns: Columns) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut stdout, value)?;
+            writeln!(stdout)
+        },
+        OutputFormat::Table => {
+            let rows = match value {
+                Value::Array(rows) => rows.iter().collect(),
+                row => vec![row],
+            };
+            write_table(&mut stdout, &rows, columns)
+        },
+    }
+}
+
+fn write_table(out: &mut impl Write, rows: &[&Value], columns: Columns) -> io::Result<()> {
+    let cells = rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|(_, pointer)| cell(row.pointer(pointer)))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let widths = columns
+        .iter()
+        .enumerate()
+        .map(|(i, (header, _))| {
+            cells
+                .iter()
+                .map(|row| row[i].chars().count())
+                .chain([header.len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let headers = columns.iter().map(|(header, _)| header.to_string());
+    write_row(out, headers, &widths)?;
+    for row in cells {
+        write_row(out, row.into_iter(), &widths)?;
+    }
+
+    Ok(())
+}
+
+fn write_row(
+    out: &mut impl Write,
+    cells: impl Iterator<Item = String>,
+    widths: &[usize],
+) -> io::Result<()> {
+    let line = cells
+        .zip(widths)
+        .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+        .collect::<Vec<_>>()
+        .join("  ");
+    writeln!(out, "{}", line.trim_end())
+}
+
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => "-".to_string(),
+        Some(Value::String(value)) => value.clone(),
+        Some(Value::Array(values)) => values.len().to_string(),
+        Some(value) => value.to_string(),
+    }
+}
@@ -0,0 +1,464 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use nats_dead_letter::{DeadLetter, DeadLetterStore};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::admin::dead_letter_stats::error_fingerprint;
+use crate::dead_letter::failure::{
+    failure_keys, DeadLetterFailure, DeadLetterFailureLog, FailureKey,
+};
+
+/// Reason a `DeadLetterWatcher` calls its notifier
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum DeadLetterAlert {
+    /// The dead letters of a consumer reached its threshold, sent again only
+    /// after the count went back below it
+    ThresholdExceeded {
+        stream: String,
+        consumer: String,
+        count: usize,
+        threshold: usize,
+    },
+    /// A dead letter was caused by an error not seen before by the watcher
+    NewError {
+        stream: String,
+        consumer: String,
+        aggregate_id: Uuid,
+        fingerprint: String,
+        error: String,
+        error_type: Option<String>,
+    },
+}
+
+pub type NotifierError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Receives the alerts of a `DeadLetterWatcher`, object safe so that the
+/// watcher can hold any implementation
+pub trait DeadLetterNotifier: Send + Sync {
+    fn notify<'a>(&'a self, alert: &'a DeadLetterAlert)
+        -> BoxFuture<'a, Result<(), NotifierError>>;
+}
+
+/// Posts every alert as JSON to a URL, e.g. a Slack or Teams incoming webhook
+/// behind a small adapter
+#[cfg(feature = "webhook")]
+#[derive(Clone)]
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[cfg(feature = "webhook")]
+impl WebhookNotifier {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into(),
+        }
+    }
+}
+
+#[cfg(feature = "webhook")]
+impl DeadLetterNotifier for WebhookNotifier {
+    fn notify<'a>(
+        &'a self,
+        alert: &'a DeadLetterAlert,
+    ) -> BoxFuture<'a, Result<(), NotifierError>> {
+        Box::pin(async move {
+            self.client
+                .post(&self.url)
+                .json(alert)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeadLetterWatchError {
+    #[error(transparent)]
+    DeadLetterStore(Box<dyn std::error::Error + Send + Sync>),
+    #[error("Failure log error: {0}")]
+    FailureLog(#[from] sqlx::Error),
+}
+
+/// Polls a `DeadLetterStore` and notifies when the dead letters of a consumer
+/// accumulate or when a new error appears, see `Feature::start_dead_letter_watcher`
+pub struct DeadLetterWatcher<DLS>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+{
+    dead_letter_store: DLS,
+    notifier: Arc<dyn DeadLetterNotifier>,
+    failure_log: Option<DeadLetterFailureLog>,
+    interval: Duration,
+    threshold: usize,
+    thresholds: HashMap<(String, String), usize>,
+    /// (stream, consumer) pairs at or above their threshold on the last poll
+    exceeded: HashSet<(String, String)>,
+    /// (consumer, fingerprint) pairs of the dead letters in the store on the
+    /// last poll, so that the set stays as small as the store. `None` until
+    /// the first poll, whose errors are taken as already known.
+    fingerprints: Option<HashSet<(String, String)>>,
+}
+
+impl<DLS> DeadLetterWatcher<DLS>
+where
+    DLS: DeadLetterStore + Send + Sync + 'static,
+{
+    pub fn new(dead_letter_store: DLS, notifier: impl DeadLetterNotifier + 'static) -> Self {
+        Self {
+            dead_letter_store,
+            notifier: Arc::new(notifier),
+            failure_log: None,
+            interval: Duration::from_secs(60),
+            threshold: 10,
+            thresholds: HashMap::new(),
+            exceeded: HashSet::new(),
+            fingerprints: None,
+        }
+    }
+
+    /// Time between two polls of the store, one minute by default
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Number of dead letters of a consumer that triggers an alert, 10 by
+    /// default
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Threshold of one consumer, in place of the default one
+    pub fn with_consumer_threshold(
+        mut self,
+        stream: &str,
+        consumer: &str,
+        threshold: usize,
+    ) -> Self {
+        self.thresholds
+            .insert((stream.to_string(), consumer.to_string()), threshold);
+        self
+    }
+
+    /// Errors recorded by the projects, needed for the new error alerts.
+    /// `Feature::start_dead_letter_watcher` sets the failure log of the
+    /// feature when none is given.
+    pub fn with_failure_log(mut self, failure_log: DeadLetterFailureLog) -> Self {
+        self.failure_log = Some(failure_log);
+        self
+    }
+
+    pub(crate) fn has_failure_log(&self) -> bool {
+        self.failure_log.is_some()
+    }
+
+    /// Poll the store forever, a failed poll is logged and retried on the
+    /// next interval
+    pub async fn watch(mut self) {
+        loop {
+            if let Err(e) = self.poll().await {
+                tracing::warn!("Failed to poll dead letters: {}", e);
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    /// Compare the store with the previous poll and send the resulting
+    /// alerts, the delivered ones are returned. An alert that could not be
+    /// delivered, or that was not built because the poll failed, is sent on
+    /// the next poll.
+    pub async fn poll(&mut self) -> Result<Vec<DeadLetterAlert>, DeadLetterWatchError> {
+        let events = self
+            .dead_letter_store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .map_err(|e| DeadLetterWatchError::DeadLetterStore(e.into()))?;
+        let failures = match &self.failure_log {
+            Some(failure_log) => Some(failure_log.find(&failure_keys(&events)).await?),
+            None => None,
+        };
+
+        let mut alerts = Vec::new();
+
+        let mut counts = HashMap::<(String, String), usize>::new();
+        for event in &events {
+            *counts
+                .entry((event.stream.clone(), event.consumer.clone()))
+                .or_default() += 1;
+        }
+        let mut exceeded = HashSet::new();
+        for (key, count) in counts {
+            let threshold = self.thresholds.get(&key).copied().unwrap_or(self.threshold);
+            if count < threshold {
+                continue;
+            }
+            if !self.exceeded.contains(&key) {
+                alerts.push(DeadLetterAlert::ThresholdExceeded {
+                    stream: key.0.clone(),
+                    consumer: key.1.clone(),
+                    count,
+                    threshold,
+                });
+            }
+            exceeded.insert(key);
+        }
+
+        if let Some(failures) = &failures {
+            alerts.extend(self.new_errors(&events, failures));
+        }
+
+        let mut delivered = Vec::new();
+        for alert in alerts {
+            match self.notifier.notify(&alert).await {
+                Ok(()) => delivered.push(alert),
+                Err(e) => {
+                    tracing::warn!(?alert, "Failed to send dead letter alert: {}", e);
+                    self.forget(&mut exceeded, &alert);
+                },
+            }
+        }
+        self.exceeded = exceeded;
+
+        Ok(delivered)
+    }
+
+    /// Forget the state an undelivered alert was raised from, so that the
+    /// next poll raises it again
+    fn forget(&mut self, exceeded: &mut HashSet<(String, String)>, alert: &DeadLetterAlert) {
+        match alert {
+            DeadLetterAlert::ThresholdExceeded {
+                stream, consumer, ..
+            } => {
+                exceeded.remove(&(stream.clone(), consumer.clone()));
+            },
+            DeadLetterAlert::NewError {
+                consumer,
+                fingerprint,
+                ..
+            } => {
+                if let Some(fingerprints) = &mut self.fingerprints {
+                    fingerprints.remove(&(consumer.clone(), fingerprint.clone()));
+                }
+            },
+        }
+    }
+
+    /// Alerts for the errors of `events` that were not in the store on the
+    /// previous poll
+    fn new_errors(
+        &mut self,
+        events: &[DeadLetter],
+        failures: &HashMap<FailureKey, DeadLetterFailure>,
+    ) -> Vec<DeadLetterAlert> {
+        let mut alerts = Vec::new();
+        // An error whose dead letters were all removed is new again when it
+        // comes back
+        let known = self.fingerprints.take();
+        let mut fingerprints = HashSet::new();
+
+        for event in events {
+            let Some(failure) = failures.get(&FailureKey::of(event)) else {
+                continue;
+            };
+            let fingerprint = error_fingerprint(&failure.error);
+            let key = (event.consumer.clone(), fingerprint.clone());
+            let is_new = known.as_ref().is_some_and(|known| !known.contains(&key));
+            if fingerprints.insert(key) && is_new {
+                alerts.push(DeadLetterAlert::NewError {
+                    stream: event.stream.clone(),
+                    consumer: event.consumer.clone(),
+                    aggregate_id: failure.aggregate_id,
+                    fingerprint,
+                    error: failure.error.clone(),
+                    error_type: failure.error_type.clone(),
+                });
+            }
+        }
+        self.fingerprints = Some(fingerprints);
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    use chrono::Utc;
+    use esrc::{
+        version::{DeserializeVersion, SerializeVersion},
+        Event,
+    };
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::dead_letter::memory::InMemoryDeadLetterStore;
+    use crate::testing::TestEnvelope;
+
+    #[derive(Event, Serialize, Deserialize, Debug, Clone, SerializeVersion, DeserializeVersion)]
+    #[esrc(event(name = "User"))]
+    enum UserEvent {
+        Created { name: String },
+    }
+
+    /// Records the alerts it delivers, fails while `failing` is set
+    #[derive(Clone, Default)]
+    struct RecordingNotifier {
+        alerts: Arc<Mutex<Vec<DeadLetterAlert>>>,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl DeadLetterNotifier for RecordingNotifier {
+        fn notify<'a>(
+            &'a self,
+            alert: &'a DeadLetterAlert,
+        ) -> BoxFuture<'a, Result<(), NotifierError>> {
+            Box::pin(async move {
+                if self.failing.load(Ordering::SeqCst) {
+                    return Err("webhook unreachable".into());
+                }
+                self.alerts.lock().unwrap().push(alert.clone());
+                Ok(())
+            })
+        }
+    }
+
+    fn dead_letter(sequence: u64) -> DeadLetter {
+        TestEnvelope::new(&UserEvent::Created {
+            name: "Ada".to_string(),
+        })
+        .sequence(sequence)
+        .dead_letter("users", "user-projector")
+    }
+
+    fn failure(dead_letter: &DeadLetter, error: &str) -> (FailureKey, DeadLetterFailure) {
+        let now = Utc::now();
+        let failure = DeadLetterFailure {
+            consumer: dead_letter.consumer.clone(),
+            stream_sequence: dead_letter.stream_sequence,
+            aggregate_id: dead_letter.aggregate_id.unwrap(),
+            error: error.to_string(),
+            error_type: Some("sqlx::Error".to_string()),
+            attempts: 1,
+            first_failed_at: now,
+            last_failed_at: now,
+        };
+        (FailureKey::of(dead_letter), failure)
+    }
+
+    #[tokio::test]
+    async fn poll_alerts_once_per_threshold_crossing() {
+        let store = InMemoryDeadLetterStore::new();
+        let notifier = RecordingNotifier::default();
+        let mut watcher = DeadLetterWatcher::new(store.clone(), notifier.clone()).with_threshold(2);
+
+        store.insert(dead_letter(1));
+        assert!(watcher.poll().await.unwrap().is_empty());
+
+        store.insert(dead_letter(2));
+        let alerts = watcher.poll().await.unwrap();
+        assert!(matches!(
+            alerts.as_slice(),
+            [DeadLetterAlert::ThresholdExceeded {
+                count: 2,
+                threshold: 2,
+                ..
+            }]
+        ));
+
+        store.insert(dead_letter(3));
+        assert!(watcher.poll().await.unwrap().is_empty());
+        assert_eq!(notifier.alerts.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn undelivered_threshold_alert_is_sent_on_the_next_poll() {
+        let store = InMemoryDeadLetterStore::with_dead_letters([dead_letter(1), dead_letter(2)]);
+        let notifier = RecordingNotifier::default();
+        let mut watcher = DeadLetterWatcher::new(store.clone(), notifier.clone()).with_threshold(2);
+
+        notifier.failing.store(true, Ordering::SeqCst);
+        assert!(watcher.poll().await.unwrap().is_empty());
+
+        notifier.failing.store(false, Ordering::SeqCst);
+        assert_eq!(watcher.poll().await.unwrap().len(), 1);
+        assert_eq!(notifier.alerts.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_poll_keeps_the_state_of_the_previous_one() {
+        let store = InMemoryDeadLetterStore::with_dead_letters([dead_letter(1), dead_letter(2)]);
+        let notifier = RecordingNotifier::default();
+        let mut watcher = DeadLetterWatcher::new(store.clone(), notifier.clone()).with_threshold(2);
+
+        store.fail_on_get(true);
+        assert!(watcher.poll().await.is_err());
+
+        store.fail_on_get(false);
+        assert_eq!(watcher.poll().await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn undelivered_new_error_is_raised_again() {
+        let mut watcher =
+            DeadLetterWatcher::new(InMemoryDeadLetterStore::new(), RecordingNotifier::default());
+        watcher.new_errors(&[], &HashMap::new());
+        let event = dead_letter(1);
+        let failures = HashMap::from([failure(&event, "connection refused")]);
+
+        let alerts = watcher.new_errors(std::slice::from_ref(&event), &failures);
+        assert_eq!(alerts.len(), 1);
+        watcher.forget(&mut HashSet::new(), &alerts[0]);
+
+        assert_eq!(watcher.new_errors(&[event], &failures).len(), 1);
+    }
+
+    #[test]
+    fn new_errors_skip_the_errors_of_the_first_poll() {
+        let mut watcher =
+            DeadLetterWatcher::new(InMemoryDeadLetterStore::new(), RecordingNotifier::default());
+        let known = dead_letter(1);
+        let failures = HashMap::from([failure(&known, "connection refused")]);
+
+        assert!(watcher.new_errors(&[known.clone()], &failures).is_empty());
+
+        let new = dead_letter(2);
+        let failures = HashMap::from([
+            failure(&known, "connection refused"),
+            failure(&new, "duplicate key value"),
+        ]);
+        let alerts = watcher.new_errors(&[known, new], &failures);
+
+        assert!(matches!(
+            alerts.as_slice(),
+            [DeadLetterAlert::NewError { error, .. }] if error == "duplicate key value"
+        ));
+    }
+
+    #[test]
+    fn new_errors_forget_the_errors_no_longer_stored() {
+        let mut watcher =
+            DeadLetterWatcher::new(InMemoryDeadLetterStore::new(), RecordingNotifier::default());
+        let first = dead_letter(1);
+        let failures = HashMap::from([failure(&first, "connection refused")]);
+        watcher.new_errors(&[first], &failures);
+
+        watcher.new_errors(&[], &HashMap::new());
+        assert_eq!(watcher.fingerprints.as_ref().map(HashSet::len), Some(0));
+
+        let again = dead_letter(2);
+        let failures = HashMap::from([failure(&again, "connection refused")]);
+        assert_eq!(watcher.new_errors(&[again], &failures).len(), 1);
+    }
+}
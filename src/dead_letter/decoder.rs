@@ -0,0 +1,272 @@
+use async_nats::HeaderMap;
+use esrc::{project::Context, EventGroup};
+use nats_dead_letter::DeadLetter;
+use serde_json::Value;
+
+use crate::dead_letter::envelope::{DeadLetterEnvelope, DeadLetterEnvelopeError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeadLetterDecodeError {
+    #[error(transparent)]
+    Envelope(#[from] DeadLetterEnvelopeError),
+    #[error("Failed to create context: {0}")]
+    Context(#[source] esrc::Error),
+    #[error("Payload is not valid JSON: {0}")]
+    Payload(#[from] serde_json::Error),
+}
+
+/// Turns the dead letters kept by a `DeadLetterStore` back into esrc types,
+/// shared by replay, inspection and export.
+pub struct DeadLetterDecoder;
+
+impl DeadLetterDecoder {
+    /// Rebuild the envelope the consumer originally received
+    pub fn envelope(dead_letter: &DeadLetter) -> Result<DeadLetterEnvelope, DeadLetterDecodeError> {
+        Ok(DeadLetterEnvelope::try_from_dead_letter(dead_letter)?)
+    }
+
+    /// Deserialize the envelope into the event group of a project
+    pub fn context<'de, G>(
+        envelope: &'de DeadLetterEnvelope,
+    ) -> Result<Context<'de, DeadLetterEnvelope, G>, DeadLetterDecodeError>
+    where
+        G: EventGroup,
+    {
+        Context::try_with_envelope(envelope).map_err(DeadLetterDecodeError::Context)
+    }
+
+    /// Headers of the original message, with the single value per name the
+    /// store keeps
+    pub fn headers(dead_letter: &DeadLetter) -> Result<HeaderMap, DeadLetterDecodeError> {
+        let mut headers = HeaderMap::new();
+        for (key, value) in dead_letter
+            .headers
+            .clone()
+            .ok_or(DeadLetterEnvelopeError::MissingHeaders)?
+        {
+            headers.insert(key, value);
+        }
+
+        Ok(headers)
+    }
+
+    /// Raw JSON payload of the dead letter, for inspection without knowing
+    /// the event group
+    pub fn payload(dead_letter: &DeadLetter) -> Result<Value, DeadLetterDecodeError> {
+        Ok(serde_json::from_slice(&dead_letter.payload)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use esrc::{
+        version::{DeserializeVersion, SerializeVersion},
+        Envelope, Event,
+    };
+    use serde::{Deserialize, Serialize};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::dead_letter::envelope::VERSION_HEADER;
+    use crate::testing::{TestEnvelope, TEST_PREFIX};
+
+    #[derive(
+        Event, Serialize, Deserialize, PartialEq, Debug, Clone, SerializeVersion, DeserializeVersion,
+    )]
+    #[esrc(event(name = "User"))]
+    enum UserEvent {
+        Created { name: String },
+    }
+
+    fn created() -> UserEvent {
+        UserEvent::Created {
+            name: "Ada".to_string(),
+        }
+    }
+
+    fn dead_letter() -> DeadLetter {
+        TestEnvelope::new(&created())
+            .metadata("Trace-Id", "abc")
+            .dead_letter("users", "user-projector")
+    }
+
+    fn with_headers(mut dead_letter: DeadLetter, headers: &[(&str, &str)]) -> DeadLetter {
+        dead_letter.headers = Some(
+            headers
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>()
+                .into_iter()
+                .collect(),
+        );
+        dead_letter
+    }
+
+    #[test]
+    fn envelope_reads_name_and_id_from_subject() {
+        let id = Uuid::now_v7();
+        let dead_letter = TestEnvelope::new(&created())
+            .id(id)
+            .dead_letter("users", "user-projector");
+
+        let envelope = DeadLetterDecoder::envelope(&dead_letter).unwrap();
+
+        assert_eq!(dead_letter.subject, format!("{}.User.{}", TEST_PREFIX, id));
+        assert_eq!(envelope.name(), "User");
+        assert_eq!(Envelope::id(&envelope), id);
+    }
+
+    #[test]
+    fn envelope_requires_prefix() {
+        let mut dead_letter = dead_letter();
+        dead_letter.prefix = None;
+
+        assert!(matches!(
+            DeadLetterDecoder::envelope(&dead_letter),
+            Err(DeadLetterDecodeError::Envelope(
+                DeadLetterEnvelopeError::MissingPrefix
+            ))
+        ));
+    }
+
+    #[test]
+    fn envelope_rejects_malformed_subject() {
+        let id = Uuid::now_v7();
+        for subject in [
+            format!("other.User.{}", id),
+            format!("{}.User", TEST_PREFIX),
+            format!("{}User.{}", TEST_PREFIX, id),
+        ] {
+            let mut dead_letter = dead_letter();
+            dead_letter.subject = subject.clone();
+
+            assert!(
+                matches!(
+                    DeadLetterDecoder::envelope(&dead_letter),
+                    Err(DeadLetterDecodeError::Envelope(
+                        DeadLetterEnvelopeError::InvalidSubject { .. }
+                    ))
+                ),
+                "{} should be rejected",
+                subject
+            );
+        }
+    }
+
+    #[test]
+    fn envelope_rejects_invalid_id() {
+        let mut dead_letter = dead_letter();
+        dead_letter.subject = format!("{}.User.not-a-uuid", TEST_PREFIX);
+
+        assert!(matches!(
+            DeadLetterDecoder::envelope(&dead_letter),
+            Err(DeadLetterDecodeError::Envelope(
+                DeadLetterEnvelopeError::InvalidId(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn envelope_requires_headers() {
+        let mut dead_letter = dead_letter();
+        dead_letter.headers = None;
+
+        assert!(matches!(
+            DeadLetterDecoder::envelope(&dead_letter),
+            Err(DeadLetterDecodeError::Envelope(
+                DeadLetterEnvelopeError::MissingHeaders
+            ))
+        ));
+        assert!(DeadLetterDecoder::headers(&dead_letter).is_err());
+    }
+
+    #[test]
+    fn envelope_version_defaults_to_one() {
+        let dead_letter = with_headers(dead_letter(), &[("Trace-Id", "abc")]);
+
+        let envelope = DeadLetterDecoder::envelope(&dead_letter).unwrap();
+
+        assert_eq!(envelope.version(), 1);
+    }
+
+    #[test]
+    fn envelope_reads_version_header() {
+        let dead_letter = TestEnvelope::new(&created())
+            .version(3)
+            .dead_letter("users", "user-projector");
+
+        let envelope = DeadLetterDecoder::envelope(&dead_letter).unwrap();
+
+        assert_eq!(envelope.version(), 3);
+    }
+
+    #[test]
+    fn envelope_rejects_invalid_version_header() {
+        let dead_letter = with_headers(dead_letter(), &[(VERSION_HEADER, "three")]);
+
+        assert!(matches!(
+            DeadLetterDecoder::envelope(&dead_letter),
+            Err(DeadLetterDecodeError::Envelope(
+                DeadLetterEnvelopeError::InvalidVersion(version)
+            )) if version == "three"
+        ));
+    }
+
+    #[test]
+    fn headers_are_decoded() {
+        let dead_letter = dead_letter();
+
+        let headers = DeadLetterDecoder::headers(&dead_letter).unwrap();
+        let envelope = DeadLetterDecoder::envelope(&dead_letter).unwrap();
+
+        assert_eq!(
+            headers.get("Trace-Id").map(|value| value.as_str()),
+            Some("abc")
+        );
+        assert_eq!(
+            headers.get(VERSION_HEADER).map(|value| value.as_str()),
+            Some("1")
+        );
+        assert_eq!(envelope.get_metadata("Trace-Id"), Some("abc"));
+    }
+
+    #[test]
+    fn context_deserializes_payload_into_event_group() {
+        let dead_letter = dead_letter();
+        let envelope = DeadLetterDecoder::envelope(&dead_letter).unwrap();
+
+        let context = DeadLetterDecoder::context::<UserEvent>(&envelope).unwrap();
+
+        assert_eq!(*context, created());
+    }
+
+    #[test]
+    fn context_rejects_payload_of_another_shape() {
+        let mut dead_letter = dead_letter();
+        dead_letter.payload = br#"{"Deleted":{}}"#.to_vec();
+        let envelope = DeadLetterDecoder::envelope(&dead_letter).unwrap();
+
+        assert!(matches!(
+            DeadLetterDecoder::context::<UserEvent>(&envelope),
+            Err(DeadLetterDecodeError::Context(_))
+        ));
+    }
+
+    #[test]
+    fn payload_is_decoded_as_json() {
+        let mut dead_letter = dead_letter();
+
+        assert_eq!(
+            DeadLetterDecoder::payload(&dead_letter).unwrap(),
+            serde_json::json!({ "Created": { "name": "Ada" } })
+        );
+
+        dead_letter.payload = b"not json".to_vec();
+        assert!(matches!(
+            DeadLetterDecoder::payload(&dead_letter),
+            Err(DeadLetterDecodeError::Payload(_))
+        ));
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use esrc::{error, event::Sequence, version::DeserializeVersion, Envelope, Event};
+use nats_dead_letter::DeadLetter;
+use uuid::Uuid;
+
+/// Header holding the version of the serialized event, as written by esrc
+pub const VERSION_HEADER: &str = "Esrc-Version";
+
+/// `Envelope` built from the data kept by a `DeadLetterStore`, without a live
+/// JetStream message behind it.
+///
+/// Replaying through this envelope never talks to the server: there is no
+/// reply subject to acknowledge. The store keeps a single value per header,
+/// so a header the original message repeated only has one of its values.
+#[derive(Debug, Clone)]
+pub struct DeadLetterEnvelope {
+    id: Uuid,
+    sequence: u64,
+    timestamp: SystemTime,
+    name: String,
+    version: usize,
+    headers: HashMap<String, String>,
+    payload: Vec<u8>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeadLetterEnvelopeError {
+    #[error("Event prefix is missing")]
+    MissingPrefix,
+    #[error("Event headers are missing")]
+    MissingHeaders,
+    #[error("Subject {subject} does not match {prefix}.<event>.<id>")]
+    InvalidSubject { subject: String, prefix: String },
+    #[error("Invalid aggregate ID in subject {0}")]
+    InvalidId(String),
+    #[error("Invalid Esrc-Version header: {0}")]
+    InvalidVersion(String),
+}
+
+impl DeadLetterEnvelope {
+    pub fn try_from_dead_letter(dead_letter: &DeadLetter) -> Result<Self, DeadLetterEnvelopeError> {
+        let prefix = dead_letter
+            .prefix
+            .as_deref()
+            .ok_or(DeadLetterEnvelopeError::MissingPrefix)?;
+        let headers = dead_letter
+            .headers
+            .clone()
+            .ok_or(DeadLetterEnvelopeError::MissingHeaders)?
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+
+        // Subjects are published by esrc as `{prefix}.{event name}.{aggregate id}`
+        let invalid_subject = || DeadLetterEnvelopeError::InvalidSubject {
+            subject: dead_letter.subject.clone(),
+            prefix: prefix.to_string(),
+        };
+        let (name, id) = dead_letter
+            .subject
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_prefix('.'))
+            .and_then(|rest| rest.split_once('.'))
+            .ok_or_else(invalid_subject)?;
+        let id = Uuid::parse_str(id)
+            .map_err(|_| DeadLetterEnvelopeError::InvalidId(dead_letter.subject.clone()))?;
+
+        let version = match headers.get(VERSION_HEADER) {
+            Some(version) => version
+                .parse()
+                .map_err(|_| DeadLetterEnvelopeError::InvalidVersion(version.clone()))?,
+            None => 1,
+        };
+
+        Ok(Self {
+            id,
+            sequence: dead_letter.stream_sequence,
+            timestamp: dead_letter.timestamp.into(),
+            name: name.to_string(),
+            version,
+            headers,
+            payload: dead_letter.payload.clone(),
+        })
+    }
+
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+impl Envelope for DeadLetterEnvelope {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn sequence(&self) -> Sequence {
+        self.sequence.into()
+    }
+
+    fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_metadata(&self, key: &str) -> Option<&str> {
+        self.headers.get(key).map(String::as_str)
+    }
+
+    fn deserialize<'de, E>(&'de self) -> error::Result<E>
+    where
+        E: DeserializeVersion<'de> + Event,
+    {
+        let mut deserializer = serde_json::Deserializer::from_slice(&self.payload);
+        E::deserialize_version(&mut deserializer, self.version)
+            .map_err(|e| error::Error::Format(e.into()))
+    }
+}
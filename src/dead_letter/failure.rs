@@ -0,0 +1,410 @@
+use std::any::Any;
+use std::collections::{BTreeSet, HashMap};
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use chrono::{DateTime, Utc};
+use esrc::{
+    project::{Context, Project},
+    Envelope,
+};
+use futures::FutureExt;
+use nats_dead_letter::DeadLetter;
+use serde::Serialize;
+use sqlx::Row;
+use uuid::Uuid;
+
+/// Message a failure is recorded for: the durable consumer that failed it and
+/// its sequence in the stream. A durable consumer reads a single stream, so
+/// the pair identifies the message as long as the consumers of different
+/// streams have different names, as the feature names of an application do.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FailureKey {
+    pub consumer: String,
+    pub stream_sequence: u64,
+}
+
+impl FailureKey {
+    /// Key of the message that was dead lettered
+    pub fn of(dead_letter: &DeadLetter) -> Self {
+        Self {
+            consumer: dead_letter.consumer.clone(),
+            stream_sequence: dead_letter.stream_sequence,
+        }
+    }
+}
+
+/// Last error of a projector for a message, the reason it ended up in the
+/// `DeadLetterStore`
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetterFailure {
+    pub consumer: String,
+    pub stream_sequence: u64,
+    pub aggregate_id: Uuid,
+    /// Display text of the error, or the panic message when `error_type` is
+    /// `None`
+    pub error: String,
+    /// Rust type of the projector error, `None` for a panic
+    pub error_type: Option<String>,
+    /// Number of failed deliveries since the failure was first recorded
+    pub attempts: i64,
+    pub first_failed_at: DateTime<Utc>,
+    pub last_failed_at: DateTime<Utc>,
+}
+
+impl DeadLetterFailure {
+    fn from_row(row: &sqlx::postgres::PgRow) -> Self {
+        Self {
+            consumer: row.get("consumer"),
+            stream_sequence: row.get::<i64, _>("stream_sequence") as u64,
+            aggregate_id: row.get("aggregate_id"),
+            error: row.get("error"),
+            error_type: row.get("error_type"),
+            attempts: row.get("attempts"),
+            first_failed_at: row.get("first_failed_at"),
+            last_failed_at: row.get("last_failed_at"),
+        }
+    }
+
+    pub fn is_panic(&self) -> bool {
+        self.error_type.is_none()
+    }
+
+    fn key(&self) -> FailureKey {
+        FailureKey {
+            consumer: self.consumer.clone(),
+            stream_sequence: self.stream_sequence,
+        }
+    }
+}
+
+/// Failures of the projectors started by a `Feature`, keyed by the message
+/// that failed, so that a dead letter can be matched with the error that
+/// caused it. The failure of a message is removed once it is projected.
+#[derive(Clone)]
+pub struct DeadLetterFailureLog {
+    db: sqlx::PgPool,
+}
+
+impl DeadLetterFailureLog {
+    pub fn new(db: sqlx::PgPool) -> Self {
+        Self { db }
+    }
+
+    pub async fn setup(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS dead_letter_failures(
+                consumer text                   NOT NULL,
+                stream_sequence bigint          NOT NULL,
+                aggregate_id uuid               NOT NULL,
+                error text                      NOT NULL,
+                error_type text,
+                attempts bigint                 NOT NULL DEFAULT 1,
+                first_failed_at timestamptz     NOT NULL DEFAULT NOW(),
+                last_failed_at timestamptz      NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (consumer, stream_sequence)
+            );",
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Record a failed delivery, replacing the error of the previous one
+    pub async fn record(
+        &self,
+        key: &FailureKey,
+        aggregate_id: Uuid,
+        error: &str,
+        error_type: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO dead_letter_failures
+                (consumer, stream_sequence, aggregate_id, error, error_type)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (consumer, stream_sequence) DO UPDATE SET
+                    error = EXCLUDED.error,
+                    error_type = EXCLUDED.error_type,
+                    attempts = dead_letter_failures.attempts + 1,
+                    last_failed_at = NOW()",
+        )
+        .bind(&key.consumer)
+        .bind(key.stream_sequence as i64)
+        .bind(aggregate_id)
+        .bind(error)
+        .bind(error_type)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Forget the failure of a message that was projected since
+    pub async fn remove(&self, key: &FailureKey) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "DELETE FROM dead_letter_failures WHERE consumer = $1 AND stream_sequence = $2",
+        )
+        .bind(&key.consumer)
+        .bind(key.stream_sequence as i64)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Failures of the given messages, the messages without a recorded
+    /// failure are left out
+    pub async fn find(
+        &self,
+        keys: &[FailureKey],
+    ) -> Result<HashMap<FailureKey, DeadLetterFailure>, sqlx::Error> {
+        if keys.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let consumers = keys
+            .iter()
+            .map(|key| key.consumer.clone())
+            .collect::<Vec<_>>();
+        let sequences = keys
+            .iter()
+            .map(|key| key.stream_sequence as i64)
+            .collect::<Vec<_>>();
+
+        let rows = sqlx::query(
+            "SELECT f.* FROM dead_letter_failures f
+                JOIN UNNEST($1::text[], $2::bigint[]) AS k(consumer, stream_sequence)
+                ON f.consumer = k.consumer AND f.stream_sequence = k.stream_sequence",
+        )
+        .bind(consumers)
+        .bind(sequences)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(DeadLetterFailure::from_row)
+            .map(|failure| (failure.key(), failure))
+            .collect())
+    }
+}
+
+/// Keys of the dead letters, as given to `DeadLetterFailureLog::find`
+pub(crate) fn failure_keys(events: &[DeadLetter]) -> Vec<FailureKey> {
+    let mut keys = events.iter().map(FailureKey::of).collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+/// `Project` recording the errors and panics of the wrapped project in a
+/// `DeadLetterFailureLog`, the outcome of the projection is left unchanged.
+///
+/// The failures are keyed by `durable_name`, the name of the consumer running
+/// the project, so that they match the `DeadLetter::consumer` of its dead
+/// letters. The failure of a message that is projected on a later delivery is
+/// removed, unless the process restarted in between or more than
+/// `MAX_TRACKED_FAILURES` newer messages failed since.
+#[derive(Clone)]
+pub struct RecordFailures<P> {
+    project: P,
+    durable_name: &'static str,
+    failure_log: Option<DeadLetterFailureLog>,
+    /// Stream sequences of the latest messages that failed in this process
+    failed: Arc<Mutex<BTreeSet<u64>>>,
+}
+
+/// Number of failed messages a `RecordFailures` remembers to remove their
+/// failure once projected. A message that was dead lettered is never
+/// delivered again, so the oldest ones are forgotten first.
+pub const MAX_TRACKED_FAILURES: usize = 10_000;
+
+/// Remember a failed message, forgetting the oldest one past
+/// `MAX_TRACKED_FAILURES`
+fn track_failure(failed: &mut BTreeSet<u64>, stream_sequence: u64) {
+    failed.insert(stream_sequence);
+    if failed.len() > MAX_TRACKED_FAILURES {
+        failed.pop_first();
+    }
+}
+
+impl<P> RecordFailures<P> {
+    pub fn new(
+        project: P,
+        durable_name: &'static str,
+        failure_log: Option<DeadLetterFailureLog>,
+    ) -> Self {
+        Self {
+            project,
+            durable_name,
+            failure_log,
+            failed: Arc::default(),
+        }
+    }
+
+    fn key(&self, stream_sequence: u64) -> FailureKey {
+        FailureKey {
+            consumer: self.durable_name.to_string(),
+            stream_sequence,
+        }
+    }
+
+    fn failed(&self) -> std::sync::MutexGuard<'_, BTreeSet<u64>> {
+        self.failed.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    async fn record(
+        &self,
+        stream_sequence: u64,
+        aggregate_id: Uuid,
+        error: &str,
+        error_type: Option<&str>,
+    ) {
+        let Some(failure_log) = &self.failure_log else {
+            return;
+        };
+        track_failure(&mut self.failed(), stream_sequence);
+        if let Err(e) = failure_log
+            .record(&self.key(stream_sequence), aggregate_id, error, error_type)
+            .await
+        {
+            tracing::warn!(consumer = self.durable_name, stream_sequence, %aggregate_id, "Failed to record projector failure: {}", e);
+        }
+    }
+
+    async fn resolve(&self, stream_sequence: u64) {
+        let Some(failure_log) = &self.failure_log else {
+            return;
+        };
+        if !self.failed().remove(&stream_sequence) {
+            return;
+        }
+        if let Err(e) = failure_log.remove(&self.key(stream_sequence)).await {
+            tracing::warn!(
+                consumer = self.durable_name,
+                stream_sequence,
+                "Failed to remove projector failure: {}",
+                e
+            );
+        }
+    }
+}
+
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string())
+}
+
+impl<P> Project for RecordFailures<P>
+where
+    P: Project,
+{
+    type EventGroup = P::EventGroup;
+    type Error = P::Error;
+
+    async fn project<'de, E: Envelope>(
+        &mut self,
+        context: Context<'de, E, Self::EventGroup>,
+    ) -> Result<(), Self::Error> {
+        let aggregate_id = Context::id(&context);
+        let stream_sequence = u64::from(Context::sequence(&context));
+
+        match AssertUnwindSafe(self.project.project(context))
+            .catch_unwind()
+            .await
+        {
+            Ok(Ok(())) => {
+                self.resolve(stream_sequence).await;
+                Ok(())
+            },
+            Ok(Err(e)) => {
+                let error_type = std::any::type_name::<P::Error>();
+                self.record(
+                    stream_sequence,
+                    aggregate_id,
+                    &e.to_string(),
+                    Some(error_type),
+                )
+                .await;
+                Err(e)
+            },
+            Err(panic) => {
+                // The panic is recorded and then resumed, so that the consumer
+                // behaves as it would without the failure log
+                let message = panic_message(panic.as_ref());
+                self.record(stream_sequence, aggregate_id, &message, None)
+                    .await;
+                std::panic::resume_unwind(panic)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use esrc::{
+        version::{DeserializeVersion, SerializeVersion},
+        Event,
+    };
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::testing::TestEnvelope;
+
+    #[derive(Event, Serialize, Deserialize, Debug, Clone, SerializeVersion, DeserializeVersion)]
+    #[esrc(event(name = "User"))]
+    enum UserEvent {
+        Created { name: String },
+    }
+
+    fn dead_letter(consumer: &str, sequence: u64) -> DeadLetter {
+        TestEnvelope::new(&UserEvent::Created {
+            name: "Ada".to_string(),
+        })
+        .sequence(sequence)
+        .dead_letter("users", consumer)
+    }
+
+    #[test]
+    fn failure_keys_separate_the_messages_of_an_aggregate() {
+        let first = dead_letter("user-projector", 1);
+        let mut second = dead_letter("user-projector", 2);
+        second.aggregate_id = first.aggregate_id;
+
+        let keys = failure_keys(&[first.clone(), second.clone(), first.clone()]);
+
+        assert_eq!(keys, vec![FailureKey::of(&first), FailureKey::of(&second)]);
+    }
+
+    #[test]
+    fn failure_keys_separate_consumers() {
+        let projector = dead_letter("user-projector", 1);
+        let mailer = dead_letter("user-mailer", 1);
+
+        let keys = failure_keys(&[projector.clone(), mailer.clone()]);
+
+        assert_eq!(keys.len(), 2);
+        assert_ne!(FailureKey::of(&projector), FailureKey::of(&mailer));
+    }
+
+    #[test]
+    fn tracked_failures_forget_the_oldest_messages() {
+        let mut failed = BTreeSet::new();
+        for stream_sequence in (1..=MAX_TRACKED_FAILURES as u64 + 2).rev() {
+            track_failure(&mut failed, stream_sequence);
+        }
+
+        assert_eq!(failed.len(), MAX_TRACKED_FAILURES);
+        assert_eq!(failed.first(), Some(&3));
+
+        track_failure(&mut failed, 5);
+        assert_eq!(failed.len(), MAX_TRACKED_FAILURES);
+    }
+
+    #[test]
+    fn panic_message_reads_str_and_string_payloads() {
+        assert_eq!(panic_message(&"boom"), "boom");
+        assert_eq!(panic_message(&"boom".to_string()), "boom");
+        assert_eq!(panic_message(&1u8), "Box<dyn Any>");
+    }
+}
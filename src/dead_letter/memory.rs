@@ -0,0 +1,294 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use nats_dead_letter::{DeadLetter, DeadLetterStore};
+use uuid::Uuid;
+
+#[derive(Debug, thiserror::Error)]
+pub enum InMemoryDeadLetterStoreError {
+    #[error("Injected failure on get")]
+    GetFailure,
+    #[error("Injected failure on remove")]
+    RemoveFailure,
+    #[error("Injected failure on store")]
+    StoreFailure,
+}
+
+#[derive(Default)]
+struct Faults {
+    get: AtomicBool,
+    remove: AtomicBool,
+    store: AtomicBool,
+}
+
+/// `DeadLetterStore` kept in memory, to test replay and admin commands
+/// without Postgres. Enabled by the `testing` feature.
+///
+/// Clones share the same dead letters and fault switches, so a test can keep
+/// a clone to inspect the store or make it fail after handing it to an
+/// `AdminHandler`.
+#[derive(Clone, Default)]
+pub struct InMemoryDeadLetterStore {
+    dead_letters: Arc<Mutex<Vec<DeadLetter>>>,
+    faults: Arc<Faults>,
+}
+
+impl InMemoryDeadLetterStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_dead_letters(dead_letters: impl IntoIterator<Item = DeadLetter>) -> Self {
+        let store = Self::new();
+        for dead_letter in dead_letters {
+            store.insert(dead_letter);
+        }
+        store
+    }
+
+    /// Add a dead letter, one without an ID gets a new one which is returned
+    pub fn insert(&self, mut dead_letter: DeadLetter) -> Uuid {
+        let id = *dead_letter.id.get_or_insert_with(Uuid::now_v7);
+        self.lock().push(dead_letter);
+        id
+    }
+
+    /// Every dead letter in the store, oldest first
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        let mut dead_letters = self.lock().clone();
+        dead_letters.sort_by_key(|e| e.timestamp);
+        dead_letters
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Make `get_dead_letters` fail until switched off
+    pub fn fail_on_get(&self, fail: bool) {
+        self.faults.get.store(fail, Ordering::SeqCst);
+    }
+
+    /// Make `remove_dead_letter` fail until switched off, the dead letter is
+    /// kept
+    pub fn fail_on_remove(&self, fail: bool) {
+        self.faults.remove.store(fail, Ordering::SeqCst);
+    }
+
+    /// Make `store_dead_letter` fail until switched off
+    pub fn fail_on_store(&self, fail: bool) {
+        self.faults.store.store(fail, Ordering::SeqCst);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<DeadLetter>> {
+        self.dead_letters
+            .lock()
+            .expect("in memory dead letter store lock poisoned")
+    }
+}
+
+impl DeadLetterStore for InMemoryDeadLetterStore {
+    type Error = InMemoryDeadLetterStoreError;
+
+    async fn store_dead_letter(&self, dead_letter: DeadLetter) -> Result<(), Self::Error> {
+        if self.faults.store.load(Ordering::SeqCst) {
+            return Err(InMemoryDeadLetterStoreError::StoreFailure);
+        }
+        self.insert(dead_letter);
+        Ok(())
+    }
+
+    /// Dead letters of the stream and consumer when given, oldest first,
+    /// paginated by `limit` and `offset` like `SqlxDeadLetterStore`
+    async fn get_dead_letters(
+        &self,
+        stream: Option<&str>,
+        consumer: Option<&str>,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<Vec<DeadLetter>, Self::Error> {
+        if self.faults.get.load(Ordering::SeqCst) {
+            return Err(InMemoryDeadLetterStoreError::GetFailure);
+        }
+
+        Ok(self
+            .dead_letters()
+            .into_iter()
+            .filter(|e| stream.is_none_or(|stream| e.stream == stream))
+            .filter(|e| consumer.is_none_or(|consumer| e.consumer == consumer))
+            .skip(offset.unwrap_or(0))
+            .take(limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    /// Removing an unknown ID is not an error, as with a SQL `DELETE`
+    async fn remove_dead_letter(&self, id: &str) -> Result<(), Self::Error> {
+        if self.faults.remove.load(Ordering::SeqCst) {
+            return Err(InMemoryDeadLetterStoreError::RemoveFailure);
+        }
+        self.lock().retain(|e| {
+            e.id.is_none_or(|dead_letter_id| dead_letter_id.to_string() != id)
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    fn dead_letter(stream: &str, consumer: &str, age: u64) -> DeadLetter {
+        DeadLetter {
+            id: Some(Uuid::now_v7()),
+            aggregate_id: Some(Uuid::now_v7()),
+            prefix: None,
+            stream: stream.to_string(),
+            consumer: consumer.to_string(),
+            subject: "test.User.1".to_string(),
+            stream_sequence: 1,
+            delivery_count: 1,
+            timestamp: (SystemTime::now() - Duration::from_secs(age)).into(),
+            headers: None,
+            payload: Vec::new(),
+        }
+    }
+
+    fn ids(dead_letters: &[DeadLetter]) -> Vec<Option<Uuid>> {
+        dead_letters.iter().map(|e| e.id).collect()
+    }
+
+    #[tokio::test]
+    async fn get_dead_letters_filters_by_stream_and_consumer_oldest_first() {
+        let newer = dead_letter("users", "user-projector", 1);
+        let older = dead_letter("users", "user-projector", 2);
+        let other_consumer = dead_letter("users", "user-mailer", 3);
+        let other_stream = dead_letter("orders", "user-projector", 4);
+        let store = InMemoryDeadLetterStore::with_dead_letters([
+            newer.clone(),
+            older.clone(),
+            other_consumer.clone(),
+            other_stream.clone(),
+        ]);
+
+        let all = store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .unwrap();
+        let stream = store
+            .get_dead_letters(Some("users"), None, None, None)
+            .await
+            .unwrap();
+        let consumer = store
+            .get_dead_letters(None, Some("user-projector"), None, None)
+            .await
+            .unwrap();
+        let both = store
+            .get_dead_letters(Some("users"), Some("user-projector"), None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            ids(&all),
+            ids(&[
+                other_stream.clone(),
+                other_consumer.clone(),
+                older.clone(),
+                newer.clone()
+            ])
+        );
+        assert_eq!(
+            ids(&stream),
+            ids(&[other_consumer, older.clone(), newer.clone()])
+        );
+        assert_eq!(
+            ids(&consumer),
+            ids(&[other_stream, older.clone(), newer.clone()])
+        );
+        assert_eq!(ids(&both), ids(&[older, newer]));
+    }
+
+    #[tokio::test]
+    async fn get_dead_letters_paginates_after_filtering() {
+        let dead_letters = (0..5)
+            .map(|age| dead_letter("users", "user-projector", 5 - age))
+            .collect::<Vec<_>>();
+        let store = InMemoryDeadLetterStore::with_dead_letters(
+            dead_letters
+                .iter()
+                .cloned()
+                .chain([dead_letter("orders", "user-projector", 10)]),
+        );
+
+        let page = store
+            .get_dead_letters(Some("users"), None, Some(2), Some(1))
+            .await
+            .unwrap();
+        let past_the_end = store
+            .get_dead_letters(Some("users"), None, Some(2), Some(5))
+            .await
+            .unwrap();
+        let rest = store
+            .get_dead_letters(Some("users"), None, None, Some(3))
+            .await
+            .unwrap();
+
+        assert_eq!(ids(&page), ids(&dead_letters[1..3]));
+        assert!(past_the_end.is_empty());
+        assert_eq!(ids(&rest), ids(&dead_letters[3..]));
+    }
+
+    #[tokio::test]
+    async fn remove_dead_letter_keeps_the_others() {
+        let removed = dead_letter("users", "user-projector", 2);
+        let kept = dead_letter("users", "user-projector", 1);
+        let store = InMemoryDeadLetterStore::with_dead_letters([removed.clone(), kept.clone()]);
+
+        store
+            .remove_dead_letter(&removed.id.unwrap().to_string())
+            .await
+            .unwrap();
+        store
+            .remove_dead_letter(&Uuid::now_v7().to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(ids(&store.dead_letters()), ids(&[kept]));
+    }
+
+    #[tokio::test]
+    async fn injected_failures_leave_the_store_unchanged() {
+        let store =
+            InMemoryDeadLetterStore::with_dead_letters([dead_letter("users", "user-projector", 1)]);
+        let id = store.dead_letters()[0].id.unwrap().to_string();
+        store.fail_on_get(true);
+        store.fail_on_remove(true);
+        store.fail_on_store(true);
+
+        assert!(store
+            .get_dead_letters(None, None, None, None)
+            .await
+            .is_err());
+        assert!(store.remove_dead_letter(&id).await.is_err());
+        assert!(store
+            .store_dead_letter(dead_letter("users", "user-projector", 0))
+            .await
+            .is_err());
+        assert_eq!(store.len(), 1);
+
+        store.fail_on_get(false);
+        assert_eq!(
+            store
+                .get_dead_letters(None, None, None, None)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
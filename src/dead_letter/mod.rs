@@ -0,0 +1,6 @@
+pub mod alert;
+pub mod decoder;
+pub mod envelope;
+pub mod failure;
+#[cfg(any(test, feature = "testing"))]
+pub mod memory;
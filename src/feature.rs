@@ -1,27 +1,182 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use chrono::{DateTime, Utc};
 use esrc::{
     event::event_model::{Automation, ViewAutomation},
     nats::NatsStore,
 };
+use futures::FutureExt;
+use serde::Serialize;
+
+use crate::admin::auto_retry::AutoRetryScheduler;
+use crate::admin::projector_registry::DeadLetterProjector;
+use crate::admin::retention::DeadLetterRetention;
+use crate::dead_letter::alert::DeadLetterWatcher;
+use crate::dead_letter::failure::{panic_message, DeadLetterFailureLog, RecordFailures};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AutomationKind {
+    Automation,
+    Translation,
+    ReadModel,
+    DeadLetter,
+    DeadLetterWatcher,
+    AutoRetry,
+    Retention,
+    Legacy,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum AutomationStatus {
+    Running,
+    /// The automation returned without an error
+    Stopped,
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AutomationState {
+    pub feature_name: &'static str,
+    pub kind: AutomationKind,
+    #[serde(flatten)]
+    pub status: AutomationStatus,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Status of the automations started by a `Feature`, shared with the admin
+/// dashboard. Every update replaces whole entries, so the states are still
+/// consistent after a thread panicked holding the lock and the poisoning is
+/// ignored.
+#[derive(Clone, Default)]
+pub struct AutomationMonitor {
+    states: Arc<RwLock<HashMap<(AutomationKind, &'static str), AutomationState>>>,
+}
+
+impl AutomationMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every automation started so far, ordered by feature name
+    pub fn snapshot(&self) -> Vec<AutomationState> {
+        let states = self.states.read().unwrap_or_else(PoisonError::into_inner);
+        let mut snapshot = states.values().cloned().collect::<Vec<_>>();
+        snapshot.sort_by_key(|state| (state.feature_name, state.kind as u8));
+        snapshot
+    }
+
+    fn started(&self, kind: AutomationKind, feature_name: &'static str) {
+        let now = Utc::now();
+        self.states
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                (kind, feature_name),
+                AutomationState {
+                    feature_name,
+                    kind,
+                    status: AutomationStatus::Running,
+                    started_at: now,
+                    updated_at: now,
+                },
+            );
+    }
+
+    fn finished<T, E>(
+        &self,
+        kind: AutomationKind,
+        feature_name: &'static str,
+        result: &Result<T, E>,
+    ) where
+        E: Display,
+    {
+        let mut states = self.states.write().unwrap_or_else(PoisonError::into_inner);
+        if let Some(state) = states.get_mut(&(kind, feature_name)) {
+            state.status = match result {
+                Ok(_) => AutomationStatus::Stopped,
+                Err(e) => AutomationStatus::Failed {
+                    error: e.to_string(),
+                },
+            };
+            state.updated_at = Utc::now();
+        }
+    }
+
+    /// Run a background task that loops until it panics, the panic is
+    /// reported as a failure of the task and then resumed
+    async fn supervise<F>(&self, kind: AutomationKind, feature_name: &'static str, task: F)
+    where
+        F: Future<Output = ()>,
+    {
+        self.started(kind, feature_name);
+        let result = AssertUnwindSafe(task).catch_unwind().await;
+        let reported = result
+            .as_ref()
+            .map_err(|panic| format!("panicked: {}", panic_message(panic.as_ref())));
+        self.finished(kind, feature_name, &reported);
+        if let Err(panic) = result {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
 
 pub struct Feature<'a> {
     store: &'a NatsStore,
+    monitor: AutomationMonitor,
+    failure_log: Option<DeadLetterFailureLog>,
 }
 
 impl<'a> Feature<'a> {
     pub fn new(store: &'a NatsStore) -> Self {
-        Self { store }
+        Self {
+            store,
+            monitor: AutomationMonitor::new(),
+            failure_log: None,
+        }
     }
 
+    /// Report the automations to a monitor shared with other features
+    pub fn with_monitor(mut self, monitor: AutomationMonitor) -> Self {
+        self.monitor = monitor;
+        self
+    }
+
+    pub fn monitor(&self) -> AutomationMonitor {
+        self.monitor.clone()
+    }
+
+    /// Record the errors and panics of the projects started from now on, so
+    /// that the dead letters of their consumers can be shown with the error
+    /// that caused them
+    pub fn with_failure_log(mut self, failure_log: DeadLetterFailureLog) -> Self {
+        self.failure_log = Some(failure_log);
+        self
+    }
+
+    /// Run the project under the durable consumer `feature_name`, its
+    /// failures are recorded under that name when the feature has a failure
+    /// log
     pub fn start_automation<A>(&self, project: A, feature_name: &'static str)
     where
         A: esrc::project::Project + 'static,
     {
         let store = self.store.clone();
+        let monitor = self.monitor.clone();
+        let project = RecordFailures::new(project, feature_name, self.failure_log.clone());
         store.get_task_tracker().spawn(async move {
-            store
-                .start_automation(project, feature_name)
-                .await
-                .expect("automation should be able to start");
+            monitor.started(AutomationKind::Automation, feature_name);
+            let result = store.start_automation(project, feature_name).await;
+            monitor.finished(AutomationKind::Automation, feature_name, &result);
+            result.expect("automation should be able to start");
         });
     }
 
@@ -34,11 +189,13 @@ impl<'a> Feature<'a> {
         A: esrc::project::Project + 'static,
     {
         let store = external_store.clone();
+        let monitor = self.monitor.clone();
+        let project = RecordFailures::new(project, feature_name, self.failure_log.clone());
         store.get_task_tracker().spawn(async move {
-            store
-                .start_automation(project, feature_name)
-                .await
-                .expect("automation should be able to start");
+            monitor.started(AutomationKind::Translation, feature_name);
+            let result = store.start_automation(project, feature_name).await;
+            monitor.finished(AutomationKind::Translation, feature_name, &result);
+            result.expect("automation should be able to start");
         });
     }
 
@@ -47,13 +204,22 @@ impl<'a> Feature<'a> {
         A: esrc::project::Project + 'static,
     {
         let store = self.store.clone();
+        let monitor = self.monitor.clone();
+        let project = RecordFailures::new(project, feature_name, self.failure_log.clone());
         store.get_task_tracker().spawn(async move {
-            store
-                .start_view_automation(project, feature_name)
-                .await
-                .expect("automation should be able to start");
+            monitor.started(AutomationKind::ReadModel, feature_name);
+            let result = store.start_view_automation(project, feature_name).await;
+            monitor.finished(AutomationKind::ReadModel, feature_name, &result);
+            result.expect("automation should be able to start");
         });
     }
+
+    /// Store the messages `durable_name` fails to process in the
+    /// `DeadLetterStore`. The automations of a feature consume under its
+    /// feature name, so `durable_name` is the `feature_name` the project was
+    /// started with. With `with_failure_log`, the error or panic of each
+    /// message it failed is kept and shown with its dead letter by the admin
+    /// API.
     pub fn start_dead_letter_automation<A>(
         &self,
         durable_name: &'static str,
@@ -65,23 +231,87 @@ impl<'a> Feature<'a> {
     {
         let store = self.store.clone();
         let dead_letter_store = dead_letter_store.clone();
+        let monitor = self.monitor.clone();
 
         store.get_task_tracker().spawn(async move {
+            monitor.started(AutomationKind::DeadLetter, feature_name);
             // Start dead letter automation for a specific stream and consumer
-            store
+            let result = store
                 .run_dead_letter_automation(
                     dead_letter_store,
                     durable_name,
                     stream_name,
                     feature_name,
                 )
-                .await
-                .expect("dead letter automation should be able to start");
+                .await;
+            monitor.finished(AutomationKind::DeadLetter, feature_name, &result);
+            result.expect("dead letter automation should be able to start");
         });
     }
 }
 
 impl<'a> Feature<'a> {
+    /// Poll the dead letters in the background and notify when they
+    /// accumulate, the watcher uses the failure log of the feature when it
+    /// has none
+    pub fn start_dead_letter_watcher<DLS>(
+        &self,
+        watcher: DeadLetterWatcher<DLS>,
+        feature_name: &'static str,
+    ) where
+        DLS: nats_dead_letter::DeadLetterStore + Send + Sync + 'static,
+    {
+        let watcher = match &self.failure_log {
+            Some(failure_log) if !watcher.has_failure_log() => {
+                watcher.with_failure_log(failure_log.clone())
+            },
+            _ => watcher,
+        };
+        let monitor = self.monitor.clone();
+        self.store.get_task_tracker().spawn(async move {
+            monitor
+                .supervise(
+                    AutomationKind::DeadLetterWatcher,
+                    feature_name,
+                    watcher.watch(),
+                )
+                .await;
+        });
+    }
+
+    /// Retry dead letters in the background, see `AdminHandler::auto_retry`
+    pub fn start_auto_retry<DLS, P>(
+        &self,
+        scheduler: AutoRetryScheduler<DLS, P>,
+        feature_name: &'static str,
+    ) where
+        DLS: nats_dead_letter::DeadLetterStore + Send + Sync + 'static,
+        P: DeadLetterProjector + Clone + 'static,
+    {
+        let monitor = self.monitor.clone();
+        self.store.get_task_tracker().spawn(async move {
+            monitor
+                .supervise(AutomationKind::AutoRetry, feature_name, scheduler.run())
+                .await;
+        });
+    }
+
+    /// Remove old dead letters in the background
+    pub fn start_dead_letter_retention<DLS>(
+        &self,
+        retention: DeadLetterRetention<DLS>,
+        feature_name: &'static str,
+    ) where
+        DLS: nats_dead_letter::DeadLetterStore + Send + Sync + 'static,
+    {
+        let monitor = self.monitor.clone();
+        self.store.get_task_tracker().spawn(async move {
+            monitor
+                .supervise(AutomationKind::Retention, feature_name, retention.run())
+                .await;
+        });
+    }
+
     pub fn start_legacy_automation<A>(
         &self,
         project: A,
@@ -91,11 +321,79 @@ impl<'a> Feature<'a> {
         A: esrc::nats::legacy::LegacyProject + 'static,
     {
         let store = self.store.clone();
+        let monitor = self.monitor.clone();
         store.get_task_tracker().spawn(async move {
-            store
+            monitor.started(AutomationKind::Legacy, feature_name);
+            let result = store
                 .run_legacy_project(project, feature_name, subjects)
-                .await
-                .expect("automation should be able to start");
+                .await;
+            monitor.finished(AutomationKind::Legacy, feature_name, &result);
+            result.expect("automation should be able to start");
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monitor_survives_a_poisoned_lock() {
+        let monitor = AutomationMonitor::new();
+        monitor.started(AutomationKind::ReadModel, "users");
+
+        let poisoned = monitor.clone();
+        let _ = std::thread::spawn(move || {
+            let _states = poisoned.states.write().unwrap();
+            panic!("automation panicked while holding the lock");
+        })
+        .join();
+        assert!(monitor.states.is_poisoned());
+
+        monitor.finished(
+            AutomationKind::ReadModel,
+            "users",
+            &Err::<(), _>("connection lost"),
+        );
+        let snapshot = monitor.snapshot();
+
+        assert_eq!(snapshot.len(), 1);
+        assert!(matches!(
+            &snapshot[0].status,
+            AutomationStatus::Failed { error } if error == "connection lost"
+        ));
+    }
+
+    #[tokio::test]
+    async fn supervise_reports_a_panicked_task() {
+        let monitor = AutomationMonitor::new();
+
+        let result = AssertUnwindSafe(monitor.supervise(
+            AutomationKind::Retention,
+            "dead-letters",
+            async { panic!("store unavailable") },
+        ))
+        .catch_unwind()
+        .await;
+        let snapshot = monitor.snapshot();
+
+        assert!(result.is_err());
+        assert_eq!(snapshot.len(), 1);
+        assert!(matches!(
+            &snapshot[0].status,
+            AutomationStatus::Failed { error } if error == "panicked: store unavailable"
+        ));
+    }
+
+    #[tokio::test]
+    async fn supervise_reports_a_returned_task() {
+        let monitor = AutomationMonitor::new();
+
+        monitor
+            .supervise(AutomationKind::AutoRetry, "dead-letters", async {})
+            .await;
+        let snapshot = monitor.snapshot();
+
+        assert!(matches!(&snapshot[0].status, AutomationStatus::Stopped));
+    }
+}
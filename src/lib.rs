@@ -1,4 +1,7 @@
 pub mod admin;
+pub mod dead_letter;
 pub mod feature;
 pub mod postgres;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod utils;
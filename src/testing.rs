@@ -0,0 +1,741 @@
+//! Support for testing a `Project` or a `View` without NATS, enabled by the
+//! `testing` feature.
+//!
+//! A `TestEnvelope` holds an event serialized the way esrc publishes it, and
+//! turns into the `Context` a project receives from a live consumer or into
+//! the `DeadLetter` stored when that consumer fails. A `ViewScenario` checks a
+//! view given a list of such events.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::SystemTime;
+
+use esrc::{
+    error,
+    event::{event_model::view::View, Sequence},
+    project::{Context, Project},
+    version::DeserializeVersion,
+    Envelope, Event, EventGroup,
+};
+use nats_dead_letter::DeadLetter;
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::dead_letter::envelope::VERSION_HEADER;
+use crate::postgres::{PgViewProjector, PgViewProjectorError};
+
+/// Prefix of the subjects of the dead letters built by
+/// `TestEnvelope::dead_letter`
+pub const TEST_PREFIX: &str = "test";
+
+/// `Envelope` kept in memory, built from a plain event value
+#[derive(Debug, Clone)]
+pub struct TestEnvelope {
+    id: Uuid,
+    sequence: u64,
+    stream_sequence: Option<u64>,
+    timestamp: SystemTime,
+    name: String,
+    version: usize,
+    metadata: HashMap<String, String>,
+    payload: Vec<u8>,
+}
+
+impl TestEnvelope {
+    /// Envelope of `event` for a new aggregate ID, at sequence 1 and version 1
+    pub fn new<E>(event: &E) -> Self
+    where
+        E: Event + Serialize,
+    {
+        Self::with_name(E::name(), event)
+    }
+
+    /// Envelope of a payload published under `name`, e.g. to test how a
+    /// project handles an event that does not match its event group
+    pub fn with_name(name: &str, payload: &impl Serialize) -> Self {
+        Self {
+            id: Uuid::now_v7(),
+            sequence: 1,
+            stream_sequence: None,
+            timestamp: SystemTime::now(),
+            name: name.to_string(),
+            version: 1,
+            metadata: HashMap::new(),
+            payload: serde_json::to_vec(payload).expect("test event should be serializable"),
+        }
+    }
+
+    pub fn id(mut self, id: Uuid) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Sequence of the event in its aggregate, see `Envelope::sequence`
+    pub fn sequence(mut self, sequence: u64) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Sequence of the event in the NATS stream, kept in the dead letter.
+    /// Defaults to the aggregate sequence, set it when the stream holds
+    /// other aggregates.
+    pub fn stream_sequence(mut self, stream_sequence: u64) -> Self {
+        self.stream_sequence = Some(stream_sequence);
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Version the payload is deserialized with, see `DeserializeVersion`
+    pub fn version(mut self, version: usize) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// The context a project receives for this envelope, panics when the
+    /// event does not belong to `G`
+    pub fn context<G>(&self) -> Context<'_, Self, G>
+    where
+        G: EventGroup,
+    {
+        Context::try_with_envelope(self).unwrap_or_else(|e| {
+            panic!(
+                "event {} should deserialize into the event group: {}",
+                self.name, e
+            )
+        })
+    }
+
+    /// The dead letter kept when `consumer` of `stream` fails this event,
+    /// published under `TEST_PREFIX` with its metadata and version as headers
+    pub fn dead_letter(&self, stream: &str, consumer: &str) -> DeadLetter {
+        let mut headers = self.metadata.clone();
+        headers.insert(VERSION_HEADER.to_string(), self.version.to_string());
+
+        DeadLetter {
+            id: Some(Uuid::now_v7()),
+            aggregate_id: Some(self.id),
+            prefix: Some(TEST_PREFIX.to_string()),
+            stream: stream.to_string(),
+            consumer: consumer.to_string(),
+            subject: format!("{}.{}.{}", TEST_PREFIX, self.name, self.id),
+            stream_sequence: self.stream_sequence.unwrap_or(self.sequence),
+            delivery_count: 1,
+            timestamp: self.timestamp.into(),
+            headers: Some(headers.into_iter().collect()),
+            payload: self.payload.clone(),
+        }
+    }
+}
+
+impl Envelope for TestEnvelope {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn sequence(&self) -> Sequence {
+        self.sequence.into()
+    }
+
+    fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_metadata(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).map(String::as_str)
+    }
+
+    fn deserialize<'de, E>(&'de self) -> error::Result<E>
+    where
+        E: DeserializeVersion<'de> + Event,
+    {
+        let mut deserializer = serde_json::Deserializer::from_slice(&self.payload);
+        E::deserialize_version(&mut deserializer, self.version)
+            .map_err(|e| error::Error::Format(e.into()))
+    }
+}
+
+/// Events of one aggregate, numbered in order from sequence 1
+#[derive(Debug, Clone)]
+pub struct TestStream {
+    id: Uuid,
+    envelopes: Vec<TestEnvelope>,
+}
+
+impl TestStream {
+    pub fn new(id: Uuid) -> Self {
+        Self {
+            id,
+            envelopes: Vec::new(),
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn event<E>(mut self, event: &E) -> Self
+    where
+        E: Event + Serialize,
+    {
+        let envelope = TestEnvelope::new(event)
+            .id(self.id)
+            .sequence(self.envelopes.len() as u64 + 1);
+        self.envelopes.push(envelope);
+        self
+    }
+
+    /// Add an envelope built by hand, its ID and sequence are kept
+    pub fn envelope(mut self, envelope: TestEnvelope) -> Self {
+        self.envelopes.push(envelope);
+        self
+    }
+
+    pub fn envelopes(&self) -> &[TestEnvelope] {
+        &self.envelopes
+    }
+
+    /// Project every event in order, stopping at the first error
+    pub async fn project<P>(&self, project: &mut P) -> Result<(), P::Error>
+    where
+        P: Project,
+    {
+        for envelope in &self.envelopes {
+            project.project(envelope.context()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Given-When-Then test of a `View`: given the events of one or more
+/// aggregates, the view of an aggregate should equal an expected value.
+///
+/// Views are compared through their JSON form, as stored by
+/// `PgViewProjector`, and a mismatch panics with the differing fields.
+pub struct ViewScenario<V> {
+    envelopes: Vec<TestEnvelope>,
+    view: std::marker::PhantomData<V>,
+}
+
+impl<V> Default for ViewScenario<V> {
+    fn default() -> Self {
+        Self {
+            envelopes: Vec::new(),
+            view: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<V> ViewScenario<V>
+where
+    V: View + Serialize + Send + Sync,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the events of a stream, applied after the ones already given
+    pub fn given(mut self, stream: TestStream) -> Self {
+        self.envelopes.extend(stream.envelopes);
+        self
+    }
+
+    pub fn given_envelope(mut self, envelope: TestEnvelope) -> Self {
+        self.envelopes.push(envelope);
+        self
+    }
+
+    /// The view of `id` after applying its events with `View::apply`
+    pub fn view(&self, id: Uuid) -> V {
+        let mut view = V::default();
+        for envelope in self.envelopes.iter().filter(|e| e.id == id) {
+            view.apply(envelope.context());
+        }
+        view
+    }
+
+    /// Compare the applied view of `id` with `expected`
+    pub fn check(&self, id: Uuid, expected: &V) -> Result<(), ViewMismatch> {
+        ViewMismatch::compare(id, expected, &self.view(id))
+    }
+
+    /// Panic with the differences when the applied view of `id` is not
+    /// `expected`
+    #[track_caller]
+    pub fn then(&self, id: Uuid, expected: &V) {
+        if let Err(mismatch) = self.check(id, expected) {
+            panic!("{}", mismatch);
+        }
+    }
+
+    /// Project every event through `projector` and compare the view it
+    /// stored for `id` with `expected`.
+    ///
+    /// The stored views of every given aggregate are deleted first, from
+    /// whatever database and table `projector` points at: only pass a
+    /// projector connected to a test database.
+    pub async fn check_projected(
+        &self,
+        projector: &PgViewProjector<V>,
+        id: Uuid,
+        expected: &V,
+    ) -> Result<Result<(), ViewMismatch>, PgViewProjectorError> {
+        let mut projector = projector.clone();
+        let mut ids = self.envelopes.iter().map(|e| e.id).collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        for id in ids {
+            projector.delete_one(id).await?;
+        }
+        for envelope in &self.envelopes {
+            projector.project(envelope.context()).await?;
+        }
+
+        let actual = projector.load(id).await?;
+        Ok(ViewMismatch::compare(id, expected, &actual))
+    }
+
+    /// Panic with the differences when the view stored by `projector` for
+    /// `id` is not `expected`. Deletes the stored views of the given
+    /// aggregates first, see `check_projected`.
+    pub async fn then_projected(&self, projector: &PgViewProjector<V>, id: Uuid, expected: &V) {
+        match self.check_projected(projector, id, expected).await {
+            Ok(Ok(())) => {},
+            Ok(Err(mismatch)) => panic!("{}", mismatch),
+            Err(e) => panic!("failed to project view {}: {}", id, e),
+        }
+    }
+}
+
+/// One field of a view that differs from the expected value
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewDifference {
+    /// JSON path of the field, e.g. `$.emails[1]`
+    pub path: String,
+    /// `None` when the field is missing from the expected view
+    pub expected: Option<Value>,
+    /// `None` when the field is missing from the actual view
+    pub actual: Option<Value>,
+}
+
+impl fmt::Display for ViewDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.expected, &self.actual) {
+            (Some(expected), Some(actual)) => {
+                write!(f, "{}: expected {}, got {}", self.path, expected, actual)
+            },
+            (Some(expected), None) => write!(f, "{}: missing, expected {}", self.path, expected),
+            (None, Some(actual)) => write!(f, "{}: unexpected {}", self.path, actual),
+            (None, None) => write!(f, "{}", self.path),
+        }
+    }
+}
+
+/// A view that does not equal the expected one, displayed as one line per
+/// differing field
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewMismatch {
+    pub id: Uuid,
+    pub differences: Vec<ViewDifference>,
+}
+
+impl ViewMismatch {
+    fn compare<V: Serialize>(id: Uuid, expected: &V, actual: &V) -> Result<(), Self> {
+        let expected = serde_json::to_value(expected).expect("view should be serializable");
+        let actual = serde_json::to_value(actual).expect("view should be serializable");
+
+        let mut differences = Vec::new();
+        diff(
+            "$".to_string(),
+            Some(&expected),
+            Some(&actual),
+            &mut differences,
+        );
+        if differences.is_empty() {
+            Ok(())
+        } else {
+            Err(Self { id, differences })
+        }
+    }
+}
+
+impl fmt::Display for ViewMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "view {} does not match the expected view:", self.id)?;
+        for difference in &self.differences {
+            write!(f, "\n  {}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ViewMismatch {}
+
+/// Collect the leaves that differ between two JSON values, objects and
+/// arrays are compared field by field
+fn diff(
+    path: String,
+    expected: Option<&Value>,
+    actual: Option<&Value>,
+    differences: &mut Vec<ViewDifference>,
+) {
+    match (expected, actual) {
+        (Some(Value::Object(expected)), Some(Value::Object(actual))) => {
+            let mut keys = expected.keys().chain(actual.keys()).collect::<Vec<_>>();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                diff(
+                    format!("{}.{}", path, key),
+                    expected.get(key),
+                    actual.get(key),
+                    differences,
+                );
+            }
+        },
+        (Some(Value::Array(expected)), Some(Value::Array(actual))) => {
+            for index in 0..expected.len().max(actual.len()) {
+                diff(
+                    format!("{}[{}]", path, index),
+                    expected.get(index),
+                    actual.get(index),
+                    differences,
+                );
+            }
+        },
+        (expected, actual) if expected != actual => differences.push(ViewDifference {
+            path,
+            expected: expected.cloned(),
+            actual: actual.cloned(),
+        }),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use esrc::version::SerializeVersion;
+    use serde::{Deserialize, Deserializer};
+    use serde_json::json;
+
+    use super::*;
+    use crate::dead_letter::decoder::DeadLetterDecoder;
+
+    #[derive(Event, Serialize, Deserialize, Debug, Clone, SerializeVersion, DeserializeVersion)]
+    #[esrc(event(name = "User"))]
+    enum UserEvent {
+        Created { name: String },
+        Renamed { name: String },
+    }
+
+    /// Names an aggregate had, in order
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct UserView {
+        name: String,
+        previous_names: Vec<String>,
+    }
+
+    impl View for UserView {
+        type EventGroup = UserEvent;
+
+        fn apply<'de, E: Envelope>(&mut self, context: Context<'de, E, Self::EventGroup>) -> bool {
+            match &*context {
+                UserEvent::Created { name } => self.name = name.clone(),
+                UserEvent::Renamed { name } => {
+                    let previous = std::mem::replace(&mut self.name, name.clone());
+                    self.previous_names.push(previous);
+                },
+            }
+            true
+        }
+    }
+
+    /// Event keeping the version its payload was deserialized with
+    #[derive(Event, Debug, PartialEq)]
+    #[esrc(event(name = "Versioned"))]
+    struct Versioned {
+        name: String,
+        version: usize,
+    }
+
+    impl<'de> DeserializeVersion<'de> for Versioned {
+        fn deserialize_version<D>(deserializer: D, version: usize) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let name = String::deserialize(deserializer)?;
+            Ok(Self { name, version })
+        }
+    }
+
+    fn created(name: &str) -> UserEvent {
+        UserEvent::Created {
+            name: name.to_string(),
+        }
+    }
+
+    fn mismatch(expected: Value, actual: Value) -> Vec<ViewDifference> {
+        ViewMismatch::compare(Uuid::nil(), &expected, &actual)
+            .err()
+            .map(|mismatch| mismatch.differences)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn context_deserializes_with_the_envelope_version() {
+        let id = Uuid::now_v7();
+        let envelope = TestEnvelope::with_name("Versioned", &"Ada")
+            .id(id)
+            .sequence(4)
+            .version(2);
+
+        let context = envelope.context::<Versioned>();
+
+        assert_eq!(
+            *context,
+            Versioned {
+                name: "Ada".to_string(),
+                version: 2,
+            }
+        );
+        assert_eq!(Context::id(&context), id);
+        assert_eq!(u64::from(Context::sequence(&context)), 4);
+    }
+
+    #[test]
+    fn metadata_is_kept_in_the_envelope_and_the_dead_letter() {
+        let envelope = TestEnvelope::new(&created("Ada"))
+            .metadata("Trace-Id", "abc")
+            .version(3);
+
+        let dead_letter = envelope.dead_letter("users", "user-projector");
+        let headers = DeadLetterDecoder::headers(&dead_letter).unwrap();
+
+        assert_eq!(envelope.get_metadata("Trace-Id"), Some("abc"));
+        assert_eq!(envelope.get_metadata("Other"), None);
+        assert_eq!(
+            headers.get("Trace-Id").map(|value| value.as_str()),
+            Some("abc")
+        );
+        assert_eq!(
+            headers.get(VERSION_HEADER).map(|value| value.as_str()),
+            Some("3")
+        );
+    }
+
+    #[test]
+    fn stream_numbers_its_events_from_one() {
+        let id = Uuid::now_v7();
+        let stream = TestStream::new(id)
+            .event(&created("Ada"))
+            .event(&created("Grace"))
+            .envelope(TestEnvelope::new(&created("Alan")).sequence(10));
+
+        let sequences = stream
+            .envelopes()
+            .iter()
+            .map(|envelope| {
+                (
+                    Envelope::id(envelope) == id,
+                    u64::from(Envelope::sequence(envelope)),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(sequences, vec![(true, 1), (true, 2), (false, 10)]);
+    }
+
+    #[test]
+    fn dead_letter_keeps_the_stream_sequence() {
+        let envelope = TestEnvelope::new(&created("Ada")).sequence(2);
+
+        assert_eq!(
+            envelope
+                .dead_letter("users", "user-projector")
+                .stream_sequence,
+            2
+        );
+
+        let envelope = envelope.stream_sequence(40);
+
+        assert_eq!(
+            envelope
+                .dead_letter("users", "user-projector")
+                .stream_sequence,
+            40
+        );
+        assert_eq!(u64::from(Envelope::sequence(&envelope)), 2);
+    }
+
+    fn scenario(ada: Uuid, grace: Uuid) -> ViewScenario<UserView> {
+        ViewScenario::new()
+            .given(
+                TestStream::new(ada)
+                    .event(&created("Ada"))
+                    .event(&UserEvent::Renamed {
+                        name: "Ada Lovelace".to_string(),
+                    }),
+            )
+            .given(TestStream::new(grace).event(&created("Grace")))
+    }
+
+    #[test]
+    fn scenario_applies_the_events_of_the_aggregate() {
+        let ada = Uuid::now_v7();
+        let grace = Uuid::now_v7();
+        let scenario = scenario(ada, grace);
+
+        assert_eq!(
+            scenario.view(ada),
+            UserView {
+                name: "Ada Lovelace".to_string(),
+                previous_names: vec!["Ada".to_string()],
+            }
+        );
+        scenario.then(
+            grace,
+            &UserView {
+                name: "Grace".to_string(),
+                previous_names: Vec::new(),
+            },
+        );
+        scenario.then(Uuid::now_v7(), &UserView::default());
+    }
+
+    #[test]
+    fn scenario_reports_the_fields_of_a_wrong_view() {
+        let ada = Uuid::now_v7();
+        let scenario = scenario(ada, Uuid::now_v7());
+
+        let mismatch = scenario
+            .check(
+                ada,
+                &UserView {
+                    name: "Ada".to_string(),
+                    previous_names: Vec::new(),
+                },
+            )
+            .unwrap_err();
+
+        assert_eq!(
+            mismatch
+                .differences
+                .iter()
+                .map(|difference| difference.path.as_str())
+                .collect::<Vec<_>>(),
+            vec!["$.name", "$.previous_names[0]"]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "$.name: expected \"Grace\", got \"Ada Lovelace\"")]
+    fn scenario_then_panics_on_a_wrong_view() {
+        let ada = Uuid::now_v7();
+
+        scenario(ada, Uuid::now_v7()).then(
+            ada,
+            &UserView {
+                name: "Grace".to_string(),
+                previous_names: vec!["Ada".to_string()],
+            },
+        );
+    }
+
+    #[test]
+    fn equal_views_have_no_differences() {
+        let view = json!({ "name": "Ada", "emails": ["ada@example.com"] });
+
+        assert!(ViewMismatch::compare(Uuid::nil(), &view, &view.clone()).is_ok());
+    }
+
+    #[test]
+    fn diff_reports_the_path_of_nested_fields() {
+        let differences = mismatch(
+            json!({ "address": { "city": "London", "zip": "N1" } }),
+            json!({ "address": { "city": "Paris", "zip": "N1" } }),
+        );
+
+        assert_eq!(
+            differences,
+            vec![ViewDifference {
+                path: "$.address.city".to_string(),
+                expected: Some(json!("London")),
+                actual: Some(json!("Paris")),
+            }]
+        );
+    }
+
+    #[test]
+    fn diff_reports_the_extra_and_missing_items_of_arrays() {
+        let differences = mismatch(json!({ "emails": ["a", "b"] }), json!({ "emails": ["a"] }));
+        assert_eq!(
+            differences,
+            vec![ViewDifference {
+                path: "$.emails[1]".to_string(),
+                expected: Some(json!("b")),
+                actual: None,
+            }]
+        );
+
+        let differences = mismatch(json!([1]), json!([1, 2, 3]));
+        let paths = differences
+            .iter()
+            .map(|difference| difference.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["$[1]", "$[2]"]);
+        assert!(differences
+            .iter()
+            .all(|difference| difference.expected.is_none()));
+    }
+
+    #[test]
+    fn diff_reports_missing_and_unexpected_keys() {
+        let differences = mismatch(
+            json!({ "name": "Ada", "email": "ada@example.com" }),
+            json!({ "name": "Ada", "phone": "555" }),
+        );
+
+        assert_eq!(
+            differences
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                r#"$.email: missing, expected "ada@example.com""#,
+                r#"$.phone: unexpected "555""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn mismatch_displays_one_line_per_difference() {
+        let id = Uuid::nil();
+        let mismatch = ViewMismatch::compare(
+            id,
+            &json!({ "name": "Ada", "age": 36 }),
+            &json!({ "name": "Grace", "age": 36 }),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            mismatch.to_string(),
+            format!(
+                "view {} does not match the expected view:\n  $.name: expected \"Ada\", got \"Grace\"",
+                id
+            )
+        );
+    }
+}